@@ -1,5 +1,13 @@
 // src/args.rs
-use clap::Parser;
+use crate::control::ControlRequest;
+use crate::keygen::KdfParams;
+use crate::proxy::StreamTimeouts;
+use crate::ratelimit::BandwidthLimits;
+use clap::{Parser, Subcommand};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
 /// Command-line arguments for the application.
 #[derive(Parser, Debug)]
 #[command(name = "onionize")]
@@ -15,30 +23,322 @@ pub struct Args {
     pub host: String,
 
     /// Nickname for the Onion Service
-    #[arg(short, long, default_value = "my-ephemeral-service")]
+    #[arg(short, long, default_value = "my-ephemeral-service", global = true)]
     pub nickname: String,
 
     /// Enable verbose logging
-    #[arg(short, long, default_value_t = false)]
+    #[arg(short, long, default_value_t = false, global = true)]
     pub verbose: bool,
 
     /// Display QR code for the Onion address
-    #[arg(long, default_value_t = false)]
+    #[arg(long, default_value_t = false, global = true)]
     pub qr: bool,
 
+    /// Write the QR code to a PNG or SVG image, with the client credential in restricted mode
+    #[arg(long, global = true, value_name = "PATH")]
+    pub qr_file: Option<PathBuf>,
+
     /// Generate x25519 keypair for Client Authorization
     #[arg(long, default_value_t = false)]
     pub keygen: bool,
 
+    /// Derive the --keygen keys from this hexadecimal seed (at least 16 bytes) instead of random ones
+    #[arg(
+        long,
+        value_name = "HEX",
+        requires = "keygen",
+        conflicts_with = "from_passphrase"
+    )]
+    pub from_seed: Option<String>,
+
+    /// Derive the --keygen keys from a passphrase, asked without echo on a terminal
+    /// or read from standard input
+    #[arg(long, default_value_t = false, requires = "keygen")]
+    pub from_passphrase: bool,
+
+    /// Memory cost of the key derivation, in KiB
+    #[arg(long, value_name = "KIB", default_value_t = KdfParams::default().memory_kib)]
+    pub kdf_memory: u32,
+
+    /// Number of iterations of the key derivation
+    #[arg(long, default_value_t = KdfParams::default().iterations)]
+    pub kdf_iterations: u32,
+
+    /// Number of lanes of the key derivation
+    #[arg(long, default_value_t = KdfParams::default().parallelism)]
+    pub kdf_parallelism: u32,
+
+    /// With --keygen, also create a service identity and write its secret key to this file
+    #[arg(long, value_name = "PATH", requires = "keygen")]
+    pub identity_file: Option<PathBuf>,
+
     /// Add authorized client (format: `descriptor:x25519:<pubkey>`)
     /// Enables restricted access (Client Auth).
-    #[arg(long)]
+    #[arg(long, global = true)]
     pub auth: Option<String>,
 
     /// Auto-generate keys and enable restricted access
     /// (Generates ephemeral keys for this session)
-    #[arg(long, default_value_t = false)]
+    #[arg(long, default_value_t = false, global = true)]
     pub restricted: bool,
+
+    /// Check that the service is reachable through Tor after launch
+    #[arg(long, default_value_t = false, global = true)]
+    pub self_test: bool,
+
+    /// Print machine-readable events to stdout as JSON lines
+    #[arg(long, default_value_t = false, global = true)]
+    pub json: bool,
+
+    /// Run a local SOCKS5 proxy through the embedded Tor client
+    #[arg(long, global = true, value_name = "ADDR")]
+    pub socks: Option<SocketAddr>,
+
+    /// Timeout for connecting to the local service, in seconds (0 disables)
+    #[arg(long, default_value_t = 10, value_name = "SECS")]
+    pub connect_timeout: u64,
+
+    /// Close streams with no traffic for this many seconds (0 disables)
+    #[arg(long, default_value_t = 0, value_name = "SECS")]
+    pub idle_timeout: u64,
+
+    /// Maximum lifetime of a stream in seconds (0 disables)
+    #[arg(long, default_value_t = 0, value_name = "SECS")]
+    pub max_lifetime: u64,
+
+    /// Limit the total upload rate (Tor client to local service), in KiB/s (0 disables)
+    #[arg(long, value_name = "KIB/S")]
+    pub upload_limit: Option<u64>,
+
+    /// Limit the total download rate (local service to Tor client), in KiB/s (0 disables)
+    #[arg(long, value_name = "KIB/S")]
+    pub download_limit: Option<u64>,
+
+    /// Limit the upload rate of each rendezvous circuit, in KiB/s (0 disables)
+    #[arg(long, value_name = "KIB/S")]
+    pub circuit_upload_limit: Option<u64>,
+
+    /// Limit the download rate of each rendezvous circuit, in KiB/s (0 disables)
+    #[arg(long, value_name = "KIB/S")]
+    pub circuit_download_limit: Option<u64>,
+
+    /// Answer with a 502 page when the local service is down (`html`, `json` or a file)
+    #[arg(long, value_name = "PAGE")]
+    pub maintenance: Option<String>,
+
+    /// Show a live traffic dashboard instead of the log output
+    #[arg(long, default_value_t = false, conflicts_with_all = ["json", "daemon"])]
+    pub dashboard: bool,
+
+    /// Serve Prometheus metrics at `/metrics` on this address (e.g. 127.0.0.1:9464)
+    #[arg(long, global = true, value_name = "ADDR")]
+    pub metrics_listen: Option<SocketAddr>,
+
+    /// Unix socket of the control API (served by a running service, used by `ctl`)
+    #[arg(long, global = true, value_name = "PATH")]
+    pub control_socket: Option<PathBuf>,
+
+    /// Detach from the terminal and run in the background (Unix)
+    #[arg(long, default_value_t = false, global = true)]
+    pub daemon: bool,
+
+    /// Write the process ID to this file, removed on exit
+    #[arg(long, global = true, value_name = "PATH")]
+    pub pid_file: Option<PathBuf>,
+
+    /// Append logs to this file instead of stderr
+    #[arg(long, global = true, value_name = "PATH", conflicts_with = "journald")]
+    pub log_file: Option<PathBuf>,
+
+    /// Send logs to the systemd journal (Unix)
+    #[arg(long, default_value_t = false, global = true)]
+    pub journald: bool,
+
+    /// Show secrets such as generated client keys in the logs (hidden by default)
+    #[arg(long, default_value_t = false, global = true)]
+    pub log_secrets: bool,
+
+    /// Lock secret keys in memory so they are never written to swap (Unix)
+    #[arg(long, default_value_t = false, global = true)]
+    pub mlock: bool,
+
+    /// Keep the Tor state, including the keys of services, in a passphrase-encrypted file
+    #[arg(long, default_value_t = false, global = true)]
+    pub encrypt_state: bool,
+
+    /// Seconds to let active streams finish after Ctrl+C or SIGTERM
+    #[arg(long, default_value_t = 30, global = true, value_name = "SECS")]
+    pub grace_period: u64,
+
+    /// Serving mode. Without a subcommand, the local port is proxied.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Alternative serving modes.
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Receive files from visitors through an upload form
+    Receive(ReceiveArgs),
+    /// Expose a remote onion service as a local port
+    Connect(ConnectArgs),
+    /// Manage a running service through its control socket
+    Ctl(CtlArgs),
+    /// Serve a subset of the Tor control protocol (ADD_ONION / DEL_ONION)
+    ControlPort(ControlPortArgs),
+    /// Validate client authorization keys
+    Keys(KeysArgs),
+}
+
+/// Arguments of the `receive` subcommand.
+#[derive(clap::Args, Debug)]
+pub struct ReceiveArgs {
+    /// Directory where uploaded files are stored
+    #[arg(short, long, default_value = ".")]
+    pub dir: PathBuf,
+
+    /// Maximum size of a single upload in megabytes
+    #[arg(long, default_value_t = 100)]
+    pub max_size: u64,
+}
+
+/// Arguments of the `connect` subcommand.
+#[derive(clap::Args, Debug)]
+pub struct ConnectArgs {
+    /// Onion service to connect to (`<address>.onion:<port>`)
+    pub target: String,
+
+    /// Local address to listen on
+    #[arg(short, long, default_value = "127.0.0.1:8080")]
+    pub listen: SocketAddr,
+
+    /// Client authorization secret key for a restricted service
+    #[arg(long)]
+    pub client_key: Option<String>,
+
+    /// Directory with client authorization (`.auth_private`) files
+    #[arg(long)]
+    pub client_auth_dir: Option<PathBuf>,
+}
+
+/// Arguments of the `control-port` subcommand.
+#[derive(clap::Args, Debug)]
+pub struct ControlPortArgs {
+    /// Local address of the control port
+    #[arg(short, long, default_value = "127.0.0.1:9051")]
+    pub listen: SocketAddr,
+
+    /// File where the authentication cookie is written
+    #[arg(long, value_name = "PATH")]
+    pub cookie_file: Option<PathBuf>,
+
+    /// Hashed password accepted by AUTHENTICATE, in addition to the cookie
+    /// (`tor --hash-password` format). A plain password is read from
+    /// `ONIONIZE_CONTROL_PASSWORD` instead, so it does not show in the process list.
+    #[arg(long, value_name = "HASH")]
+    pub hashed_password: Option<String>,
+}
+
+/// Arguments of the `keys` subcommand.
+#[derive(clap::Args, Debug)]
+pub struct KeysArgs {
+    /// What to do with the keys
+    #[command(subcommand)]
+    pub action: KeysAction,
+}
+
+/// Actions of the `keys` subcommand.
+#[derive(Subcommand, Debug)]
+pub enum KeysAction {
+    /// Check keys and show the public key of secrets
+    Inspect {
+        /// Key strings, or files with one key per line (e.g. `.auth_private` files)
+        #[arg(required = true)]
+        inputs: Vec<String>,
+        /// Public key of the server the secrets must correspond to (format: `descriptor:x25519:<key>`)
+        #[arg(long)]
+        public: Option<String>,
+    },
+    /// Change the passphrase of the encrypted Tor state (--encrypt-state)
+    Rekey {
+        /// The encrypted file, by default the one of --encrypt-state
+        #[arg(long, value_name = "PATH")]
+        file: Option<PathBuf>,
+    },
+}
+
+/// Arguments of the `ctl` subcommand.
+#[derive(clap::Args, Debug)]
+pub struct CtlArgs {
+    /// The request to send
+    #[command(subcommand)]
+    pub action: CtlAction,
+}
+
+/// Requests of the `ctl` subcommand, see [`ControlRequest`].
+#[derive(Subcommand, Debug)]
+pub enum CtlAction {
+    /// List services with their onion addresses, port mappings and clients
+    List,
+    /// Show traffic statistics and the Tor status
+    Stats,
+    /// Forward a virtual port to another local target
+    MapPort {
+        /// Virtual port of the onion service
+        port: u16,
+        /// Local address to forward to (e.g. 127.0.0.1:8080)
+        target: String,
+    },
+    /// Forward a virtual port to the default target again
+    UnmapPort {
+        /// Virtual port of the onion service
+        port: u16,
+    },
+    /// Authorize a client (enables restricted discovery)
+    AddClient {
+        /// Public key of the client (format: `descriptor:x25519:<key>`)
+        key: String,
+        /// Name of the client
+        #[arg(long)]
+        nickname: Option<String>,
+    },
+    /// Revoke the authorization of a client
+    RevokeClient {
+        /// Name of the client
+        nickname: String,
+    },
+    /// Stop the service gracefully
+    Shutdown,
+}
+
+impl CtlAction {
+    /// Returns the control request for this action.
+    pub fn request(&self) -> ControlRequest {
+        match self {
+            CtlAction::List => ControlRequest::List,
+            CtlAction::Stats => ControlRequest::Stats,
+            CtlAction::MapPort { port, target } => ControlRequest::MapPort {
+                port: *port,
+                target: target.clone(),
+            },
+            CtlAction::UnmapPort { port } => ControlRequest::UnmapPort { port: *port },
+            CtlAction::AddClient { key, nickname } => ControlRequest::AddClient {
+                key: key.clone(),
+                nickname: nickname.clone(),
+            },
+            CtlAction::RevokeClient { nickname } => ControlRequest::RevokeClient {
+                nickname: nickname.clone(),
+            },
+            CtlAction::Shutdown => ControlRequest::Shutdown,
+        }
+    }
+}
+
+impl ReceiveArgs {
+    /// Returns the upload size limit in bytes.
+    pub fn max_size_bytes(&self) -> u64 {
+        self.max_size.saturating_mul(1024 * 1024)
+    }
 }
 
 impl Args {
@@ -54,6 +354,49 @@ impl Args {
         }
     }
 
+    /// Returns the cost of the key derivation of `--from-seed` / `--from-passphrase`.
+    pub fn kdf_params(&self) -> KdfParams {
+        KdfParams {
+            memory_kib: self.kdf_memory,
+            iterations: self.kdf_iterations,
+            parallelism: self.kdf_parallelism,
+        }
+    }
+
+    /// Returns the time limits for proxied streams. A value of `0` disables a limit.
+    pub fn stream_timeouts(&self) -> StreamTimeouts {
+        let secs = |value: u64| (value > 0).then(|| Duration::from_secs(value));
+        StreamTimeouts {
+            connect: secs(self.connect_timeout),
+            idle: secs(self.idle_timeout),
+            lifetime: secs(self.max_lifetime),
+        }
+    }
+
+    /// Returns the bandwidth limits shared by all streams, in bytes per second.
+    /// A value of `0` disables a limit.
+    pub fn global_limits(&self) -> BandwidthLimits {
+        BandwidthLimits {
+            upload: self.upload_limit.and_then(kib_to_bytes),
+            download: self.download_limit.and_then(kib_to_bytes),
+        }
+    }
+
+    /// Returns the bandwidth limits of each rendezvous circuit, in bytes per second.
+    /// A value of `0` disables a limit.
+    pub fn circuit_limits(&self) -> BandwidthLimits {
+        BandwidthLimits {
+            upload: self.circuit_upload_limit.and_then(kib_to_bytes),
+            download: self.circuit_download_limit.and_then(kib_to_bytes),
+        }
+    }
+
+    /// Returns `true` if `--dashboard` is set and the mode shows it: only the proxy
+    /// mode does, not the subcommands or `--keygen`.
+    pub fn shows_dashboard(&self) -> bool {
+        self.dashboard && self.command.is_none() && !self.keygen
+    }
+
     /// Returns the effective nickname for the service.
     ///
     /// If the user did not provide a custom nickname, a random one is generated
@@ -69,6 +412,10 @@ impl Args {
     }
 }
 
+fn kib_to_bytes(kib: u64) -> Option<u64> {
+    (kib > 0).then(|| kib.saturating_mul(1024))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -115,4 +462,55 @@ mod tests {
         // They should not be the same
         assert_ne!(nick1, nick2, "Generated nicknames should be different");
     }
+
+    #[test]
+    fn test_zero_disables_bandwidth_limits() {
+        let args = Args::parse_from([
+            "bin",
+            "--upload-limit",
+            "0",
+            "--download-limit",
+            "64",
+            "--circuit-upload-limit",
+            "0",
+        ]);
+        assert_eq!(args.global_limits().upload, None);
+        assert_eq!(args.global_limits().download, Some(64 * 1024));
+        assert_eq!(args.circuit_limits().upload, None);
+        assert_eq!(args.circuit_limits().download, None);
+    }
+
+    #[test]
+    fn test_dashboard_only_in_proxy_mode() {
+        assert!(Args::parse_from(["bin", "--dashboard"]).shows_dashboard());
+        assert!(!Args::parse_from(["bin", "--dashboard", "receive"]).shows_dashboard());
+        assert!(!Args::parse_from(["bin"]).shows_dashboard());
+    }
+
+    #[test]
+    fn test_ctl_request() {
+        let args = Args::parse_from([
+            "bin",
+            "--control-socket",
+            "/tmp/onionize.sock",
+            "ctl",
+            "map-port",
+            "22",
+            "127.0.0.1:2222",
+        ]);
+        let Some(Command::Ctl(ctl)) = args.command else {
+            panic!("expected the ctl subcommand");
+        };
+        assert_eq!(
+            ctl.action.request(),
+            ControlRequest::MapPort {
+                port: 22,
+                target: "127.0.0.1:2222".to_string(),
+            }
+        );
+        assert_eq!(
+            args.control_socket,
+            Some(PathBuf::from("/tmp/onionize.sock"))
+        );
+    }
 }
@@ -0,0 +1,191 @@
+// src/backend.rs
+use crate::error::{Error, Result};
+use futures::future::BoxFuture;
+use futures::task::{Spawn, SpawnExt};
+use futures::{AsyncRead, AsyncWrite};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use tokio_util::compat::{Compat, TokioAsyncReadCompatExt};
+use tor_rtcompat::NetStreamProvider;
+
+/// Size of the in-memory pipe between a Tor stream and an in-process handler.
+const PIPE_SIZE: usize = 16 * 1024;
+
+/// A byte stream to a backend, as returned by [`Backend::connect`].
+pub trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
+/// Where the streams of an onion service are sent.
+///
+/// Every accepted Tor stream gets its own connection, and data is copied between
+/// the two with the stream timeouts, bandwidth limits and statistics of the service.
+/// Built-in backends forward to a TCP address ([`TcpBackend`]), a Unix socket
+/// ([`UnixBackend`]) or an async function in the same process ([`HandlerBackend`]).
+pub trait Backend: Send + Sync {
+    /// Opens a connection for a new Tor stream.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the backend cannot be reached, usually
+    /// [`Error::BackendUnreachable`].
+    fn connect(&self) -> BoxFuture<'_, Result<Box<dyn Connection>>>;
+
+    /// Describes the backend in logs and errors (e.g., "127.0.0.1:8080").
+    fn describe(&self) -> String {
+        std::any::type_name::<Self>().to_string()
+    }
+}
+
+/// Forwards streams to a TCP address.
+#[derive(Debug, Clone)]
+pub struct TcpBackend<R> {
+    runtime: R,
+    target: String,
+}
+
+impl<R> TcpBackend<R> {
+    /// Creates a backend connecting to `target` (e.g., "127.0.0.1:8080") with `runtime`.
+    ///
+    /// The address is checked on every connection, and an invalid one is reported
+    /// as [`Error::InvalidTarget`].
+    pub fn new(runtime: R, target: impl Into<String>) -> Self {
+        Self {
+            runtime,
+            target: target.into(),
+        }
+    }
+}
+
+impl<R: NetStreamProvider> Backend for TcpBackend<R> {
+    fn connect(&self) -> BoxFuture<'_, Result<Box<dyn Connection>>> {
+        Box::pin(async move {
+            let addr =
+                SocketAddr::from_str(&self.target).map_err(|source| Error::InvalidTarget {
+                    target: self.target.clone(),
+                    source,
+                })?;
+            let stream =
+                self.runtime
+                    .connect(&addr)
+                    .await
+                    .map_err(|source| Error::BackendUnreachable {
+                        target: self.target.clone(),
+                        source,
+                    })?;
+            Ok(Box::new(stream) as Box<dyn Connection>)
+        })
+    }
+
+    fn describe(&self) -> String {
+        self.target.clone()
+    }
+}
+
+/// Forwards streams to a Unix socket.
+#[cfg(unix)]
+#[derive(Debug, Clone)]
+pub struct UnixBackend<R> {
+    runtime: R,
+    path: std::path::PathBuf,
+}
+
+#[cfg(unix)]
+impl<R> UnixBackend<R> {
+    /// Creates a backend connecting to the socket at `path` with `runtime`.
+    pub fn new(runtime: R, path: impl Into<std::path::PathBuf>) -> Self {
+        Self {
+            runtime,
+            path: path.into(),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl<R> Backend for UnixBackend<R>
+where
+    R: NetStreamProvider<std::os::unix::net::SocketAddr>,
+{
+    fn connect(&self) -> BoxFuture<'_, Result<Box<dyn Connection>>> {
+        Box::pin(async move {
+            let unreachable = |source| Error::BackendUnreachable {
+                target: self.describe(),
+                source,
+            };
+            let addr =
+                std::os::unix::net::SocketAddr::from_pathname(&self.path).map_err(unreachable)?;
+            let stream = self.runtime.connect(&addr).await.map_err(unreachable)?;
+            Ok(Box::new(stream) as Box<dyn Connection>)
+        })
+    }
+
+    fn describe(&self) -> String {
+        format!("unix:{}", self.path.display())
+    }
+}
+
+/// The stream an in-process handler talks to, see [`HandlerBackend`].
+pub type InProcessStream = Compat<tokio::io::DuplexStream>;
+
+/// Serves streams with an async function in the same process, without a listener.
+///
+/// For every Tor stream, `handler` is spawned with one end of an in-memory pipe;
+/// the other end is proxied like a connection to a local service.
+///
+/// ```no_run
+/// # async fn example() -> anyhow::Result<()> {
+/// use futures::AsyncReadExt;
+/// use onionize::backend::HandlerBackend;
+/// use tor_rtcompat::PreferredRuntime;
+///
+/// // An echo service
+/// let backend = HandlerBackend::new(PreferredRuntime::current()?, |stream| async move {
+///     let (reader, mut writer) = stream.split();
+///     let _ = futures::io::copy(reader, &mut writer).await;
+/// });
+///
+/// let service = onionize::Onionizer::builder()
+///     .backend(backend)
+///     .build()?
+///     .launch()
+///     .await?;
+/// println!("Serving at http://{}", service.onion_address());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct HandlerBackend<R, F> {
+    runtime: R,
+    handler: F,
+}
+
+impl<R, F, Fut> HandlerBackend<R, F>
+where
+    R: Spawn + Send + Sync,
+    F: Fn(InProcessStream) -> Fut + Send + Sync,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    /// Creates a backend spawning `handler` on `runtime` for every stream.
+    pub fn new(runtime: R, handler: F) -> Self {
+        Self { runtime, handler }
+    }
+}
+
+impl<R, F, Fut> Backend for HandlerBackend<R, F>
+where
+    R: Spawn + Send + Sync,
+    F: Fn(InProcessStream) -> Fut + Send + Sync,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    fn connect(&self) -> BoxFuture<'_, Result<Box<dyn Connection>>> {
+        Box::pin(async move {
+            let (local, remote) = tokio::io::duplex(PIPE_SIZE);
+            self.runtime.spawn((self.handler)(remote.compat()))?;
+            Ok(Box::new(local.compat()) as Box<dyn Connection>)
+        })
+    }
+
+    fn describe(&self) -> String {
+        "in-process handler".to_string()
+    }
+}
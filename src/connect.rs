@@ -0,0 +1,106 @@
+// src/connect.rs
+use crate::error::{Error, Result};
+use futures::task::SpawnExt;
+use futures::{AsyncRead, AsyncWrite, StreamExt};
+use rust_i18n::t;
+use std::net::SocketAddr;
+use tor_rtcompat::{NetStreamListener, Runtime};
+use tracing::{debug, info, warn};
+
+/// Parses a client-mode target of the form `<address>.onion:<port>`.
+///
+/// The port defaults to `80` when omitted.
+///
+/// # Errors
+///
+/// Returns [`Error::InvalidOnionTarget`] if the host is not an `.onion` address or the
+/// port is invalid.
+pub fn parse_onion_target(target: &str) -> Result<(String, u16)> {
+    let invalid = || Error::InvalidOnionTarget(target.to_string());
+
+    let (host, port) = match target.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse::<u16>().map_err(|_| invalid())?),
+        None => (target, 80),
+    };
+
+    let host = host.to_ascii_lowercase();
+    if port == 0 || host.len() <= ".onion".len() || !host.ends_with(".onion") {
+        return Err(invalid());
+    }
+
+    Ok((host, port))
+}
+
+/// Runs the client-mode loop, exposing a remote service on a local port.
+///
+/// Every TCP connection accepted on `local_addr` gets its own stream opened via
+/// `dial`, and data is copied in both directions until either side closes.
+///
+/// # Arguments
+///
+/// * `runtime` - The runtime used to listen locally and spawn tasks.
+/// * `local_addr` - The local address to listen on (e.g., "127.0.0.1:8080").
+/// * `dial` - Opens a new stream to the remote service (e.g., through Tor).
+///
+/// # Errors
+///
+/// Returns an error if the local listener cannot be created.
+pub async fn run_connect_loop<R, D, Fut, S>(
+    runtime: R,
+    local_addr: SocketAddr,
+    dial: D,
+) -> Result<()>
+where
+    R: Runtime,
+    D: Fn() -> Fut,
+    Fut: Future<Output = Result<S>> + Send + 'static,
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let listener = runtime
+        .listen(&local_addr)
+        .await
+        .map_err(|source| Error::Listen {
+            address: local_addr,
+            source,
+        })?;
+
+    let mut incoming = listener.incoming();
+
+    while let Some(accepted) = incoming.next().await {
+        let (local_stream, peer) = match accepted {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("{}", t!("connect.errors.accept", err = e));
+                continue;
+            }
+        };
+
+        info!("{}", t!("connect.accepted", peer = peer));
+
+        let remote = dial();
+        let spawn_res = runtime.spawn(async move {
+            let result: Result<_> = async {
+                let remote_stream = remote.await?;
+                crate::proxy::copy_bidirectional(local_stream, remote_stream).await
+            }
+            .await;
+
+            match result {
+                Ok((up, down)) => debug!("Stream closed. Up: {} B, Down: {} B", up, down),
+                Err(e) => warn!(
+                    "{}",
+                    t!(
+                        "connect.errors.connection",
+                        error = format!("{:#}", anyhow::Error::from(e))
+                    )
+                ),
+            }
+        });
+
+        if let Err(e) = spawn_res {
+            warn!("{}: {}", t!("connect.errors.task"), e);
+        }
+    }
+
+    Ok(())
+}
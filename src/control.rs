@@ -0,0 +1,463 @@
+// src/control.rs
+use crate::error::{Error, Result};
+use crate::proxy::PortMap;
+use crate::shutdown::ShutdownHandle;
+use crate::stats::TrafficStats;
+use crate::tor::{RestrictedClient, TorStatus};
+use crate::utils::read_line_limited;
+use futures::io::BufReader;
+use futures::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, StreamExt};
+use rust_i18n::t;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::path::Path;
+use std::sync::Mutex;
+use tor_rtcompat::Runtime;
+use tracing::{debug, info};
+
+/// Maximum length of a request line; longer lines close the connection.
+pub const MAX_REQUEST_LINE: usize = 64 * 1024;
+
+/// A request sent to the control socket, as one line of JSON (e.g., `{"cmd":"list"}`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum ControlRequest {
+    /// Lists the services with their onion addresses, port mappings and clients.
+    List,
+    /// Returns the traffic statistics and the Tor status.
+    Stats,
+    /// Forwards a virtual port to another local target.
+    MapPort {
+        /// The virtual port of the onion service.
+        port: u16,
+        /// The local address to forward it to (e.g., "127.0.0.1:8080").
+        target: String,
+    },
+    /// Forwards a virtual port to the default target again.
+    UnmapPort {
+        /// The virtual port of the onion service.
+        port: u16,
+    },
+    /// Authorizes a client; enables restricted discovery if the service was public.
+    AddClient {
+        /// Public key of the client (format: `descriptor:x25519:<KEY>`).
+        key: String,
+        /// Name of the client; generated if missing.
+        #[serde(default)]
+        nickname: Option<String>,
+    },
+    /// Revokes the authorization of a client.
+    RevokeClient {
+        /// Name of the client.
+        nickname: String,
+    },
+    /// Stops accepting new streams and exits once the active ones have finished.
+    Shutdown,
+}
+
+/// The answer to a [`ControlRequest`], as one line of JSON.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ControlResponse {
+    /// Whether the request succeeded.
+    pub ok: bool,
+    /// The result of a successful request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    /// Why the request failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl From<Result<Value>> for ControlResponse {
+    /// Converts the result of a request; a failure is reported with its causes.
+    fn from(result: Result<Value>) -> Self {
+        match result {
+            Ok(value) => Self {
+                ok: true,
+                result: Some(value),
+                error: None,
+            },
+            Err(e) => Self {
+                ok: false,
+                result: None,
+                error: Some(format!("{:#}", anyhow::Error::from(e))),
+            },
+        }
+    }
+}
+
+/// A service managed through the control socket.
+#[derive(Debug, Clone)]
+pub struct ControlledService {
+    /// Nickname of the onion service.
+    pub nickname: String,
+    /// The `.onion` address of the service.
+    pub onion_address: String,
+    /// Default local target, or `None` when the service is not a proxy (e.g., `receive`).
+    pub target: Option<String>,
+    /// Targets of individual virtual ports, shared with the proxy loop.
+    pub ports: PortMap,
+    /// Whether restricted discovery is enabled.
+    pub restricted: bool,
+    /// Clients allowed to discover the service.
+    pub clients: Vec<RestrictedClient>,
+}
+
+/// A port mapping, as listed by [`ControlRequest::List`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PortMapping {
+    /// The virtual port of the onion service.
+    pub port: u16,
+    /// The local address it is forwarded to.
+    pub target: String,
+}
+
+/// A service, as listed by [`ControlRequest::List`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ServiceInfo {
+    /// Nickname of the onion service.
+    pub nickname: String,
+    /// The `.onion` address of the service.
+    pub onion_address: String,
+    /// Default local target, if the service is a proxy.
+    pub target: Option<String>,
+    /// Virtual ports forwarded elsewhere than the default target.
+    pub ports: Vec<PortMapping>,
+    /// Whether restricted discovery is enabled.
+    pub restricted: bool,
+    /// Clients allowed to discover the service.
+    pub clients: Vec<RestrictedClient>,
+}
+
+/// Applies a new list of authorized clients to the running service.
+pub type SetClients = Box<dyn Fn(&[RestrictedClient]) -> Result<()> + Send + Sync>;
+
+/// Shared runtime state that control requests read and change.
+pub struct Controller {
+    service: Mutex<ControlledService>,
+    set_clients: SetClients,
+    stats: TrafficStats,
+    tor: TorStatus,
+    shutdown: ShutdownHandle,
+}
+
+impl Controller {
+    /// Creates a controller for `service`.
+    ///
+    /// # Arguments
+    ///
+    /// * `service` - The service to manage.
+    /// * `stats` - Traffic counters of the service.
+    /// * `tor` - Status of the Tor client and of the onion service.
+    /// * `shutdown` - Triggered by [`ControlRequest::Shutdown`].
+    /// * `set_clients` - Called with the complete new list whenever a client is added or
+    ///   revoked (e.g., with [`crate::tor::set_restricted_clients`]).
+    pub fn new(
+        service: ControlledService,
+        stats: TrafficStats,
+        tor: TorStatus,
+        shutdown: ShutdownHandle,
+        set_clients: SetClients,
+    ) -> Self {
+        Self {
+            service: Mutex::new(service),
+            set_clients,
+            stats,
+            tor,
+            shutdown,
+        }
+    }
+
+    /// Executes `request` and returns its result.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request is invalid in the current state
+    /// (e.g., revoking an unknown client) or cannot be applied.
+    pub fn handle(&self, request: ControlRequest) -> Result<Value> {
+        match request {
+            ControlRequest::List => Ok(json!({ "services": [self.service_info()] })),
+            ControlRequest::Stats => Ok(json!({
+                "traffic": self.stats.snapshot(),
+                "tor": self.tor.snapshot(),
+            })),
+            ControlRequest::MapPort { port, target } => {
+                let service = self.service.lock().expect("poisoned lock");
+                if service.target.is_none() {
+                    return Err(Error::NotProxy);
+                }
+                let previous = service.ports.insert(port, &target)?;
+                info!(
+                    "{}",
+                    t!("control.port_mapped", port = port, target = target)
+                );
+                Ok(json!({ "port": port, "target": target, "previous": previous }))
+            }
+            ControlRequest::UnmapPort { port } => {
+                let service = self.service.lock().expect("poisoned lock");
+                let target = service
+                    .ports
+                    .remove(port)
+                    .ok_or(Error::PortNotMapped(port))?;
+                info!("{}", t!("control.port_unmapped", port = port));
+                Ok(json!({ "port": port, "target": target }))
+            }
+            ControlRequest::AddClient { key, nickname } => {
+                let client = RestrictedClient::new(&key, nickname.as_deref())?;
+                let mut service = self.service.lock().expect("poisoned lock");
+                if service
+                    .clients
+                    .iter()
+                    .any(|c| c.nickname == client.nickname)
+                {
+                    return Err(Error::ClientExists(client.nickname));
+                }
+
+                let mut clients = service.clients.clone();
+                clients.push(client.clone());
+                (self.set_clients)(&clients)?;
+                service.clients = clients;
+                service.restricted = true;
+
+                info!("{}", t!("control.client_added", nick = client.nickname));
+                Ok(json!(client))
+            }
+            ControlRequest::RevokeClient { nickname } => {
+                let mut service = self.service.lock().expect("poisoned lock");
+                if !service.clients.iter().any(|c| c.nickname == nickname) {
+                    return Err(Error::UnknownClient(nickname));
+                }
+
+                let clients: Vec<RestrictedClient> = service
+                    .clients
+                    .iter()
+                    .filter(|c| c.nickname != nickname)
+                    .cloned()
+                    .collect();
+                (self.set_clients)(&clients)?;
+                service.clients = clients;
+
+                info!("{}", t!("control.client_revoked", nick = nickname));
+                Ok(json!({ "nickname": nickname }))
+            }
+            ControlRequest::Shutdown => {
+                info!("{}", t!("control.shutdown_requested"));
+                self.shutdown.shutdown();
+                Ok(json!({ "active_streams": self.shutdown.active_streams() }))
+            }
+        }
+    }
+
+    fn service_info(&self) -> ServiceInfo {
+        let service = self.service.lock().expect("poisoned lock");
+        ServiceInfo {
+            nickname: service.nickname.clone(),
+            onion_address: service.onion_address.clone(),
+            target: service.target.clone(),
+            ports: service
+                .ports
+                .list()
+                .into_iter()
+                .map(|(port, target)| PortMapping { port, target })
+                .collect(),
+            restricted: service.restricted,
+            clients: service.clients.clone(),
+        }
+    }
+}
+
+/// Answers requests on a control connection, one JSON line each, until it is closed.
+///
+/// # Errors
+///
+/// Returns an error if reading from or writing to `stream` fails, or if a request
+/// is longer than [`MAX_REQUEST_LINE`].
+pub async fn serve_connection<S>(stream: S, controller: &Controller) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (reader, mut writer) = futures::AsyncReadExt::split(stream);
+    let mut reader = BufReader::new(reader);
+
+    while let Some(line) = read_line_limited(&mut reader, MAX_REQUEST_LINE).await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match serde_json::from_str::<ControlRequest>(&line) {
+            Ok(request) => {
+                debug!("Control request: {:?}", request);
+                ControlResponse::from(controller.handle(request))
+            }
+            Err(e) => ControlResponse::from(Err(Error::BadControlRequest(e))),
+        };
+
+        let mut out = serde_json::to_string(&response).map_err(Error::Json)?;
+        out.push('\n');
+        writer.write_all(out.as_bytes()).await?;
+        writer.flush().await?;
+    }
+
+    Ok(())
+}
+
+/// Serves the control API on a Unix socket at `path`.
+///
+/// A stale socket left by a previous run is replaced. The socket is only
+/// accessible to the current user: it is created in a private directory and
+/// moved to `path` once its permissions are set, so no one else can connect
+/// in between.
+///
+/// # Errors
+///
+/// Returns an error if another instance is listening on `path`, or the socket
+/// cannot be created.
+#[cfg(unix)]
+pub async fn run_control_server<R: Runtime>(
+    runtime: R,
+    path: &Path,
+    controller: std::sync::Arc<Controller>,
+) -> Result<()> {
+    use futures::task::SpawnExt;
+    use std::os::unix::fs::PermissionsExt;
+    use tor_rtcompat::NetStreamListener;
+    use tracing::warn;
+
+    remove_stale_socket(path)?;
+
+    let private_dir = create_private_dir(path)?;
+    let bind_path = private_dir.join("control.sock");
+    let bound = async {
+        let addr = std::os::unix::net::SocketAddr::from_pathname(&bind_path)?;
+        let listener = runtime.listen(&addr).await?;
+        std::fs::set_permissions(&bind_path, std::fs::Permissions::from_mode(0o600))?;
+        std::fs::rename(&bind_path, path)?;
+        std::io::Result::Ok(listener)
+    }
+    .await;
+    let _ = std::fs::remove_file(&bind_path);
+    let _ = std::fs::remove_dir(&private_dir);
+    let listener = bound.map_err(|source| Error::ControlSocket {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    info!("{}", t!("control.listening", path = path.display()));
+
+    let mut incoming = listener.incoming();
+    while let Some(accepted) = incoming.next().await {
+        let stream = match accepted {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                warn!("{}", t!("control.errors.accept", err = e));
+                continue;
+            }
+        };
+
+        let controller = controller.clone();
+        let spawn_res = runtime.spawn(async move {
+            if let Err(e) = serve_connection(stream, &controller).await {
+                debug!("Control connection failed: {:#}", e);
+            }
+        });
+
+        if let Err(e) = spawn_res {
+            warn!("{}: {}", t!("control.errors.task"), e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Creates a directory only accessible to the current user next to `path`, to
+/// bind the socket in.
+#[cfg(unix)]
+fn create_private_dir(path: &Path) -> Result<std::path::PathBuf> {
+    use std::os::unix::fs::DirBuilderExt;
+
+    let parent = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let dir = parent.join(format!(".onionize-{:016x}", rand::random::<u64>()));
+    std::fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&dir)
+        .map_err(|source| Error::ControlSocket {
+            path: path.to_path_buf(),
+            source,
+        })?;
+    Ok(dir)
+}
+
+/// Removes a socket left over at `path` by an instance that is no longer running.
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let Ok(metadata) = std::fs::symlink_metadata(path) else {
+        return Ok(());
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(Error::NotASocket(path.to_path_buf()));
+    }
+    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+        return Err(Error::ControlSocketInUse(path.to_path_buf()));
+    }
+    std::fs::remove_file(path).map_err(|source| Error::ControlSocket {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// Sends `request` to the control socket at `path` and returns the response.
+///
+/// # Errors
+///
+/// Returns an error if the socket cannot be reached or the response is malformed.
+#[cfg(unix)]
+pub async fn send_request<R: Runtime>(
+    runtime: &R,
+    path: &Path,
+    request: &ControlRequest,
+) -> Result<ControlResponse> {
+    let connect_error = |source| Error::ControlConnect {
+        path: path.to_path_buf(),
+        source,
+    };
+    let addr = std::os::unix::net::SocketAddr::from_pathname(path).map_err(connect_error)?;
+    let stream = runtime.connect(&addr).await.map_err(connect_error)?;
+
+    let (reader, mut writer) = futures::AsyncReadExt::split(stream);
+    let mut line = serde_json::to_string(request).map_err(Error::Json)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await?;
+    writer.flush().await?;
+
+    let response = BufReader::new(reader)
+        .lines()
+        .next()
+        .await
+        .ok_or(Error::NoControlResponse(None))??;
+    serde_json::from_str(&response).map_err(|e| Error::NoControlResponse(Some(e)))
+}
+
+/// Unix sockets are not available on this platform.
+#[cfg(not(unix))]
+pub async fn run_control_server<R: Runtime>(
+    _runtime: R,
+    _path: &Path,
+    _controller: std::sync::Arc<Controller>,
+) -> Result<()> {
+    Err(Error::ControlSocketUnsupported)
+}
+
+/// Unix sockets are not available on this platform.
+#[cfg(not(unix))]
+pub async fn send_request<R: Runtime>(
+    _runtime: &R,
+    _path: &Path,
+    _request: &ControlRequest,
+) -> Result<ControlResponse> {
+    Err(Error::ControlSocketUnsupported)
+}
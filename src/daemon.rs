@@ -0,0 +1,294 @@
+// src/daemon.rs
+use anyhow::{Context, Result};
+use rust_i18n::t;
+use std::fmt;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tor_rtcompat::SleepProvider;
+use tracing::debug;
+
+/// Exit code for invalid arguments or configuration (`EX_CONFIG` in sysexits.h).
+pub const EXIT_CONFIG: u8 = 78;
+
+/// Exit code when Tor cannot bootstrap or the service cannot be launched
+/// (`EX_UNAVAILABLE` in sysexits.h).
+pub const EXIT_BOOTSTRAP: u8 = 69;
+
+/// Exit code for any other error.
+pub const EXIT_FAILURE: u8 = 1;
+
+/// Write end of the pipe to the parent waiting in [`detach`], until a status is reported.
+#[cfg(unix)]
+static PARENT_PIPE: std::sync::Mutex<Option<std::io::PipeWriter>> = std::sync::Mutex::new(None);
+
+/// The kind of a fatal error, which selects the exit code of the process.
+///
+/// Attach it to an error as context, e.g. `.context(Failure::Bootstrap)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// The arguments or a file they point to are invalid.
+    Config,
+    /// The Tor client could not bootstrap or the onion service could not be launched.
+    Bootstrap,
+}
+
+impl Failure {
+    /// Returns the exit code for this kind of failure.
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Failure::Config => EXIT_CONFIG,
+            Failure::Bootstrap => EXIT_BOOTSTRAP,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Config => f.write_str(&t!("daemon.failure.config")),
+            Failure::Bootstrap => f.write_str(&t!("daemon.failure.bootstrap")),
+        }
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// Returns the exit code for `err`: the one of its [`Failure`], or [`EXIT_FAILURE`].
+pub fn exit_code(err: &anyhow::Error) -> u8 {
+    err.downcast_ref::<Failure>()
+        .map_or(EXIT_FAILURE, |failure| failure.exit_code())
+}
+
+/// Sends state changes to the service manager (`sd_notify`).
+///
+/// Does nothing when the process is not started by systemd with `Type=notify`.
+#[derive(Debug, Clone, Default)]
+pub struct Notifier {
+    socket: Option<String>,
+}
+
+impl Notifier {
+    /// Creates a notifier for the socket in `NOTIFY_SOCKET`, if any.
+    pub fn from_env() -> Self {
+        Self {
+            socket: std::env::var("NOTIFY_SOCKET")
+                .ok()
+                .filter(|socket| !socket.is_empty()),
+        }
+    }
+
+    /// Creates a notifier for `socket`: a path, or an abstract name starting with `@`.
+    pub fn new(socket: impl Into<String>) -> Self {
+        Self {
+            socket: Some(socket.into()),
+        }
+    }
+
+    /// Returns `true` if there is a service manager to notify.
+    pub fn is_enabled(&self) -> bool {
+        self.socket.is_some()
+    }
+
+    /// Sends `state`, one `KEY=VALUE` assignment per line.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the datagram cannot be sent.
+    #[cfg(unix)]
+    pub fn notify(&self, state: &str) -> std::io::Result<()> {
+        use std::os::unix::net::UnixDatagram;
+
+        let Some(socket) = &self.socket else {
+            return Ok(());
+        };
+        let datagram = UnixDatagram::unbound()?;
+
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if let Some(name) = socket.strip_prefix('@') {
+            #[cfg(target_os = "android")]
+            use std::os::android::net::SocketAddrExt;
+            #[cfg(target_os = "linux")]
+            use std::os::linux::net::SocketAddrExt;
+
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            datagram.send_to_addr(state.as_bytes(), &addr)?;
+            return Ok(());
+        }
+
+        datagram.send_to(state.as_bytes(), socket)?;
+        Ok(())
+    }
+
+    /// Sends `state`, one `KEY=VALUE` assignment per line.
+    ///
+    /// # Errors
+    ///
+    /// Never fails: there is no service manager to notify on this platform.
+    #[cfg(not(unix))]
+    pub fn notify(&self, _state: &str) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    /// Reports that the service is up, with a human-readable status.
+    ///
+    /// After [`detach`], this also lets the waiting parent exit with success.
+    pub fn ready(&self, status: &str) {
+        self.send(&format!("READY=1\nSTATUS={}\n", one_line(status)));
+        report_to_parent(0);
+    }
+
+    /// Updates the human-readable status shown by `systemctl status`.
+    pub fn status(&self, status: &str) {
+        self.send(&format!("STATUS={}\n", one_line(status)));
+    }
+
+    /// Reports that the service is shutting down.
+    pub fn stopping(&self, status: &str) {
+        self.send(&format!("STOPPING=1\nSTATUS={}\n", one_line(status)));
+    }
+
+    /// Tells the watchdog that the process is alive.
+    pub fn watchdog(&self) {
+        self.send("WATCHDOG=1\n");
+    }
+
+    fn send(&self, state: &str) {
+        if let Err(e) = self.notify(state) {
+            debug!("Failed to notify the service manager: {}", e);
+        }
+    }
+}
+
+/// Collapses `status` to one line, as the notification format requires.
+fn one_line(status: &str) -> String {
+    status.replace(['\r', '\n'], " ")
+}
+
+/// Returns the watchdog timeout set by systemd (`WatchdogSec=`) for this process, if any.
+pub fn watchdog_timeout() -> Option<Duration> {
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    // Without WATCHDOG_PID, the timeout applies to this process
+    if let Ok(pid) = std::env::var("WATCHDOG_PID")
+        && pid.parse() != Ok(std::process::id())
+    {
+        return None;
+    }
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
+
+/// Pings the watchdog at half its `timeout`, for as long as the runtime is responsive.
+pub async fn run_watchdog<R: SleepProvider>(runtime: R, notifier: Notifier, timeout: Duration) {
+    loop {
+        notifier.watchdog();
+        runtime.sleep(timeout / 2).await;
+    }
+}
+
+/// A file holding the process ID, removed when dropped.
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Writes the ID of the current process to `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be written.
+    pub fn create(path: &Path) -> Result<Self> {
+        std::fs::write(path, format!("{}\n", std::process::id()))
+            .with_context(|| t!("daemon.errors.pid_file", path = path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Returns the path of the file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Detaches the process from the terminal: forks, starts a new session and
+/// redirects the standard streams to `/dev/null`.
+///
+/// Only the child returns; the working directory is kept so relative paths still work.
+/// The parent stays in the foreground until the child reports its status with
+/// [`Notifier::ready`] or [`report_to_parent`], and exits with that code, so a
+/// failed start is seen by whoever ran the command. If the child dies without
+/// reporting, the parent exits with [`EXIT_FAILURE`].
+/// This must be called before the async runtime is started.
+///
+/// # Errors
+///
+/// Returns an error if the process cannot be forked.
+#[cfg(unix)]
+pub fn detach() -> Result<()> {
+    let cwd = std::env::current_dir().context(t!("daemon.errors.detach"))?;
+    let (reader, writer) = std::io::pipe().context(t!("daemon.errors.detach"))?;
+
+    let outcome = daemonize::Daemonize::new()
+        .working_directory(cwd)
+        .umask(0o022)
+        .execute();
+    match outcome {
+        daemonize::Outcome::Parent(Ok(_)) => {
+            // Only the child may hold the write end, so its exit ends the wait
+            drop(writer);
+            std::process::exit(wait_for_status(reader).into())
+        }
+        daemonize::Outcome::Parent(Err(e)) => Err(e).context(t!("daemon.errors.detach")),
+        daemonize::Outcome::Child(result) => {
+            drop(reader);
+            result.context(t!("daemon.errors.detach"))?;
+            *PARENT_PIPE.lock().expect("poisoned lock") = Some(writer);
+            Ok(())
+        }
+    }
+}
+
+/// Detaches the process from the terminal.
+///
+/// # Errors
+///
+/// Always fails: detaching needs `fork`, which is not available on this platform.
+#[cfg(not(unix))]
+pub fn detach() -> Result<()> {
+    anyhow::bail!(t!("daemon.errors.unsupported"))
+}
+
+/// Reads the exit code the detached child sends to the parent: one byte, or
+/// [`EXIT_FAILURE`] if the pipe is closed first.
+pub fn wait_for_status(mut pipe: impl Read) -> u8 {
+    let mut code = [EXIT_FAILURE];
+    match pipe.read_exact(&mut code) {
+        Ok(()) => code[0],
+        Err(_) => EXIT_FAILURE,
+    }
+}
+
+/// Tells the parent waiting in [`detach`] to exit with `code`.
+///
+/// Only the first report counts; it does nothing when the process was not detached.
+pub fn report_to_parent(code: u8) {
+    #[cfg(unix)]
+    {
+        use std::io::Write;
+
+        if let Some(mut pipe) = PARENT_PIPE.lock().expect("poisoned lock").take()
+            && let Err(e) = pipe.write_all(&[code])
+        {
+            debug!("Failed to report the status to the parent process: {}", e);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = code;
+}
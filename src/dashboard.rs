@@ -0,0 +1,132 @@
+// src/dashboard.rs
+use crate::stats::{StatsSnapshot, TrafficStats};
+use crate::utils::format_bytes;
+use rust_i18n::t;
+use std::fmt::Write as _;
+use std::io::Write;
+use std::time::Duration;
+use tor_rtcompat::SleepProvider;
+
+/// How often the dashboard is redrawn.
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Maximum number of circuits listed in the dashboard.
+const MAX_CIRCUIT_ROWS: usize = 10;
+
+/// ANSI sequence that clears the terminal and moves the cursor to the top left corner.
+const CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
+
+/// Redraws the dashboard every [`REFRESH_INTERVAL`], forever.
+///
+/// # Arguments
+///
+/// * `runtime` - The runtime used to wait between frames.
+/// * `stats` - The counters to display.
+/// * `header` - Lines shown above the counters (e.g., the onion address).
+pub async fn run_dashboard<R: SleepProvider>(runtime: R, stats: TrafficStats, header: Vec<String>) {
+    loop {
+        let frame = render(&stats.snapshot(), &header);
+
+        {
+            let mut stdout = std::io::stdout().lock();
+            let _ = write!(stdout, "{CLEAR_SCREEN}{frame}");
+            let _ = stdout.flush();
+        }
+
+        runtime.sleep(REFRESH_INTERVAL).await;
+    }
+}
+
+/// Renders one frame of the dashboard.
+pub fn render(snapshot: &StatsSnapshot, header: &[String]) -> String {
+    let mut out = String::new();
+    let rule = "─".repeat(56);
+
+    for line in header {
+        let _ = writeln!(out, " {line}");
+    }
+    let _ = writeln!(out, "{rule}");
+    let _ = writeln!(
+        out,
+        " {:<12}{}",
+        t!("dashboard.circuits"),
+        t!(
+            "dashboard.active_total",
+            active = snapshot.circuits_active,
+            total = snapshot.circuits_total
+        )
+    );
+    let _ = writeln!(
+        out,
+        " {:<12}{}",
+        t!("dashboard.streams"),
+        t!(
+            "dashboard.active_total",
+            active = snapshot.streams_active,
+            total = snapshot.streams_total
+        )
+    );
+    let _ = writeln!(
+        out,
+        " {:<12}↑ {}   ↓ {}",
+        t!("dashboard.traffic"),
+        format_bytes(snapshot.bytes_up),
+        format_bytes(snapshot.bytes_down)
+    );
+
+    let latency = match snapshot.backend_connect_avg_ms {
+        Some(avg) => t!(
+            "dashboard.latency",
+            avg = avg,
+            max = snapshot.backend_connect_max_ms
+        )
+        .to_string(),
+        None => "-".to_string(),
+    };
+    let _ = writeln!(out, " {:<12}{}", t!("dashboard.backend"), latency);
+
+    let closed = if snapshot.closed.is_empty() {
+        "-".to_string()
+    } else {
+        snapshot
+            .closed
+            .iter()
+            .map(|(reason, count)| format!("{reason}: {count}"))
+            .collect::<Vec<_>>()
+            .join("  ")
+    };
+    let _ = writeln!(out, " {:<12}{}", t!("dashboard.closed"), closed);
+    let _ = writeln!(out, "{rule}");
+
+    if snapshot.circuits.is_empty() {
+        let _ = writeln!(out, " {}", t!("dashboard.no_circuits"));
+    } else {
+        let _ = writeln!(
+            out,
+            " {:<10}{:>10}{:>14}{:>14}",
+            t!("dashboard.circuit"),
+            t!("dashboard.streams"),
+            "↑",
+            "↓"
+        );
+        // Show the most recent circuits
+        let skip = snapshot.circuits.len().saturating_sub(MAX_CIRCUIT_ROWS);
+        for circuit in snapshot.circuits.iter().skip(skip) {
+            let _ = writeln!(
+                out,
+                " {:<10}{:>10}{:>14}{:>14}",
+                format!("#{}", circuit.id),
+                format!("{}/{}", circuit.streams_active, circuit.streams_total),
+                format_bytes(circuit.bytes_up),
+                format_bytes(circuit.bytes_down)
+            );
+        }
+        if skip > 0 {
+            let _ = writeln!(out, " {}", t!("dashboard.more_circuits", count = skip));
+        }
+    }
+
+    let _ = writeln!(out, "{rule}");
+    let _ = writeln!(out, " {}", t!("dashboard.quit_hint"));
+    out
+}
@@ -0,0 +1,423 @@
+// src/error.rs
+use std::path::PathBuf;
+
+/// Errors returned by the library.
+///
+/// Messages are plain English and only the command line translates them, so
+/// callers can match on the kind of failure instead of its text. The underlying
+/// error, if any, is kept as the [`source`](std::error::Error::source).
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The data directories of the user cannot be determined.
+    #[error("Failed to determine project directories")]
+    ProjectDirs,
+
+    /// The Tor client could not be created or bootstrapped.
+    #[error("Bootstrap error")]
+    Bootstrap(#[source] arti_client::Error),
+
+    /// The nickname of an onion service is not a valid Tor nickname.
+    #[error("Invalid nickname: {0}")]
+    InvalidNickname(String),
+
+    /// The nickname of an authorized client is not a valid Tor nickname.
+    #[error("Invalid client nickname: {0}")]
+    InvalidClientNickname(String),
+
+    /// A client authorization key is not in the `descriptor:x25519:<key>` format.
+    #[error("Invalid auth string format. Expected: descriptor:x25519:<key>")]
+    InvalidAuthKey(String),
+
+    /// A base32 x25519 key does not encode 32 bytes.
+    #[error("Invalid x25519 key: expected 52 base32 characters")]
+    InvalidKey,
+
+    /// A base32 x25519 key has the wrong number of characters.
+    #[error("Invalid x25519 key: expected 52 base32 characters, got {0}")]
+    KeyLength(usize),
+
+    /// A base32 x25519 key contains a character outside of the base32 alphabet.
+    #[error("Invalid x25519 key: {character:?} at position {position} is not a base32 character")]
+    KeyCharacter {
+        /// The rejected character.
+        character: char,
+        /// Its position in the key, starting at 1.
+        position: usize,
+    },
+
+    /// A seed for key derivation is not hexadecimal or too short.
+    #[error("Invalid seed: expected at least 16 bytes in hexadecimal")]
+    InvalidSeed,
+
+    /// The cost parameters of the key derivation are out of range.
+    #[error("Invalid key derivation parameters: {0}")]
+    InvalidKdfParams(String),
+
+    /// A key file could not be written.
+    #[error("Failed to write key file: {}", file.display())]
+    WriteKeyFile {
+        /// The file.
+        file: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// The passphrase of an encrypted keystore is wrong.
+    #[error("Wrong passphrase for {}", .0.display())]
+    KeystorePassphrase(PathBuf),
+
+    /// An encrypted keystore is already open in another process.
+    #[error("Encrypted keystore {} is in use by another process", .0.display())]
+    KeystoreLocked(PathBuf),
+
+    /// An encrypted keystore could not be read, decrypted or written.
+    #[error("Failed to access encrypted keystore: {}", file.display())]
+    Keystore {
+        /// The encrypted file.
+        file: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// A passphrase could not be read from the terminal.
+    #[error("Failed to read passphrase")]
+    ReadPassphrase(#[source] std::io::Error),
+
+    /// A new passphrase and its confirmation differ.
+    #[error("Passphrases do not match")]
+    PassphraseMismatch,
+
+    /// An empty passphrase was given.
+    #[error("Empty passphrase")]
+    EmptyPassphrase,
+
+    /// Data is too long for a QR code.
+    #[error("Data too long for a QR code")]
+    QrCode(#[source] qrcode::types::QrError),
+
+    /// A QR code image has an unsupported extension.
+    #[error("Unsupported QR code image format, expected .png or .svg: {}", .0.display())]
+    QrFormat(PathBuf),
+
+    /// A QR code image could not be written.
+    #[error("Failed to write QR code image: {}", file.display())]
+    QrFile {
+        /// The image file.
+        file: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// A line of a `.auth_private` file is malformed.
+    #[error(
+        "Invalid client authorization entry. Expected: <onion-address>:descriptor:x25519:<key>"
+    )]
+    InvalidAuthPrivate,
+
+    /// A string is not a v3 onion address.
+    #[error("Invalid onion address: {0}")]
+    InvalidOnionAddress(String),
+
+    /// A local service address is not an IP address and a port.
+    #[error("Invalid local service address: {target}")]
+    InvalidTarget {
+        /// The rejected address.
+        target: String,
+        #[source]
+        source: std::net::AddrParseError,
+    },
+
+    /// There is neither a default target nor a mapped port to serve.
+    #[error("No target: set a default target or map at least one port")]
+    NoTarget,
+
+    /// A service was launched outside of a Tokio runtime.
+    #[error("Launching a service requires a Tokio runtime")]
+    Runtime(#[source] std::io::Error),
+
+    /// The configuration of an onion service was rejected.
+    #[error("Error in service configuration")]
+    ServiceConfig(#[source] tor_config::ConfigBuildError),
+
+    /// The onion service could not be launched, or has no address.
+    #[error("Failed to launch service")]
+    LaunchService(#[source] Option<arti_client::Error>),
+
+    /// A running onion service could not be reconfigured.
+    #[error("Failed to reconfigure the onion service")]
+    Reconfigure(#[source] tor_config::ReconfigureError),
+
+    /// The keys of a stopped onion service could not be removed from the keystore.
+    #[error("Failed to remove the keys of service {nickname}")]
+    RemoveServiceKeys {
+        /// The nickname of the service.
+        nickname: String,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    /// A remote onion service could not be reached.
+    #[error("Failed to connect to onion service: {target}")]
+    Connect {
+        /// The `host:port` that was dialed.
+        target: String,
+        #[source]
+        source: arti_client::Error,
+    },
+
+    /// A client authorization key could not be stored in the keystore.
+    #[error("Failed to store client authorization key for {address}")]
+    ClientKey {
+        /// The onion address the key is for.
+        address: String,
+        #[source]
+        source: arti_client::Error,
+    },
+
+    /// A visitor asked for a virtual port that is not mapped, with only mapped ports served.
+    #[error("Refused a stream to unmapped virtual port {0}")]
+    UnmappedPort(u16),
+
+    /// A stream hook rejected a stream to this virtual port.
+    #[error("A hook rejected a stream to virtual port {0}")]
+    StreamRejected(u16),
+
+    /// The local service refused the connection.
+    #[error("Local service unreachable: {target}")]
+    BackendUnreachable {
+        /// The address of the local service.
+        target: String,
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// The local service did not accept the connection in time.
+    #[error("Timed out connecting to local service: {0}")]
+    ConnectTimeout(String),
+
+    /// The other end closed the Tor stream with an `END` cell while data was in flight.
+    #[error("Stream closed by the remote side")]
+    RemoteClosed(#[source] std::io::Error),
+
+    /// A client authorization directory could not be read.
+    #[error("Failed to read client authorization directory: {}", dir.display())]
+    ReadAuthDir {
+        /// The directory.
+        dir: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// A client authorization file could not be read or contains an invalid entry.
+    #[error("Failed to read client authorization file: {}", file.display())]
+    ReadAuthFile {
+        /// The file.
+        file: PathBuf,
+        #[source]
+        source: Box<Error>,
+    },
+
+    /// A client-mode target is not an `.onion` address with a valid port.
+    #[error("Invalid onion target: {0}. Expected: <address>.onion:<port>")]
+    InvalidOnionTarget(String),
+
+    /// A local listener could not be created.
+    #[error("Failed to listen on {address}")]
+    Listen {
+        /// The local address.
+        address: std::net::SocketAddr,
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// The local SOCKS proxy could not be started.
+    #[error("Failed to start SOCKS proxy on {address}")]
+    SocksListen {
+        /// The local address.
+        address: std::net::SocketAddr,
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// The metrics endpoint could not be started.
+    #[error("Failed to listen for metrics on {address}")]
+    MetricsListen {
+        /// The local address.
+        address: std::net::SocketAddr,
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// A SOCKS client sent a malformed handshake.
+    #[error("SOCKS handshake failed")]
+    Socks(#[source] tor_socksproto::Error),
+
+    /// A SOCKS client asked for something other than `CONNECT`.
+    #[error("Unsupported SOCKS command: {0}")]
+    UnsupportedSocksCommand(String),
+
+    /// A stream requested by a SOCKS client could not be opened through Tor.
+    #[error("Failed to open stream to {target}")]
+    OpenStream {
+        /// The `host:port` that was requested.
+        target: String,
+        #[source]
+        source: arti_client::Error,
+    },
+
+    /// The descriptor of the service was not published in time for the self-test.
+    #[error("Timed out waiting for the service descriptor to be published")]
+    PublishTimeout,
+
+    /// The self-test could not open a stream to the service in time.
+    #[error("Timed out connecting to the service through Tor")]
+    SelfTestTimeout,
+
+    /// The onion service is broken or shut down; holds the current problem, if any.
+    #[error("Onion service is not running {0}")]
+    ServiceNotRunning(String),
+
+    /// A stream was closed before the local service answered.
+    #[error("Stream closed without a response (is the local service running?)")]
+    NoResponse,
+
+    /// A control request needs a proxied local port.
+    #[error("Port mappings are only available when proxying a local port")]
+    NotProxy,
+
+    /// A control request names a virtual port that is not mapped.
+    #[error("Virtual port {0} is not mapped")]
+    PortNotMapped(u16),
+
+    /// A control request adds a client under a nickname already in use.
+    #[error("Client {0} is already authorized")]
+    ClientExists(String),
+
+    /// A control request names a client that is not authorized.
+    #[error("Unknown client: {0}")]
+    UnknownClient(String),
+
+    /// A line sent to the control socket is not a valid request.
+    #[error("Invalid request")]
+    BadControlRequest(#[source] serde_json::Error),
+
+    /// The control socket could not be created.
+    #[error("Failed to create the control socket {}", path.display())]
+    ControlSocket {
+        /// The socket path.
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// The control socket of a running instance could not be reached.
+    #[error(
+        "Failed to connect to the control socket {} (is onionize running with --control-socket?)",
+        path.display()
+    )]
+    ControlConnect {
+        /// The socket path.
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// The control socket path exists and is not a socket.
+    #[error("{} exists and is not a socket", .0.display())]
+    NotASocket(PathBuf),
+
+    /// Another instance is listening on the control socket path.
+    #[error("Another instance is already listening on {}", .0.display())]
+    ControlSocketInUse(PathBuf),
+
+    /// The control socket sent no response, or one that is not valid JSON.
+    #[error("Invalid or missing response from the control socket")]
+    NoControlResponse(#[source] Option<serde_json::Error>),
+
+    /// A control message could not be encoded as JSON.
+    #[error("Failed to encode JSON")]
+    Json(#[source] serde_json::Error),
+
+    /// The control socket is not available on this platform.
+    #[error("The control API needs Unix sockets, which are not available on this platform")]
+    ControlSocketUnsupported,
+
+    /// An upload request is not valid `multipart/form-data`.
+    #[error("Malformed upload request")]
+    MalformedUpload,
+
+    /// The connection was closed before the request was complete.
+    #[error("Connection closed in the middle of a request")]
+    IncompleteRequest,
+
+    /// An uploaded file could not be created.
+    #[error("Failed to create file: {}", file.display())]
+    CreateUpload {
+        /// The file.
+        file: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// An uploaded file could not be written.
+    #[error("Failed to write file: {file}")]
+    WriteUpload {
+        /// The name of the file in the upload directory.
+        file: String,
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// A hashed control port password is not in the format of C-tor.
+    #[error("Invalid hashed password: expected 16: followed by 58 hexadecimal digits")]
+    InvalidHashedPassword,
+
+    /// The authentication cookie of the control port could not be written.
+    #[error("Failed to write the authentication cookie {}", file.display())]
+    ControlCookie {
+        /// The cookie file.
+        file: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// A maintenance page could not be read.
+    #[error("Failed to read maintenance page: {}", file.display())]
+    MaintenancePage {
+        /// The page file.
+        file: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// A task could not be spawned on the runtime.
+    #[error("Failed to spawn a task")]
+    Spawn(#[from] futures::task::SpawnError),
+
+    /// An HTTP connection served over an onion stream failed (feature "axum").
+    #[error("HTTP connection failed")]
+    Http(#[source] Box<dyn std::error::Error + Send + Sync>),
+
+    /// Any other I/O error, e.g. while copying data between streams.
+    #[error(transparent)]
+    Io(std::io::Error),
+}
+
+impl From<std::io::Error> for Error {
+    /// Wraps an I/O error, telling apart streams closed by the other end of a Tor circuit.
+    fn from(err: std::io::Error) -> Self {
+        let end_received = err
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<tor_proto::Error>())
+            .is_some_and(|inner| matches!(inner, tor_proto::Error::EndReceived(_)));
+        if end_received {
+            Error::RemoteClosed(err)
+        } else {
+            Error::Io(err)
+        }
+    }
+}
+
+/// A `Result` whose error is [`Error`].
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,117 @@
+// src/events.rs
+use crate::proxy::CloseReason;
+use futures::Stream;
+use futures::channel::mpsc;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Whether events are printed to stdout as JSON lines (`--json`).
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+/// A machine-readable event, printed as one JSON object per line with `--json`.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// The onion service has been launched.
+    ServiceReady {
+        /// The `.onion` address of the service.
+        onion_address: String,
+        /// The `auth_private` line clients need for a service restricted with a
+        /// generated key (`--restricted`), absent otherwise.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        credential: Option<String>,
+    },
+    /// Result of the self-test performed after launch.
+    SelfTest {
+        /// Whether the service could be reached through Tor.
+        success: bool,
+        /// Time taken to open a stream to the service, in milliseconds.
+        connect_ms: Option<u64>,
+        /// Time from sending the probe request to the first response byte, in milliseconds.
+        response_ms: Option<u64>,
+        /// First line of the backend response, if any.
+        status_line: Option<String>,
+        /// Why the self-test failed.
+        error: Option<String>,
+    },
+    /// A proxied stream has been closed.
+    StreamClosed {
+        /// Why the stream was closed.
+        reason: CloseReason,
+        /// Bytes sent from the Tor client to the local service.
+        bytes_up: u64,
+        /// Bytes sent from the local service to the Tor client.
+        bytes_down: u64,
+        /// How long the stream was open, in milliseconds.
+        duration_ms: u64,
+    },
+}
+
+/// Enables or disables JSON event output.
+pub fn set_json_output(enabled: bool) {
+    JSON_OUTPUT.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns `true` if events are printed as JSON lines.
+pub fn json_output() -> bool {
+    JSON_OUTPUT.load(Ordering::Relaxed)
+}
+
+/// Prints `event` to stdout as a JSON line, if JSON output is enabled.
+pub fn emit(event: &Event) {
+    if !json_output() {
+        return;
+    }
+    if let Ok(line) = serde_json::to_string(event) {
+        println!("{}", line);
+    }
+}
+
+/// Delivers the events of a service to its subscribers.
+///
+/// The last [`Event::ServiceReady`] is kept and sent first to later subscribers,
+/// since it is published before anyone can subscribe. Clones share the same subscribers.
+#[derive(Debug, Clone, Default)]
+pub struct EventBus {
+    inner: Arc<Mutex<Subscribers>>,
+}
+
+#[derive(Debug, Default)]
+struct Subscribers {
+    senders: Vec<mpsc::UnboundedSender<Event>>,
+    ready: Option<Event>,
+}
+
+impl EventBus {
+    /// Creates a bus without subscribers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a stream of the events published from now on, starting with the
+    /// last [`Event::ServiceReady`] if there was one.
+    pub fn subscribe(&self) -> impl Stream<Item = Event> + use<> {
+        let (sender, receiver) = mpsc::unbounded();
+        let mut inner = self.inner.lock().expect("poisoned lock");
+        if let Some(ready) = &inner.ready {
+            // Cannot fail: the receiver is still here
+            let _ = sender.unbounded_send(ready.clone());
+        }
+        inner.senders.push(sender);
+        receiver
+    }
+
+    /// Sends `event` to every subscriber and prints it if JSON output is enabled.
+    pub fn publish(&self, event: &Event) {
+        emit(event);
+        let mut inner = self.inner.lock().expect("poisoned lock");
+        if matches!(event, Event::ServiceReady { .. }) {
+            inner.ready = Some(event.clone());
+        }
+        // Subscribers that dropped their stream are forgotten
+        inner
+            .senders
+            .retain(|sender| sender.unbounded_send(event.clone()).is_ok());
+    }
+}
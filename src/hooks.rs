@@ -0,0 +1,292 @@
+// src/hooks.rs
+use crate::proxy::CloseReason;
+use crate::ratelimit::Direction;
+use rust_i18n::t;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::debug;
+
+/// Identifies the stream a [`StreamHook`] is called for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct StreamInfo {
+    /// Sequential number of the rendezvous circuit since launch, as in
+    /// [`crate::stats::CircuitSnapshot::id`]. Zero outside of the proxy loop.
+    pub circuit_id: u64,
+    /// The virtual port the visitor asked for.
+    pub virtual_port: u16,
+}
+
+/// What to do with a requested stream, as returned by [`StreamHook::on_stream_request`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum StreamDecision {
+    /// Proxy the stream as usual.
+    #[default]
+    Accept,
+    /// Close the stream without connecting to the local service.
+    Reject,
+    /// Forward the stream to this TCP address (e.g., "127.0.0.1:8081") instead.
+    Remap(String),
+}
+
+/// How a stream ended, as passed to [`StreamHook::on_stream_closed`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamSummary {
+    /// Why the stream was closed.
+    pub reason: CloseReason,
+    /// Bytes sent from the Tor client to the local service.
+    pub bytes_up: u64,
+    /// Bytes sent from the local service to the Tor client.
+    pub bytes_down: u64,
+    /// How long the stream was open.
+    pub duration: Duration,
+}
+
+/// Callbacks invoked by the proxy for every circuit and stream, to audit or
+/// customize connections.
+///
+/// Every method does nothing by default, so a hook only implements what it needs.
+/// Hooks are called from the proxy tasks and must return quickly.
+///
+/// ```
+/// use onionize::hooks::{StreamDecision, StreamHook, StreamInfo};
+///
+/// /// Only serves the web ports.
+/// struct WebOnly;
+///
+/// impl StreamHook for WebOnly {
+///     fn on_stream_request(&self, stream: &StreamInfo) -> StreamDecision {
+///         match stream.virtual_port {
+///             80 | 443 => StreamDecision::Accept,
+///             _ => StreamDecision::Reject,
+///         }
+///     }
+/// }
+/// ```
+pub trait StreamHook: Send + Sync {
+    /// Called when a visitor opens a rendezvous circuit.
+    fn on_circuit_opened(&self, _circuit_id: u64) {}
+
+    /// Called for every stream a visitor opens, before connecting to the local service.
+    fn on_stream_request(&self, _stream: &StreamInfo) -> StreamDecision {
+        StreamDecision::Accept
+    }
+
+    /// Called once the local service `target` accepted the connection after `latency`.
+    fn on_backend_connected(&self, _stream: &StreamInfo, _target: &str, _latency: Duration) {}
+
+    /// Called for every chunk of `bytes` copied in `direction`.
+    fn on_transfer(&self, _stream: &StreamInfo, _direction: Direction, _bytes: usize) {}
+
+    /// Called when a proxied stream is closed, including when the local service
+    /// could not be reached or the stream was refused.
+    fn on_stream_closed(&self, _stream: &StreamInfo, _summary: &StreamSummary) {}
+}
+
+/// The hooks of a service, called in the order they were added.
+///
+/// Like [`crate::stats::TrafficStats`], the clones returned by [`Hooks::for_circuit`]
+/// and [`Hooks::for_stream`] remember which circuit and stream they are called for.
+#[derive(Clone, Default)]
+pub struct Hooks {
+    hooks: Arc<Vec<Arc<dyn StreamHook>>>,
+    stream: StreamInfo,
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("len", &self.hooks.len())
+            .field("stream", &self.stream)
+            .finish()
+    }
+}
+
+impl Hooks {
+    /// Creates an empty list of hooks.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `hook` after the existing ones.
+    pub fn add(&mut self, hook: impl StreamHook + 'static) {
+        Arc::make_mut(&mut self.hooks).push(Arc::new(hook));
+    }
+
+    /// Returns `true` if no hook has been added.
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    /// Returns the stream the hooks are called for.
+    pub fn stream(&self) -> StreamInfo {
+        self.stream
+    }
+
+    /// Reports a new rendezvous circuit and returns hooks called for its streams.
+    pub fn for_circuit(&self, circuit_id: u64) -> Self {
+        self.each(|hook| hook.on_circuit_opened(circuit_id));
+        Self {
+            hooks: self.hooks.clone(),
+            stream: StreamInfo {
+                circuit_id,
+                virtual_port: 0,
+            },
+        }
+    }
+
+    /// Returns hooks called for a stream to `virtual_port` on the same circuit.
+    pub fn for_stream(&self, virtual_port: u16) -> Self {
+        Self {
+            hooks: self.hooks.clone(),
+            stream: StreamInfo {
+                virtual_port,
+                ..self.stream
+            },
+        }
+    }
+
+    /// Asks every hook about the stream; the first decision other than
+    /// [`StreamDecision::Accept`] wins.
+    pub fn stream_request(&self) -> StreamDecision {
+        self.hooks
+            .iter()
+            .map(|hook| hook.on_stream_request(&self.stream))
+            .find(|decision| *decision != StreamDecision::Accept)
+            .unwrap_or_default()
+    }
+
+    /// Reports a successful connection to `target`.
+    pub fn backend_connected(&self, target: &str, latency: Duration) {
+        self.each(|hook| hook.on_backend_connected(&self.stream, target, latency));
+    }
+
+    /// Reports `bytes` copied in `direction`.
+    pub fn transferred(&self, direction: Direction, bytes: usize) {
+        self.each(|hook| hook.on_transfer(&self.stream, direction, bytes));
+    }
+
+    /// Reports a closed stream.
+    pub fn stream_closed(&self, summary: &StreamSummary) {
+        self.each(|hook| hook.on_stream_closed(&self.stream, summary));
+    }
+
+    fn each(&self, call: impl Fn(&dyn StreamHook)) {
+        for hook in self.hooks.iter() {
+            call(hook.as_ref());
+        }
+    }
+}
+
+/// Logs circuits and streams at debug level, with their circuit and virtual port.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogHook;
+
+impl StreamHook for LogHook {
+    fn on_circuit_opened(&self, circuit_id: u64) {
+        debug!("{}", t!("hooks.circuit_opened", circuit = circuit_id));
+    }
+
+    fn on_stream_request(&self, stream: &StreamInfo) -> StreamDecision {
+        debug!(
+            "{}",
+            t!(
+                "hooks.stream_request",
+                circuit = stream.circuit_id,
+                port = stream.virtual_port
+            )
+        );
+        StreamDecision::Accept
+    }
+
+    fn on_backend_connected(&self, stream: &StreamInfo, target: &str, latency: Duration) {
+        debug!(
+            "{}",
+            t!(
+                "hooks.backend_connected",
+                circuit = stream.circuit_id,
+                target = target,
+                ms = latency.as_millis()
+            )
+        );
+    }
+
+    fn on_stream_closed(&self, stream: &StreamInfo, summary: &StreamSummary) {
+        debug!(
+            "{}",
+            t!(
+                "hooks.stream_closed",
+                circuit = stream.circuit_id,
+                port = stream.virtual_port,
+                reason = summary.reason,
+                ms = summary.duration.as_millis()
+            )
+        );
+    }
+}
+
+/// Counters of a single virtual port, see [`PortMetrics`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct PortSnapshot {
+    /// Streams closed, for any reason.
+    pub streams_closed: u64,
+    /// Streams closed for any reason other than completion.
+    pub errors: u64,
+    /// Bytes sent from Tor clients to the local service.
+    pub bytes_up: u64,
+    /// Bytes sent from the local service to Tor clients.
+    pub bytes_down: u64,
+}
+
+/// Counts streams and bytes per virtual port, which [`crate::stats::TrafficStats`]
+/// does not break down.
+///
+/// Clones share the same counters, so keep one to read them while another is in
+/// the hooks of the service.
+#[derive(Debug, Clone, Default)]
+pub struct PortMetrics {
+    ports: Arc<Mutex<BTreeMap<u16, PortSnapshot>>>,
+}
+
+impl PortMetrics {
+    /// Creates empty counters.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a copy of the counters, by virtual port.
+    pub fn snapshot(&self) -> BTreeMap<u16, PortSnapshot> {
+        self.ports.lock().expect("poisoned lock").clone()
+    }
+
+    fn update(&self, port: u16, update: impl FnOnce(&mut PortSnapshot)) {
+        update(
+            self.ports
+                .lock()
+                .expect("poisoned lock")
+                .entry(port)
+                .or_default(),
+        );
+    }
+}
+
+impl StreamHook for PortMetrics {
+    fn on_transfer(&self, stream: &StreamInfo, direction: Direction, bytes: usize) {
+        let bytes = bytes as u64;
+        self.update(stream.virtual_port, |port| match direction {
+            Direction::Upload => port.bytes_up += bytes,
+            Direction::Download => port.bytes_down += bytes,
+        });
+    }
+
+    fn on_stream_closed(&self, stream: &StreamInfo, summary: &StreamSummary) {
+        self.update(stream.virtual_port, |port| {
+            port.streams_closed += 1;
+            if summary.reason != CloseReason::Completed {
+                port.errors += 1;
+            }
+        });
+    }
+}
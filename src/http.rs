@@ -0,0 +1,166 @@
+// src/http.rs
+use crate::error::{Error, Result};
+use crate::proxy::CloseReason;
+use crate::shutdown::ShutdownHandle;
+use crate::stats::TrafficStats;
+use futures::future::{self, Either};
+use futures::task::{Spawn, SpawnExt};
+use futures::{AsyncRead, AsyncWrite, Stream};
+use hyper::body::{Body, Incoming};
+use hyper::{Request, Response};
+use hyper_util::rt::TokioIo;
+use hyper_util::server::conn::auto;
+use std::pin::pin;
+use tokio_util::compat::FuturesAsyncReadCompatExt;
+use tor_hsservice::RendRequest;
+use tor_rtcompat::Runtime;
+use tower_service::Service;
+
+/// Error type accepted from services and bodies, as in hyper.
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Where an HTTP request came from, added to the extensions of every request.
+///
+/// With axum, extract it with `Extension<OnionStream>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OnionStream {
+    /// Sequential number of the rendezvous circuit since launch. Requests with the
+    /// same number come from the same circuit, which usually means the same visitor.
+    pub circuit_id: u64,
+    /// The virtual port the visitor asked for.
+    pub virtual_port: u16,
+}
+
+/// Serves `service` over HTTP/1.1 and HTTP/2 on every stream of an onion service.
+///
+/// This is the HTTP counterpart of [`crate::proxy::run_proxy_loop`]: no local port is
+/// bound, the requests are passed to `service` (e.g., an `axum::Router`) with an
+/// [`OnionStream`] extension. Every virtual port is served.
+///
+/// Once `shutdown` is triggered, no new streams are accepted and open connections
+/// finish their current requests.
+///
+/// # Arguments
+///
+/// * `runtime` - The runtime used to spawn tasks.
+/// * `rendezvous_requests` - The stream of incoming requests from the Onion Service.
+/// * `service` - The service answering the requests, cloned for every connection.
+/// * `shutdown` - Stops the loop and tracks active streams.
+/// * `stats` - Counts circuits and streams.
+pub async fn run_http_loop<R, S, B>(
+    runtime: R,
+    rendezvous_requests: impl Stream<Item = RendRequest> + Unpin,
+    service: S,
+    shutdown: ShutdownHandle,
+    stats: TrafficStats,
+) where
+    R: Runtime,
+    S: Service<Request<Incoming>, Response = Response<B>> + Clone + Send + Sync + 'static,
+    S::Future: Send + 'static,
+    S::Error: Into<BoxError>,
+    B: Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    let executor = runtime.clone();
+    let stop = shutdown.clone();
+    crate::proxy::run_stream_loop(
+        runtime,
+        rendezvous_requests,
+        shutdown,
+        stats,
+        move |circuit_stats| {
+            let info = OnionStream {
+                circuit_id: circuit_stats.circuit_id().unwrap_or_default(),
+                virtual_port: 0,
+            };
+            let executor = executor.clone();
+            let service = service.clone();
+            let stop = stop.clone();
+            move |tor_stream, port| {
+                let info = OnionStream {
+                    virtual_port: port,
+                    ..info
+                };
+                let executor = executor.clone();
+                let service = service.clone();
+                let stop = stop.clone();
+                let stats = circuit_stats.clone();
+                async move {
+                    stats.stream_opened();
+                    let result =
+                        serve_http_connection(executor, tor_stream, service, info, stop).await;
+                    stats.stream_closed(match result {
+                        Ok(()) => CloseReason::Completed,
+                        Err(_) => CloseReason::Error,
+                    });
+                    result
+                }
+            }
+        },
+    )
+    .await;
+}
+
+/// Serves `service` over HTTP/1.1 or HTTP/2 on a single `stream`, until the
+/// client closes it.
+///
+/// Every request gets `info` as an extension. When `shutdown` is triggered, the
+/// connection is closed gracefully after the current requests.
+///
+/// # Errors
+///
+/// Returns [`Error::Http`] if the connection fails, e.g. on a protocol error.
+pub async fn serve_http_connection<R, T, S, B>(
+    runtime: R,
+    stream: T,
+    service: S,
+    info: OnionStream,
+    shutdown: ShutdownHandle,
+) -> Result<()>
+where
+    R: Spawn + Clone + Send + Sync + 'static,
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: Service<Request<Incoming>, Response = Response<B>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Into<BoxError>,
+    B: Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    let hyper_service = hyper::service::service_fn(move |mut request: Request<Incoming>| {
+        request.extensions_mut().insert(info);
+        let mut service = service.clone();
+        async move {
+            future::poll_fn(|cx| service.poll_ready(cx)).await?;
+            service.call(request).await
+        }
+    });
+
+    let builder = auto::Builder::new(RuntimeExecutor(runtime));
+    let mut connection =
+        pin!(builder.serve_connection_with_upgrades(TokioIo::new(stream.compat()), hyper_service));
+
+    let result = match future::select(connection.as_mut(), pin!(shutdown.wait())).await {
+        Either::Left((result, _)) => result,
+        Either::Right(_) => {
+            connection.as_mut().graceful_shutdown();
+            connection.await
+        }
+    };
+    result.map_err(Error::Http)
+}
+
+/// Runs the background tasks of hyper (e.g., HTTP/2 streams) on our runtime.
+#[derive(Clone)]
+struct RuntimeExecutor<R>(R);
+
+impl<R, F> hyper::rt::Executor<F> for RuntimeExecutor<R>
+where
+    R: Spawn,
+    F: Future<Output = ()> + Send + 'static,
+{
+    fn execute(&self, future: F) {
+        let _ = self.0.spawn(future);
+    }
+}
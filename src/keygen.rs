@@ -1,10 +1,45 @@
-use anyhow::Result;
+use crate::error::{Error, Result};
+use crate::secret::Secret;
 use rust_i18n::t;
+use safelog::DisplayRedacted;
+use serde::Serialize;
+use std::fmt;
+use std::path::Path;
+use tor_hscrypto::pk::{HsId, HsIdKey, HsIdKeypair};
+use tor_llcrypto::pk::ed25519;
 use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+/// Prefix shared by all x25519 client authorization strings.
+const DESCRIPTOR_X25519: &str = "descriptor:x25519:";
+
+/// Extension of C-tor client authorization files.
+const AUTH_PRIVATE_EXT: &str = "auth_private";
+
+/// Length of a base32-encoded x25519 key.
+const KEY_B32_LEN: usize = 52;
+
+/// Minimum length of a seed for [`derive_keys`], in bytes.
+const MIN_SEED_LEN: usize = 16;
+
+/// Argon2id salt of derived client authorization keys, so they differ from the
+/// service identity derived from the same input.
+const CLIENT_AUTH_SALT: &[u8] = b"onionize client auth x25519";
+
+/// Argon2id salt of derived service identities.
+const SERVICE_ID_SALT: &[u8] = b"onionize service id ed25519";
+
+/// Header of a C-tor `hs_ed25519_secret_key` file.
+const CTOR_SECRET_KEY_HEADER: &[u8; 32] = b"== ed25519v1-secret: type0 ==\0\0\0";
+
+/// Key type of the onion service keys of the C-tor control protocol (`ADD_ONION`).
+const CTOR_KEY_TYPE: &str = "ED25519-V3";
 
 /// A container for generated Tor authorization keys.
 ///
 /// Holds the keys in various formats required for server and client configuration.
+/// The client string is a [`Secret`]: wiped on drop and redacted in `Debug`.
+#[derive(Debug, Clone)]
 pub struct TorKeys {
     /// The formatted string for the server-side configuration (public key).
     /// Format: `descriptor:x25519:<PUBLIC_KEY_BASE32>`
@@ -12,7 +47,7 @@ pub struct TorKeys {
 
     /// The formatted string for the client-side configuration (private key).
     /// Format: `<PUBLIC_KEY_BASE32>:descriptor:x25519:<PRIVATE_KEY_BASE32>`
-    pub client_string: String,
+    pub client_string: Secret<String>,
 
     /// The raw public key encoded in Base32 (RFC 4648 no padding).
     pub public_b32: String,
@@ -24,52 +59,240 @@ pub struct TorKeys {
 /// them in the format expected by Tor configuration files.
 pub fn generate_keys() -> TorKeys {
     // Generate a new x25519 static secret
-    let secret = StaticSecret::random();
+    keys_from_secret(StaticSecret::random())
+}
+
+/// Derives an x25519 keypair for Client Authorization from `input`, a seed or a passphrase.
+///
+/// The same input and `params` always give the same keys, so test environments
+/// can rely on known keys without storing secrets. The input goes through Argon2id.
+///
+/// # Errors
+///
+/// Returns [`Error::InvalidKdfParams`] if `params` are out of the range Argon2 accepts.
+pub fn derive_keys(input: &[u8], params: &KdfParams) -> Result<TorKeys> {
+    let secret = derive_secret(input, CLIENT_AUTH_SALT, params)?;
+    Ok(keys_from_secret(StaticSecret::from(*secret)))
+}
 
+/// Formats the keys of `secret`.
+fn keys_from_secret(secret: StaticSecret) -> TorKeys {
     // Derive the public key from the secret
     let public = PublicKey::from(&secret);
 
-    // (RFC 4648, no padding)
-    let secret_b32 = base32::encode(
-        base32::Alphabet::Rfc4648 { padding: false },
-        secret.to_bytes().as_slice(),
-    )
-    .to_lowercase();
-
-    let public_b32 = base32::encode(
-        base32::Alphabet::Rfc4648 { padding: false },
-        public.as_bytes(),
-    )
-    .to_lowercase();
+    let secret_b32 = Zeroizing::new(encode_key_b32(&Zeroizing::new(secret.to_bytes())));
+    let public_b32 = encode_key_b32(public.as_bytes());
 
     TorKeys {
         server_string: format!("descriptor:x25519:{}", public_b32),
-        client_string: format!("{}:descriptor:x25519:{}", public_b32, secret_b32),
+        client_string: Secret::new(format!("{}:descriptor:x25519:{}", public_b32, *secret_b32)),
         public_b32,
     }
 }
 
+/// Cost of the Argon2id derivation of [`derive_keys`] and [`derive_service_identity`].
+///
+/// Changing any parameter changes the derived keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    /// Memory used, in KiB.
+    pub memory_kib: u32,
+    /// Number of passes over the memory.
+    pub iterations: u32,
+    /// Number of lanes.
+    pub parallelism: u32,
+}
+
+impl Default for KdfParams {
+    /// The OWASP recommendation for Argon2id: 19 MiB, 2 iterations, 1 lane.
+    fn default() -> Self {
+        Self {
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+/// Runs Argon2id over `input` with `salt`, which separates the kinds of keys.
+fn derive_secret(input: &[u8], salt: &[u8], params: &KdfParams) -> Result<Zeroizing<[u8; 32]>> {
+    let invalid = |e: argon2::Error| Error::InvalidKdfParams(e.to_string());
+    let argon2_params = argon2::Params::new(
+        params.memory_kib,
+        params.iterations,
+        params.parallelism,
+        Some(32),
+    )
+    .map_err(invalid)?;
+    let argon2 = argon2::Argon2::new(
+        argon2::Algorithm::Argon2id,
+        argon2::Version::V0x13,
+        argon2_params,
+    );
+
+    let mut secret = Zeroizing::new([0u8; 32]);
+    argon2
+        .hash_password_into(input, salt, secret.as_mut())
+        .map_err(invalid)?;
+    Ok(secret)
+}
+
+/// Parses a hexadecimal seed for [`derive_keys`].
+///
+/// # Errors
+///
+/// Returns [`Error::InvalidSeed`] if `hex_seed` is not hexadecimal or shorter than 16 bytes.
+pub fn parse_seed(hex_seed: &str) -> Result<Secret<Vec<u8>>> {
+    hex::decode(hex_seed.trim())
+        .ok()
+        .map(Secret::new)
+        .filter(|seed| seed.len() >= MIN_SEED_LEN)
+        .ok_or(Error::InvalidSeed)
+}
+
+/// The ed25519 identity of an onion service, which determines its address.
+pub struct ServiceIdentity {
+    /// The onion address of the service, including the `.onion` suffix.
+    pub onion_address: String,
+    keypair: ed25519::ExpandedKeypair,
+}
+
+impl fmt::Debug for ServiceIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServiceIdentity")
+            .field("onion_address", &self.onion_address)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ServiceIdentity {
+    fn new(seed: [u8; 32]) -> Self {
+        Self::from_keypair(ed25519::ExpandedKeypair::from(
+            &ed25519::Keypair::from_bytes(&seed),
+        ))
+    }
+
+    fn from_keypair(keypair: ed25519::ExpandedKeypair) -> Self {
+        let onion_address = HsId::from(HsIdKey::from(*keypair.public()))
+            .display_unredacted()
+            .to_string();
+        Self {
+            onion_address,
+            keypair,
+        }
+    }
+
+    /// Writes the secret key in the format of C-tor's `hs_ed25519_secret_key` file,
+    /// readable only by the current user.
+    ///
+    /// C-tor uses it from a `HiddenServiceDir`, and `arti hss ctor-migrate` imports it.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::WriteKeyFile`] if the file cannot be written.
+    pub fn write_ctor_secret_key(&self, file: &Path) -> Result<()> {
+        let write_error = |source| Error::WriteKeyFile {
+            file: file.to_path_buf(),
+            source,
+        };
+        let mut content = Zeroizing::new(Vec::with_capacity(96));
+        content.extend_from_slice(CTOR_SECRET_KEY_HEADER);
+        content.extend_from_slice(Zeroizing::new(self.keypair.to_secret_key_bytes()).as_slice());
+        std::fs::write(file, content.as_slice()).map_err(write_error)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(file, std::fs::Permissions::from_mode(0o600))
+                .map_err(write_error)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the secret key as `ADD_ONION` prints it: `ED25519-V3:` followed by
+    /// the base64-encoded expanded secret key.
+    pub fn ctor_private_key(&self) -> Secret<String> {
+        use base64::Engine;
+
+        let bytes = Zeroizing::new(self.keypair.to_secret_key_bytes());
+        let mut key = format!("{CTOR_KEY_TYPE}:");
+        base64::engine::general_purpose::STANDARD.encode_string(bytes.as_slice(), &mut key);
+        Secret::new(key)
+    }
+
+    /// Parses a secret key in the format of [`ServiceIdentity::ctor_private_key`].
+    ///
+    /// Returns `None` if `key` is not an `ED25519-V3` key of 64 bytes.
+    pub fn from_ctor_private_key(key: &str) -> Option<Self> {
+        use base64::Engine;
+
+        let (key_type, blob) = key.split_once(':')?;
+        if !key_type.eq_ignore_ascii_case(CTOR_KEY_TYPE) {
+            return None;
+        }
+        let decoded = Zeroizing::new(
+            base64::engine::general_purpose::STANDARD
+                .decode(blob)
+                .ok()?,
+        );
+        let mut bytes = Zeroizing::new([0u8; 64]);
+        if decoded.len() != bytes.len() {
+            return None;
+        }
+        bytes.copy_from_slice(&decoded);
+        let keypair = ed25519::ExpandedKeypair::from_secret_key_bytes(*bytes)?;
+        Some(Self::from_keypair(keypair))
+    }
+
+    /// Converts the identity into the keypair Arti launches the service with.
+    pub fn into_hsid_keypair(self) -> HsIdKeypair {
+        HsIdKeypair::from(self.keypair)
+    }
+}
+
+/// Generates a new random service identity.
+pub fn generate_service_identity() -> ServiceIdentity {
+    ServiceIdentity::new(rand::random())
+}
+
+/// Derives a service identity from `input` like [`derive_keys`]; the same input
+/// gives the same onion address.
+///
+/// # Errors
+///
+/// Returns [`Error::InvalidKdfParams`] if `params` are out of the range Argon2 accepts.
+pub fn derive_service_identity(input: &[u8], params: &KdfParams) -> Result<ServiceIdentity> {
+    Ok(ServiceIdentity::new(*derive_secret(
+        input,
+        SERVICE_ID_SALT,
+        params,
+    )?))
+}
+
 /// Generates a keypair and prints the formatted strings to stdout.
 ///
 /// Used by the CLI command `--keygen`.
 pub fn print_new_keypair() -> Result<()> {
-    let keys = generate_keys();
+    print_keypair(&generate_keys());
+    Ok(())
+}
 
+/// Prints the formatted strings of `keys` to stdout, as `--keygen` does.
+pub fn print_keypair(keys: &TorKeys) {
     println!("{}", t!("keygen.beginning"));
 
     println!("{}", t!("keygen.public_text"));
     println!(
         "{}",
-        t!(
-            "keygen.public_format_text",
-            server_string = keys.server_string
-        )
+        t!("keygen.public_format_text", server_str = keys.server_string)
     );
 
     println!("{}", t!("keygen.private_text"));
     println!(
         "{}",
-        t!("keygen.secret_string", secret_str = keys.client_string)
+        t!(
+            "keygen.secret_string",
+            secret_str = keys.client_string.as_str()
+        )
     );
     println!(
         "{}",
@@ -78,6 +301,260 @@ pub fn print_new_keypair() -> Result<()> {
             secret_b32 = keys.client_string.split(':').next_back().unwrap()
         )
     );
+}
 
-    Ok(())
+/// A client authorization credential read from a `.auth_private` file.
+#[derive(Debug, Clone)]
+pub struct ClientAuthEntry {
+    /// The onion address of the service, including the `.onion` suffix.
+    pub onion_address: String,
+
+    /// The raw x25519 secret key of the client.
+    pub secret: Secret<[u8; 32]>,
+}
+
+/// Decodes a base32-encoded (RFC 4648, no padding, any case) x25519 key.
+///
+/// # Errors
+///
+/// Returns an error if the string is not valid base32 or does not encode 32 bytes.
+pub fn decode_key_b32(encoded: &str) -> Result<[u8; 32]> {
+    base32::decode(
+        base32::Alphabet::Rfc4648 { padding: false },
+        &encoded.trim().to_uppercase(),
+    )
+    .map(Zeroizing::new)
+    .and_then(|bytes| <[u8; 32]>::try_from(bytes.as_slice()).ok())
+    .ok_or(Error::InvalidKey)
+}
+
+/// Encodes an x25519 key in lowercase base32 (RFC 4648, no padding), as Tor expects.
+pub fn encode_key_b32(key: &[u8; 32]) -> String {
+    base32::encode(base32::Alphabet::Rfc4648 { padding: false }, key).to_lowercase()
+}
+
+/// Decodes a base32 x25519 key like [`decode_key_b32`], explaining what is wrong
+/// with an invalid one.
+///
+/// # Errors
+///
+/// * [`Error::KeyCharacter`] for the first character outside of the base32 alphabet.
+/// * [`Error::KeyLength`] if the key does not have 52 characters.
+pub fn check_key_b32(encoded: &str) -> Result<[u8; 32]> {
+    let encoded = encoded.trim();
+    if let Some((index, character)) = encoded
+        .chars()
+        .enumerate()
+        .find(|(_, c)| !c.is_ascii_alphabetic() && !('2'..='7').contains(c))
+    {
+        return Err(Error::KeyCharacter {
+            character,
+            position: index + 1,
+        });
+    }
+    if encoded.len() != KEY_B32_LEN {
+        return Err(Error::KeyLength(encoded.len()));
+    }
+    decode_key_b32(encoded)
+}
+
+/// Returns the public key of an x25519 `secret`.
+pub fn public_key_of(secret: [u8; 32]) -> [u8; 32] {
+    PublicKey::from(&StaticSecret::from(secret)).to_bytes()
+}
+
+/// Parses a client secret key as accepted by `--client-key`.
+///
+/// Accepts the raw base32 secret, `descriptor:x25519:<secret>`, or the full
+/// client string `<prefix>:descriptor:x25519:<secret>` printed by `--keygen`.
+pub fn parse_client_secret(input: &str) -> Result<[u8; 32]> {
+    let encoded = match input.trim().rsplit_once(DESCRIPTOR_X25519) {
+        Some((_, secret)) => secret,
+        None => input,
+    };
+    decode_key_b32(encoded)
+}
+
+/// Parses a line of a C-tor `.auth_private` file.
+///
+/// Format: `<onion-address>:descriptor:x25519:<secret>`, where the onion address
+/// may be given with or without the `.onion` suffix.
+pub fn parse_auth_private(line: &str) -> Result<ClientAuthEntry> {
+    let (address, secret) = line
+        .trim()
+        .split_once(&format!(":{DESCRIPTOR_X25519}"))
+        .ok_or(Error::InvalidAuthPrivate)?;
+
+    let address = address.trim().to_ascii_lowercase();
+    let onion_address = if address.ends_with(".onion") {
+        address
+    } else {
+        format!("{address}.onion")
+    };
+
+    Ok(ClientAuthEntry {
+        onion_address,
+        secret: Secret::new(decode_key_b32(secret)?),
+    })
+}
+
+/// Formats a client authorization for `onion_address` as a line of a C-tor
+/// `.auth_private` file, as read by [`parse_auth_private`], Orbot and C-tor.
+pub fn auth_private_line(onion_address: &str, secret: &[u8; 32]) -> Secret<String> {
+    let address = onion_address.trim_end_matches(".onion");
+    let secret_b32 = Zeroizing::new(encode_key_b32(secret));
+    Secret::new(format!("{address}:{DESCRIPTOR_X25519}{}", *secret_b32))
+}
+
+/// Reads every `*.auth_private` file in `dir`.
+///
+/// Empty lines and lines starting with `#` are ignored.
+///
+/// # Errors
+///
+/// Returns an error if the directory cannot be read or a file contains an invalid entry.
+pub fn read_auth_private_dir(dir: &Path) -> Result<Vec<ClientAuthEntry>> {
+    let mut entries = Vec::new();
+
+    let read_dir_error = |source| Error::ReadAuthDir {
+        dir: dir.to_path_buf(),
+        source,
+    };
+    let read_dir = std::fs::read_dir(dir).map_err(read_dir_error)?;
+
+    for dir_entry in read_dir {
+        let path = dir_entry.map_err(read_dir_error)?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some(AUTH_PRIVATE_EXT) {
+            continue;
+        }
+
+        let file_error = |source| Error::ReadAuthFile {
+            file: path.clone(),
+            source: Box::new(source),
+        };
+        let content = std::fs::read_to_string(&path).map_err(|e| file_error(e.into()))?;
+
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let entry = parse_auth_private(line).map_err(file_error)?;
+            entries.push(entry);
+        }
+    }
+
+    Ok(entries)
+}
+
+/// What a key string checked by [`inspect_key`] contains.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyKind {
+    /// `descriptor:x25519:<public>`, as passed to `--auth`.
+    PublicKey,
+    /// A raw base32 secret, as printed by `--keygen`.
+    SecretKey,
+    /// `<public>:descriptor:x25519:<secret>`, as printed by `--keygen`.
+    ClientString,
+    /// `<onion-address>:descriptor:x25519:<secret>`, a line of a `.auth_private` file.
+    AuthPrivate,
+}
+
+/// A valid key string, as returned by [`inspect_key`]. Never contains the secret.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct KeyInfo {
+    /// The format of the string.
+    pub kind: KeyKind,
+    /// The public key, given or derived from the secret, as `descriptor:x25519:<key>`.
+    pub public_key: String,
+    /// Whether the string contains a secret key.
+    pub has_secret: bool,
+    /// The onion address of a `.auth_private` line.
+    pub onion_address: Option<String>,
+    /// The public key written in front of the secret of a client string, if the
+    /// secret does not correspond to it.
+    pub mismatched_public_key: Option<String>,
+}
+
+impl KeyInfo {
+    /// Returns `true` if this key is or corresponds to `public_key`, given as
+    /// `descriptor:x25519:<key>` or raw base32.
+    pub fn matches(&self, public_key: &str) -> bool {
+        let expected = public_key.trim();
+        let expected = expected.strip_prefix(DESCRIPTOR_X25519).unwrap_or(expected);
+        self.public_key[DESCRIPTOR_X25519.len()..].eq_ignore_ascii_case(expected)
+    }
+}
+
+/// Validates a client authorization key string and derives its public key.
+///
+/// Accepts `descriptor:x25519:<public>` (a public key), a raw base32 secret, the
+/// client string `<public>:descriptor:x25519:<secret>` printed by `--keygen` and a
+/// `.auth_private` line `<onion-address>:descriptor:x25519:<secret>`. For a client
+/// string, the public key in front is checked against the secret.
+///
+/// # Errors
+///
+/// * [`Error::KeyCharacter`] or [`Error::KeyLength`] for a malformed key.
+/// * [`Error::InvalidOnionAddress`] for a malformed onion address.
+/// * [`Error::InvalidAuthPrivate`] if the part in front of the secret is neither.
+pub fn inspect_key(input: &str) -> Result<KeyInfo> {
+    let input = input.trim();
+    let Some((prefix, key)) = input.rsplit_once(DESCRIPTOR_X25519) else {
+        let secret = check_key_b32(input)?;
+        return Ok(KeyInfo {
+            kind: KeyKind::SecretKey,
+            public_key: format!(
+                "{DESCRIPTOR_X25519}{}",
+                encode_key_b32(&public_key_of(secret))
+            ),
+            has_secret: true,
+            onion_address: None,
+            mismatched_public_key: None,
+        });
+    };
+
+    let key = check_key_b32(key)?;
+    if prefix.is_empty() {
+        return Ok(KeyInfo {
+            kind: KeyKind::PublicKey,
+            public_key: format!("{DESCRIPTOR_X25519}{}", encode_key_b32(&key)),
+            has_secret: false,
+            onion_address: None,
+            mismatched_public_key: None,
+        });
+    }
+
+    let public = public_key_of(key);
+    let public_key = format!("{DESCRIPTOR_X25519}{}", encode_key_b32(&public));
+    let prefix = prefix
+        .strip_suffix(':')
+        .ok_or(Error::InvalidAuthPrivate)?
+        .trim();
+
+    if prefix.len() == KEY_B32_LEN {
+        let written = check_key_b32(prefix)?;
+        return Ok(KeyInfo {
+            kind: KeyKind::ClientString,
+            public_key,
+            has_secret: true,
+            onion_address: None,
+            mismatched_public_key: (written != public)
+                .then(|| format!("{DESCRIPTOR_X25519}{}", encode_key_b32(&written))),
+        });
+    }
+
+    let entry = parse_auth_private(input)?;
+    entry
+        .onion_address
+        .parse::<HsId>()
+        .map_err(|_| Error::InvalidOnionAddress(entry.onion_address.clone()))?;
+    Ok(KeyInfo {
+        kind: KeyKind::AuthPrivate,
+        public_key,
+        has_secret: true,
+        onion_address: Some(entry.onion_address),
+        mismatched_public_key: None,
+    })
 }
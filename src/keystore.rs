@@ -0,0 +1,395 @@
+// src/keystore.rs
+use crate::error::{Error, Result};
+use crate::secret::Secret;
+use crate::utils::write_private_file;
+use age::secrecy::SecretString;
+use rust_i18n::t;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::iter;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+use zeroize::Zeroizing;
+
+/// Environment variable holding the passphrase, read by [`read_passphrase`]
+/// instead of asking on the terminal.
+pub const PASSPHRASE_ENV: &str = "ONIONIZE_PASSPHRASE";
+
+/// Environment variable holding the new passphrase of `keys rekey`.
+pub const NEW_PASSPHRASE_ENV: &str = "ONIONIZE_NEW_PASSPHRASE";
+
+/// Name of the encrypted state in the data directory, see [`default_file`].
+pub const STATE_FILE: &str = "state.age";
+
+/// Subdirectory of an Arti state directory holding the keys.
+const KEYSTORE_DIR: &str = "keystore";
+
+/// Prefix of the decrypted state directories, followed by the id of the process.
+const STATE_DIR_PREFIX: &str = "onionize-state-";
+
+/// Returns where `--encrypt-state` keeps the encrypted Tor state.
+///
+/// # Errors
+///
+/// Returns [`Error::ProjectDirs`] if the home directory of the user cannot be found.
+pub fn default_file() -> Result<PathBuf> {
+    Ok(crate::tor::project_dirs()?.data_dir().join(STATE_FILE))
+}
+
+/// A Tor state directory, including the keys of onion services and clients, kept
+/// encrypted with a passphrase at rest.
+///
+/// [`EncryptedKeystore::open`] decrypts the file into a private directory, on a
+/// tmpfs such as `$XDG_RUNTIME_DIR` when there is one, to be used as the state
+/// directory of Arti (see [`crate::tor::storage_config`]). Dropping the keystore
+/// saves it and deletes the decrypted directory; call [`EncryptedKeystore::save`]
+/// to handle errors, e.g. right after new keys were created. Directories left by
+/// a killed process are deleted by the next [`EncryptedKeystore::open`].
+///
+/// The file is a tar archive encrypted in the age format with a scrypt passphrase,
+/// so `age -d` can also decrypt it.
+pub struct EncryptedKeystore {
+    file: PathBuf,
+    // Held until the keystore is dropped, after the last save
+    _lock: fs::File,
+    state_dir: PathBuf,
+    passphrase: Secret<String>,
+    work_factor: Option<u8>,
+    is_new: bool,
+}
+
+impl EncryptedKeystore {
+    /// Decrypts `file` with `passphrase`, or starts an empty state if `file` does
+    /// not exist yet; it is created by the first save.
+    ///
+    /// Without a runtime directory, the decrypted state is written to the
+    /// temporary directory, which may be on disk, and a warning is logged.
+    ///
+    /// The file is locked until the keystore is dropped, so another process cannot
+    /// overwrite it, e.g. with the old passphrase after a rekey.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::KeystoreLocked`] if another process has the file open,
+    /// [`Error::KeystorePassphrase`] if the passphrase is wrong, and
+    /// [`Error::Keystore`] if the file cannot be read or decrypted.
+    pub fn open(file: impl Into<PathBuf>, passphrase: Secret<String>) -> Result<Self> {
+        let file = file.into();
+        let lock = lock_file(&file)?;
+        let root = work_root();
+        remove_stale_states(&root);
+        let state_dir = root.join(format!(
+            "{STATE_DIR_PREFIX}{}-{:016x}",
+            std::process::id(),
+            rand::random::<u64>()
+        ));
+        create_private_dir(&state_dir).map_err(file_error(&state_dir))?;
+
+        let is_new = !file.exists();
+        if !is_new && let Err(e) = decrypt_into(&file, &passphrase, &state_dir) {
+            let _ = fs::remove_dir_all(&state_dir);
+            return Err(e);
+        }
+
+        Ok(Self {
+            file,
+            _lock: lock,
+            state_dir,
+            passphrase,
+            work_factor: None,
+            is_new,
+        })
+    }
+
+    /// Returns the encrypted file.
+    pub fn file(&self) -> &Path {
+        &self.file
+    }
+
+    /// Returns the decrypted state directory, to be used by Arti.
+    pub fn state_dir(&self) -> &Path {
+        &self.state_dir
+    }
+
+    /// Returns `true` if the encrypted file did not exist when opened.
+    pub fn is_new(&self) -> bool {
+        self.is_new
+    }
+
+    /// Sets the scrypt work factor of the next saves to `N = 2^log_n`.
+    ///
+    /// By default, it is picked so that decrypting takes about a second on this
+    /// machine; lower values are only meant for tests.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `log_n` is 0 or 64 and above.
+    pub fn set_work_factor(&mut self, log_n: u8) {
+        assert!(0 < log_n && log_n < 64);
+        self.work_factor = Some(log_n);
+    }
+
+    /// Moves the keys of a plain Arti state directory, such as the one used before
+    /// the state was encrypted. Returns `false` if `state_dir` holds no keys.
+    ///
+    /// The keys are copied and saved, then deleted from `state_dir`, since a plain
+    /// copy would defeat the encryption. If they cannot be deleted, a warning tells
+    /// where they stayed.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Keystore`] if a key cannot be copied or the state cannot be saved.
+    pub fn import_keys(&self, state_dir: &Path) -> Result<bool> {
+        let keys = state_dir.join(KEYSTORE_DIR);
+        if !keys.is_dir() {
+            return Ok(false);
+        }
+        copy_dir(&keys, &self.state_dir.join(KEYSTORE_DIR))?;
+        self.save()?;
+        if let Err(e) = fs::remove_dir_all(&keys) {
+            warn!(
+                "{}",
+                t!("keystore.plain_kept", dir = keys.display(), error = e)
+            );
+        }
+        Ok(true)
+    }
+
+    /// Encrypts the state directory into the file, which is replaced atomically.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Keystore`] if the state cannot be archived or written.
+    pub fn save(&self) -> Result<()> {
+        let error = file_error(&self.file);
+
+        let mut archive = Zeroizing::new(Vec::new());
+        {
+            let mut builder = tar::Builder::new(&mut *archive);
+            builder
+                .append_dir_all(".", &self.state_dir)
+                .map_err(file_error(&self.state_dir))?;
+            builder.finish().map_err(file_error(&self.state_dir))?;
+        }
+
+        let mut recipient =
+            age::scrypt::Recipient::new(SecretString::from(self.passphrase.as_str().to_owned()));
+        if let Some(log_n) = self.work_factor {
+            recipient.set_work_factor(log_n);
+        }
+        let encryptor = age::Encryptor::with_recipients(iter::once(&recipient as _))
+            .map_err(|e| error(io::Error::other(e)))?;
+        let mut encrypted = Vec::new();
+        let mut writer = encryptor.wrap_output(&mut encrypted).map_err(&error)?;
+        writer.write_all(&archive).map_err(&error)?;
+        writer.finish().map_err(&error)?;
+
+        // Written aside and renamed, so a crash never leaves a truncated file
+        if let Some(parent) = self.file.parent() {
+            fs::create_dir_all(parent).map_err(&error)?;
+        }
+        let temp_file = self.file.with_extension("age.tmp");
+        write_private_file(&temp_file, &encrypted).map_err(&error)?;
+        fs::rename(&temp_file, &self.file).map_err(&error)
+    }
+
+    /// Changes the passphrase and saves the file with it.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Keystore`] if the file cannot be written.
+    pub fn rekey(&mut self, passphrase: Secret<String>) -> Result<()> {
+        self.passphrase = passphrase;
+        self.save()
+    }
+}
+
+impl Drop for EncryptedKeystore {
+    fn drop(&mut self) {
+        if let Err(e) = self.save() {
+            warn!("{}", t!("keystore.save_failed", error = e));
+        }
+        let _ = fs::remove_dir_all(&self.state_dir);
+    }
+}
+
+/// Reads a passphrase from the environment variable `env_var`, or asks for it on
+/// the terminal. A `new` passphrase is asked twice.
+///
+/// # Errors
+///
+/// Returns [`Error::ReadPassphrase`] if there is no terminal, [`Error::PassphraseMismatch`]
+/// if the two passphrases differ and [`Error::EmptyPassphrase`] if it is empty.
+pub fn read_passphrase(env_var: &str, new: bool) -> Result<Secret<String>> {
+    let passphrase = match std::env::var(env_var) {
+        Ok(passphrase) => Secret::new(passphrase),
+        Err(_) => {
+            let prompt = if new {
+                t!("keystore.new_prompt")
+            } else {
+                t!("keystore.prompt")
+            };
+            let passphrase = prompt_password(&prompt)?;
+            if new && prompt_password(&t!("keystore.confirm_prompt"))? != passphrase {
+                return Err(Error::PassphraseMismatch);
+            }
+            passphrase
+        }
+    };
+
+    if passphrase.is_empty() {
+        return Err(Error::EmptyPassphrase);
+    }
+    Ok(passphrase)
+}
+
+fn prompt_password(prompt: &str) -> Result<Secret<String>> {
+    rpassword::prompt_password(prompt)
+        .map(Secret::new)
+        .map_err(Error::ReadPassphrase)
+}
+
+/// Takes an exclusive lock on `<file>.lock`, next to `file` since the file itself
+/// is replaced on every save.
+fn lock_file(file: &Path) -> Result<fs::File> {
+    let path = file.with_extension("age.lock");
+    let error = file_error(&path);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(&error)?;
+    }
+    let lock = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .map_err(&error)?;
+    match lock.try_lock() {
+        Ok(()) => Ok(lock),
+        Err(fs::TryLockError::WouldBlock) => Err(Error::KeystoreLocked(file.to_path_buf())),
+        Err(fs::TryLockError::Error(e)) => Err(error(e)),
+    }
+}
+
+fn file_error(file: &Path) -> impl Fn(io::Error) -> Error + '_ {
+    move |source| Error::Keystore {
+        file: file.to_path_buf(),
+        source,
+    }
+}
+
+/// Deletes the decrypted states in `root` whose process is no longer running,
+/// e.g. after it was killed or the machine lost power, and returns how many.
+///
+/// Directories of running processes are kept. Errors are ignored, as the states
+/// of other users cannot be deleted anyway.
+pub fn remove_stale_states(root: &Path) -> usize {
+    let Ok(entries) = fs::read_dir(root) else {
+        return 0;
+    };
+    let mut removed = 0;
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let Some(suffix) = name
+            .to_str()
+            .and_then(|name| name.strip_prefix(STATE_DIR_PREFIX))
+        else {
+            continue;
+        };
+        // Older versions did not put the process id in the name
+        let running = suffix
+            .split_once('-')
+            .and_then(|(pid, _)| pid.parse().ok())
+            .is_some_and(is_running);
+        if !running && entry.path().is_dir() && fs::remove_dir_all(entry.path()).is_ok() {
+            warn!(
+                "{}",
+                t!("keystore.stale_removed", dir = entry.path().display())
+            );
+            removed += 1;
+        }
+    }
+    removed
+}
+
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // SAFETY: signal 0 only checks that the process exists
+    match unsafe { libc::kill(pid, 0) } {
+        0 => true,
+        // The process exists but belongs to another user
+        _ => io::Error::last_os_error().raw_os_error() == Some(libc::EPERM),
+    }
+}
+
+#[cfg(not(unix))]
+fn is_running(_pid: u32) -> bool {
+    // Without a way to check, never delete the state of another process
+    true
+}
+
+/// Where decrypted states are kept: the runtime directory, usually a tmpfs, or the
+/// temporary directory with a warning, as it may be on disk.
+fn work_root() -> PathBuf {
+    match crate::tor::project_dirs()
+        .ok()
+        .and_then(|dirs| dirs.runtime_dir().map(Path::to_path_buf))
+    {
+        Some(dir) => dir,
+        None => {
+            let dir = std::env::temp_dir();
+            warn!("{}", t!("keystore.no_runtime_dir", dir = dir.display()));
+            dir
+        }
+    }
+}
+
+fn decrypt_into(file: &Path, passphrase: &Secret<String>, state_dir: &Path) -> Result<()> {
+    let error = file_error(file);
+    let encrypted = fs::read(file).map_err(&error)?;
+
+    let identity = age::scrypt::Identity::new(SecretString::from(passphrase.as_str().to_owned()));
+    let decryptor = age::Decryptor::new_buffered(encrypted.as_slice())
+        .map_err(|e| error(io::Error::new(io::ErrorKind::InvalidData, e)))?;
+    let mut reader = decryptor
+        .decrypt(iter::once(&identity as _))
+        .map_err(|e| match e {
+            age::DecryptError::DecryptionFailed | age::DecryptError::NoMatchingKeys => {
+                Error::KeystorePassphrase(file.to_path_buf())
+            }
+            e => error(io::Error::new(io::ErrorKind::InvalidData, e)),
+        })?;
+
+    let mut archive = Zeroizing::new(Vec::new());
+    reader.read_to_end(&mut archive).map_err(&error)?;
+    tar::Archive::new(archive.as_slice())
+        .unpack(state_dir)
+        .map_err(&error)
+}
+
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(dir)
+}
+
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    create_private_dir(to).map_err(file_error(to))?;
+    for entry in fs::read_dir(from).map_err(file_error(from))? {
+        let path = entry.map_err(file_error(from))?.path();
+        let target = to.join(path.file_name().unwrap_or_default());
+        if path.is_dir() {
+            copy_dir(&path, &target)?;
+        } else {
+            fs::copy(&path, &target).map_err(file_error(&path))?;
+        }
+    }
+    Ok(())
+}
@@ -1,8 +1,34 @@
 #![doc = include_str!("../README.md")]
 
 pub mod args;
+pub mod backend;
+pub mod connect;
+pub mod control;
+pub mod daemon;
+pub mod dashboard;
+pub mod error;
+pub mod events;
+pub mod hooks;
+#[cfg(feature = "axum")]
+pub mod http;
 pub mod keygen;
+pub mod keystore;
+pub mod maintenance;
+pub mod metrics;
 pub mod proxy;
+pub mod qr;
+pub mod ratelimit;
+pub mod receive;
+pub mod secret;
+pub mod selftest;
+pub mod service;
+pub mod shutdown;
+pub mod socks;
+pub mod stats;
 pub mod tor;
+pub mod torcontrol;
 pub mod utils;
 rust_i18n::i18n!("locales");
+
+pub use error::{Error, Result};
+pub use service::{Onionizer, OnionizerBuilder, ServiceHandle};
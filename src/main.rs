@@ -1,25 +1,113 @@
 // src/main.rs
-mod proxy;
-mod tor;
 use anyhow::Context;
 use anyhow::Result;
+use arti_client::{TorClient, TorClientConfig};
 use clap::{Arg, ArgAction, CommandFactory, FromArgMatches};
-use onionize::args::Args;
-use onionize::keygen;
-use qrcode::QrCode;
-use qrcode::render::unicode;
-use safelog::DisplayRedacted;
+use onionize::args::{Args, Command, ConnectArgs, ControlPortArgs, CtlArgs, KeysAction, KeysArgs};
+use onionize::control::{ControlledService, Controller};
+use onionize::daemon::{self, Failure, Notifier, PidFile};
+use onionize::events::{self, Event, EventBus};
+use onionize::hooks::{Hooks, LogHook};
+use onionize::keygen::KeyKind;
+use onionize::keystore::{self, EncryptedKeystore};
+use onionize::maintenance::MaintenancePage;
+use onionize::proxy::{PortMap, ProxyOptions};
+use onionize::qr;
+use onionize::ratelimit::RateLimiter;
+use onionize::receive::{self, ReceiveConfig};
+use onionize::secret::Secret;
+use onionize::shutdown::ShutdownHandle;
+use onionize::stats::TrafficStats;
+use onionize::tor::{RestrictedClient, TorStatus};
+use onionize::torcontrol::{ControlPassword, ControlPortAuth, OnionRegistry};
+use onionize::{
+    connect, control, dashboard, keygen, metrics, proxy, selftest, socks, tor, torcontrol,
+};
+use safelog::{DisplayRedacted, Sensitive};
+use std::io::IsTerminal;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::signal;
+use tor_hsservice::RunningOnionService;
 use tor_rtcompat::PreferredRuntime;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
+use zeroize::Zeroizing;
 
 use rust_i18n::t;
 rust_i18n::i18n!("./locales");
 
-#[tokio::main]
-async fn main() -> Result<()> {
+/// Virtual port used by the self-test (every port is forwarded to the backend).
+const SELF_TEST_PORT: u16 = 80;
+
+fn main() -> ExitCode {
     onionize::utils::setup_locale();
 
+    let args = parse_args();
+
+    if let Err(e) = init_logging(&args) {
+        eprintln!("Error: {e:?}");
+        return ExitCode::from(daemon::EXIT_CONFIG);
+    }
+    // Kept until exit: secrets only show up in the logs with --log-secrets
+    let _log_secrets = match args
+        .log_secrets
+        .then(safelog::disable_safe_logging)
+        .transpose()
+    {
+        Ok(guard) => guard,
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            return ExitCode::from(daemon::EXIT_CONFIG);
+        }
+    };
+
+    if args.daemon {
+        if args.log_file.is_none() && !args.journald {
+            warn!("{}", t!("daemon.no_log"));
+        }
+        // Fork before the runtime starts its threads
+        if let Err(e) = daemon::detach() {
+            error!("{:#}", e);
+            return ExitCode::from(daemon::EXIT_FAILURE);
+        }
+    }
+
+    let pid_file = match args.pid_file.as_deref().map(PidFile::create).transpose() {
+        Ok(pid_file) => pid_file,
+        Err(e) => {
+            error!("{:#}", e);
+            daemon::report_to_parent(daemon::EXIT_CONFIG);
+            return ExitCode::from(daemon::EXIT_CONFIG);
+        }
+    };
+
+    let logs_elsewhere = args.log_file.is_some() || args.journald;
+    let result = tokio::runtime::Runtime::new()
+        .context(t!("daemon.errors.runtime"))
+        .and_then(|runtime| runtime.block_on(run(args)));
+    drop(pid_file);
+
+    let code = match result {
+        Ok(()) => 0,
+        Err(e) => {
+            if logs_elsewhere {
+                error!("{:#}", e);
+            } else {
+                eprintln!("Error: {e:?}");
+            }
+            daemon::exit_code(&e)
+        }
+    };
+    // With --daemon, the parent exits with the code of a run that ended before it was ready
+    daemon::report_to_parent(code);
+    ExitCode::from(code)
+}
+
+/// Parses the command line, with localized help. Exits on invalid arguments.
+fn parse_args() -> Args {
     let mut command: clap::Command = Args::command();
 
     command = command.about(t!("cli.about"));
@@ -36,6 +124,8 @@ async fn main() -> Result<()> {
     command = command
         //.mut_arg("version", |arg| arg.help(t!("cli.version")))
         .help_template(help_template)
+        // The global --version flag needs a version on every subcommand
+        .propagate_version(true)
         .disable_help_flag(true)
         .disable_version_flag(true)
         .arg(
@@ -57,32 +147,182 @@ async fn main() -> Result<()> {
         //.mut_arg("help", |arg| arg.help(t!("cli.help_info")))
         .mut_arg("port", |arg| arg.help(t!("cli.port_help")))
         .mut_arg("qr", |arg| arg.help(t!("cli.qr_help")))
+        .mut_arg("qr_file", |arg| arg.help(t!("cli.qr_file_help")))
         .mut_arg("auth", |arg| arg.help(t!("cli.auth_help")))
         .mut_arg("verbose", |arg| arg.help(t!("cli.verbose_help")))
         .mut_arg("host", |arg| arg.help(t!("cli.host_help")))
         .mut_arg("restricted", |arg| arg.help(t!("cli.restricted_help")))
-        .mut_arg("nickname", |arg| arg.help(t!("cli.nickname_help")));
+        .mut_arg("nickname", |arg| arg.help(t!("cli.nickname_help")))
+        .mut_arg("socks", |arg| arg.help(t!("cli.socks_help")))
+        .mut_arg("self_test", |arg| arg.help(t!("cli.self_test_help")))
+        .mut_arg("json", |arg| arg.help(t!("cli.json_help")))
+        .mut_arg("connect_timeout", |arg| {
+            arg.help(t!("cli.connect_timeout_help"))
+        })
+        .mut_arg("idle_timeout", |arg| arg.help(t!("cli.idle_timeout_help")))
+        .mut_arg("max_lifetime", |arg| arg.help(t!("cli.max_lifetime_help")))
+        .mut_arg("grace_period", |arg| arg.help(t!("cli.grace_period_help")))
+        .mut_arg("daemon", |arg| arg.help(t!("cli.daemon_help")))
+        .mut_arg("pid_file", |arg| arg.help(t!("cli.pid_file_help")))
+        .mut_arg("log_file", |arg| arg.help(t!("cli.log_file_help")))
+        .mut_arg("journald", |arg| arg.help(t!("cli.journald_help")))
+        .mut_arg("log_secrets", |arg| arg.help(t!("cli.log_secrets_help")))
+        .mut_arg("mlock", |arg| arg.help(t!("cli.mlock_help")))
+        .mut_arg("encrypt_state", |arg| {
+            arg.help(t!("cli.encrypt_state_help"))
+        })
+        .mut_arg("dashboard", |arg| arg.help(t!("cli.dashboard_help")))
+        .mut_arg("metrics_listen", |arg| {
+            arg.help(t!("cli.metrics_listen_help"))
+        })
+        .mut_arg("control_socket", |arg| {
+            arg.help(t!("cli.control_socket_help"))
+        })
+        .mut_arg("maintenance", |arg| arg.help(t!("cli.maintenance_help")))
+        .mut_arg("upload_limit", |arg| arg.help(t!("cli.upload_limit_help")))
+        .mut_arg("download_limit", |arg| {
+            arg.help(t!("cli.download_limit_help"))
+        })
+        .mut_arg("circuit_upload_limit", |arg| {
+            arg.help(t!("cli.circuit_upload_limit_help"))
+        })
+        .mut_arg("circuit_download_limit", |arg| {
+            arg.help(t!("cli.circuit_download_limit_help"))
+        })
+        .mut_subcommand("receive", |cmd| {
+            cmd.about(t!("cli.receive_help"))
+                .mut_arg("dir", |arg| arg.help(t!("cli.dir_help")))
+                .mut_arg("max_size", |arg| arg.help(t!("cli.max_size_help")))
+        })
+        .mut_subcommand("connect", |cmd| {
+            cmd.about(t!("cli.connect_help"))
+                .mut_arg("target", |arg| arg.help(t!("cli.target_help")))
+                .mut_arg("listen", |arg| arg.help(t!("cli.listen_help")))
+                .mut_arg("client_key", |arg| arg.help(t!("cli.client_key_help")))
+                .mut_arg("client_auth_dir", |arg| {
+                    arg.help(t!("cli.client_auth_dir_help"))
+                })
+        })
+        .mut_subcommand("ctl", |cmd| {
+            cmd.about(t!("cli.ctl_help"))
+                .mut_subcommand("list", |cmd| cmd.about(t!("cli.ctl_list_help")))
+                .mut_subcommand("stats", |cmd| cmd.about(t!("cli.ctl_stats_help")))
+                .mut_subcommand("map-port", |cmd| {
+                    cmd.about(t!("cli.ctl_map_port_help"))
+                        .mut_arg("port", |arg| arg.help(t!("cli.virtual_port_help")))
+                        .mut_arg("target", |arg| arg.help(t!("cli.map_target_help")))
+                })
+                .mut_subcommand("unmap-port", |cmd| {
+                    cmd.about(t!("cli.ctl_unmap_port_help"))
+                        .mut_arg("port", |arg| arg.help(t!("cli.virtual_port_help")))
+                })
+                .mut_subcommand("add-client", |cmd| {
+                    cmd.about(t!("cli.ctl_add_client_help"))
+                        .mut_arg("key", |arg| arg.help(t!("cli.client_pubkey_help")))
+                        .mut_arg("nickname", |arg| arg.help(t!("cli.client_nickname_help")))
+                })
+                .mut_subcommand("revoke-client", |cmd| {
+                    cmd.about(t!("cli.ctl_revoke_client_help"))
+                        .mut_arg("nickname", |arg| arg.help(t!("cli.client_nickname_help")))
+                })
+                .mut_subcommand("shutdown", |cmd| cmd.about(t!("cli.ctl_shutdown_help")))
+        })
+        .mut_subcommand("control-port", |cmd| {
+            cmd.about(t!("cli.control_port_help"))
+                .mut_arg("listen", |arg| arg.help(t!("cli.control_port_listen_help")))
+                .mut_arg("cookie_file", |arg| arg.help(t!("cli.cookie_file_help")))
+                .mut_arg("hashed_password", |arg| {
+                    arg.help(t!("cli.control_port_password_help"))
+                })
+        });
 
     let mut matches: clap::ArgMatches = command.get_matches();
 
-    let args = Args::from_arg_matches_mut(&mut matches)?;
+    Args::from_arg_matches_mut(&mut matches).unwrap_or_else(|e| e.exit())
+}
 
-    // Set up logging
-    let filter = if args.verbose {
+/// Sets up logging to stderr, to `--log-file` or to the systemd journal.
+fn init_logging(args: &Args) -> Result<()> {
+    let filter = if args.shows_dashboard() {
+        // The dashboard replaces the log output, only errors are shown
+        "error"
+    } else if args.verbose {
         "debug"
     } else {
-        "warn,arti_onion_proxy=info"
+        "warn,onionize=info"
     };
 
-    tracing_subscriber::fmt().with_env_filter(filter).init();
+    if args.journald {
+        #[cfg(unix)]
+        {
+            use tracing_subscriber::prelude::*;
+
+            let journald = tracing_journald::layer().context(t!("daemon.errors.journald"))?;
+            tracing_subscriber::registry()
+                .with(tracing_subscriber::EnvFilter::new(filter))
+                .with(journald)
+                .init();
+        }
+        #[cfg(not(unix))]
+        anyhow::bail!(t!("daemon.errors.unsupported"));
+    } else if let Some(path) = &args.log_file {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| t!("daemon.errors.log_file", path = path.display()))?;
+        tracing_subscriber::fmt()
+            .with_env_filter(filter)
+            .with_writer(std::sync::Mutex::new(file))
+            .with_ansi(false)
+            .init();
+    } else {
+        tracing_subscriber::fmt()
+            .with_env_filter(filter)
+            .with_writer(std::io::stderr)
+            .init();
+    }
+
+    Ok(())
+}
+
+/// Runs the selected mode until it finishes or the process is asked to stop.
+async fn run(args: Args) -> Result<()> {
+    events::set_json_output(args.json);
+    onionize::secret::set_memory_locking(args.mlock);
 
     debug!("{:?}", rust_i18n::available_locales!());
 
     info!("{}", t!("main.starting"));
 
+    if args.dashboard && !args.shows_dashboard() {
+        warn!("{}", t!("main.dashboard_ignored"));
+    }
+
     if args.keygen {
-        keygen::print_new_keypair()?;
-        return Ok(());
+        return run_keygen(&args);
+    }
+
+    if let Some(Command::Keys(keys_args)) = &args.command {
+        return run_keys(keys_args, args.json);
+    }
+
+    if let Some(Command::Ctl(ctl_args)) = &args.command {
+        return run_ctl(ctl_args, args.control_socket.as_deref()).await;
+    }
+
+    if let Some(file) = &args.qr_file {
+        qr::ImageFormat::from_path(file)
+            .map_err(localize)
+            .context(Failure::Config)?;
+    }
+
+    // Decrypted for this run, saved and deleted again when `run` returns
+    let keystore = open_keystore(&args)?;
+    let tor_config = tor_config(keystore.as_ref())?;
+
+    if let Some(Command::Connect(connect_args)) = &args.command {
+        return run_client(connect_args, args.socks, tor_config).await;
     }
 
     let (auth_config, generated_client_key) = if args.restricted {
@@ -94,41 +334,149 @@ async fn main() -> Result<()> {
         // Use what the user provided in --auth (or None)
         (args.auth.clone(), None)
     };
+    let restricted_clients: Vec<RestrictedClient> = auth_config
+        .as_deref()
+        .map(|key| RestrictedClient::new(key, None))
+        .transpose()
+        .map_err(localize)
+        .context(Failure::Config)?
+        .into_iter()
+        .collect();
+
+    let receive_config = match &args.command {
+        Some(Command::Receive(receive_args)) => {
+            std::fs::create_dir_all(&receive_args.dir)
+                .with_context(|| t!("receive.errors.dir", dir = receive_args.dir.display()))
+                .context(Failure::Config)?;
+            Some(ReceiveConfig {
+                dir: receive_args.dir.clone(),
+                max_size: receive_args.max_size_bytes(),
+            })
+        }
+        _ => None,
+    };
+
+    let mut hooks = Hooks::new();
+    hooks.add(LogHook);
+
+    // Load the maintenance page before bootstrapping, so a bad path fails fast
+    let proxy_options = ProxyOptions {
+        timeouts: args.stream_timeouts(),
+        maintenance: args
+            .maintenance
+            .as_deref()
+            .map(MaintenancePage::from_arg)
+            .transpose()
+            .map_err(localize)
+            .context(Failure::Config)?
+            .map(Arc::new),
+        rate_limiter: RateLimiter::new(args.global_limits()),
+        circuit_limits: args.circuit_limits(),
+        stats: TrafficStats::new(),
+        ports: PortMap::new(),
+        mapped_ports_only: false,
+        events: EventBus::new(),
+        hooks,
+    };
+
+    if let Some(Command::ControlPort(control_port_args)) = &args.command {
+        return run_control_port(&args, control_port_args, proxy_options, tor_config).await;
+    }
 
     let host = args.get_normalized_host();
     let nickname = args.get_effective_nickname();
     let target_address = format!("{}:{}", host, args.port);
 
-    info!("{}", t!("main.target_address", addr = target_address));
-    if args.host != host {
-        info!("{}", t!("main.localhost_conversion"));
+    if receive_config.is_none() {
+        info!("{}", t!("main.target_address", addr = target_address));
+        if args.host != host {
+            info!("{}", t!("main.localhost_conversion"));
+        }
     }
     info!("{}", t!("main.nickname_server", nickname = nickname));
 
     // Initialize the preferred runtime
     let runtime = PreferredRuntime::current()?;
 
-    // Run the Tor client
-    let tor_client = tor::start_tor_client(runtime.clone(), None).await?;
+    let notifier = Notifier::from_env();
+    spawn_watchdog(runtime.clone(), notifier.clone());
+    notifier.status(&t!("daemon.status.bootstrapping"));
+
+    // Start the metrics endpoint early, so the bootstrap can be monitored
+    let tor_status = TorStatus::new();
+    if let Some(metrics_addr) = args.metrics_listen {
+        spawn_metrics_server(
+            runtime.clone(),
+            metrics_addr,
+            proxy_options.stats.clone(),
+            tor_status.clone(),
+        );
+    }
+
+    // Run the Tor client, watched from the start of the bootstrap
+    let tor_client = tor::create_tor_client(runtime.clone(), tor_config)
+        .map_err(localize)
+        .context(Failure::Bootstrap)?;
+    tokio::spawn(tor::watch_bootstrap(tor_client.clone(), tor_status.clone()));
+    tor::bootstrap_tor_client(&tor_client)
+        .await
+        .map_err(localize)
+        .context(Failure::Bootstrap)?;
+
+    if let Some(socks_addr) = args.socks {
+        spawn_socks_proxy(runtime.clone(), tor_client.clone(), socks_addr);
+    }
 
     // Launch the Onion Service
     let (service, requests) =
-        tor::launch_onion_service(&tor_client, &nickname, auth_config).await?;
+        tor::launch_onion_service_with_clients(&tor_client, &nickname, &restricted_clients)
+            .await
+            .map_err(localize)
+            .context(Failure::Bootstrap)?;
+    tokio::spawn(tor::watch_service(service.clone(), tor_status.clone()));
+    if let Some(keystore) = &keystore {
+        // The keys of a new service exist now, don't wait for the exit to save them
+        keystore.save().map_err(localize)?;
+    }
 
     let o_addr = service
         .onion_address()
-        .ok_or(anyhow::anyhow!(t!("main.notgen")))?;
+        .ok_or(anyhow::anyhow!(t!("main.notgen")))
+        .context(Failure::Bootstrap)?;
 
+    let url = format!("http://{}", o_addr.display_unredacted());
+    // A restricted service is useless without the credential, which includes the address
+    let credential = match &generated_client_key {
+        Some(client_key) => {
+            let secret = Secret::new(keygen::parse_client_secret(client_key).map_err(localize)?);
+            Some(keygen::auth_private_line(
+                &o_addr.display_unredacted().to_string(),
+                &secret,
+            ))
+        }
+        None => None,
+    };
+
+    // Stdout may be collected, e.g. by a service manager, so the client key is
+    // only printed on a terminal unless asked for
+    let shows_client_key = !args.json && (args.log_secrets || std::io::stdout().is_terminal());
     if args.qr {
-        let code = QrCode::new(format!("http://{}", o_addr.display_unredacted()))
-            .context(t!("main.long"))?;
-        let image = code
-            .render::<unicode::Dense1x2>()
-            .dark_color(unicode::Dense1x2::Light)
-            .light_color(unicode::Dense1x2::Dark)
-            .build();
+        print_qr(&url, "main.qrcode")?;
+        if let Some(credential) = &credential
+            && shows_client_key
+        {
+            print_qr(credential, "main.qrcode_credential")?;
+        }
+    }
 
-        println!("{}", t!("main.qrcode", image = image));
+    if let Some(file) = &args.qr_file {
+        let data = credential
+            .as_ref()
+            .map_or(url.as_str(), |line| line.as_str());
+        qr::encode(data)
+            .and_then(|code| qr::write_file(&code, file))
+            .map_err(localize)?;
+        info!("{}", t!("main.qr_file_written", file = file.display()));
     }
 
     info!(
@@ -136,23 +484,924 @@ async fn main() -> Result<()> {
         t!("main.o_created", o_addr = o_addr.display_unredacted())
     );
 
-    if let Some(client_key) = generated_client_key {
+    // Goes to stdout only: with --json the generated key is printed nowhere else
+    events::emit(&Event::ServiceReady {
+        onion_address: o_addr.display_unredacted().to_string(),
+        credential: credential.as_ref().map(|line| line.as_str().to_string()),
+    });
+    notifier.ready(&t!(
+        "daemon.status.ready",
+        o_addr = o_addr.display_unredacted()
+    ));
+
+    if args.self_test {
+        if let Some(client_key) = &generated_client_key {
+            // Our own client needs the key to find a restricted service
+            let secret = keygen::parse_client_secret(client_key).map_err(localize)?;
+            tor::add_client_auth_key(
+                &tor_client,
+                &o_addr.display_unredacted().to_string(),
+                secret,
+            )
+            .map_err(localize)?;
+        } else if args.auth.is_some() {
+            warn!("{}", t!("selftest.auth_unsupported"));
+        }
+        spawn_self_test(tor_client.clone(), service.clone());
+    }
+
+    if let Some(client_key) = &generated_client_key {
         info!("{}", t!("main.restricted_info"));
-        info!("{}", t!("main.restricted_client", client_key = client_key));
+        info!(
+            "{}",
+            t!(
+                "main.restricted_client",
+                client_key = Sensitive::new(client_key.as_str())
+            )
+        );
+        // The log above is scrubbed, with --json the key is in the service_ready event
+        if shows_client_key {
+            println!(
+                "{}",
+                t!("main.restricted_client", client_key = client_key.as_str())
+            );
+        } else if !args.json {
+            warn!("{}", t!("main.restricted_hidden"));
+        }
+    }
+
+    if args.shows_dashboard() {
+        let mut header = vec![format!(
+            "onionize  {} → {}",
+            o_addr.display_unredacted(),
+            target_address
+        )];
+        if let Some(client_key) = &generated_client_key
+            && shows_client_key
+        {
+            header.push(t!("main.restricted_client", client_key = client_key.as_str()).to_string());
+        }
+        tokio::spawn(dashboard::run_dashboard(
+            runtime.clone(),
+            proxy_options.stats.clone(),
+            header,
+        ));
     }
 
-    info!("{}", t!("main.redirecting_to", addr = target_address));
+    let shutdown = ShutdownHandle::new();
+
+    if let Some(socket_path) = &args.control_socket {
+        let service_for_clients = service.clone();
+        let service_nickname = nickname.clone();
+        let controller = Controller::new(
+            ControlledService {
+                nickname: nickname.clone(),
+                onion_address: o_addr.display_unredacted().to_string(),
+                target: receive_config.is_none().then(|| target_address.clone()),
+                ports: proxy_options.ports.clone(),
+                restricted: !restricted_clients.is_empty(),
+                clients: restricted_clients.clone(),
+            },
+            proxy_options.stats.clone(),
+            tor_status.clone(),
+            shutdown.clone(),
+            Box::new(move |clients| {
+                tor::set_restricted_clients(&service_for_clients, &service_nickname, clients)
+            }),
+        );
+        spawn_control_server(runtime.clone(), socket_path.clone(), Arc::new(controller));
+    }
+
+    let serve = async {
+        match receive_config {
+            Some(config) => {
+                info!("{}", t!("receive.storing_in", dir = config.dir.display()));
+                receive::run_receive_loop(
+                    runtime.clone(),
+                    requests,
+                    config,
+                    shutdown.clone(),
+                    proxy_options.stats.clone(),
+                )
+                .await;
+            }
+            None => {
+                info!("{}", t!("main.redirecting_to", addr = target_address));
+                proxy::run_proxy_loop(
+                    runtime.clone(),
+                    requests,
+                    &target_address,
+                    proxy_options,
+                    shutdown.clone(),
+                )
+                .await;
+            }
+        }
+    };
+
+    // Run the serving loop until Ctrl+C, SIGTERM or a shutdown request.
+    // The loop also returns on shutdown, so the shutdown arm is checked first.
+    tokio::select! {
+        biased;
+        _ = shutdown.wait() => {}
+        _ = shutdown_signal() => {
+            info!("{}", t!("main.quit"));
+        }
+        _ = serve => {
+            error!("{}", t!("main.errors.loop_crashed"));
+            return Ok(());
+        }
+    }
+
+    // Stop accepting new streams and let the active ones finish
+    let grace = Duration::from_secs(args.grace_period);
+    drain_streams(&runtime, &shutdown, grace, &notifier).await;
+
+    if let Some(socket_path) = &args.control_socket {
+        let _ = std::fs::remove_file(socket_path);
+    }
+
+    Ok(())
+}
+
+/// Shuts down `shutdown` and waits up to `grace` for the active streams to finish.
+///
+/// A second Ctrl+C stops waiting.
+async fn drain_streams(
+    runtime: &PreferredRuntime,
+    shutdown: &ShutdownHandle,
+    grace: Duration,
+    notifier: &Notifier,
+) {
+    shutdown.shutdown();
+    notifier.stopping(&t!(
+        "daemon.status.stopping",
+        active = shutdown.active_streams()
+    ));
+    tokio::select! {
+        drained = shutdown.drain(runtime, grace) => {
+            if drained {
+                info!("{}", t!("shutdown.drained"));
+            } else {
+                warn!("{}", t!("shutdown.grace_expired", active = shutdown.active_streams()));
+            }
+        }
+        _ = signal::ctrl_c() => {
+            warn!("{}", t!("shutdown.forced"));
+        }
+    }
+}
+
+/// Pings the systemd watchdog in the background, if `WatchdogSec=` is set for this process.
+fn spawn_watchdog(runtime: PreferredRuntime, notifier: Notifier) {
+    if let Some(timeout) = daemon::watchdog_timeout() {
+        tokio::spawn(daemon::run_watchdog(runtime, notifier, timeout));
+    }
+}
+
+/// Resolves when the process receives Ctrl+C (SIGINT) or SIGTERM.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal as unix_signal};
+
+        match unix_signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = signal::ctrl_c() => {}
+                    _ = sigterm.recv() => {}
+                }
+                return;
+            }
+            Err(e) => debug!("Failed to install SIGTERM handler: {}", e),
+        }
+    }
+
+    let _ = signal::ctrl_c().await;
+}
+
+/// Runs client mode: exposes a remote onion service on a local port.
+async fn run_client(
+    connect_args: &ConnectArgs,
+    socks: Option<SocketAddr>,
+    tor_config: Option<TorClientConfig>,
+) -> Result<()> {
+    let (onion_host, onion_port) = connect::parse_onion_target(&connect_args.target)
+        .map_err(localize)
+        .context(Failure::Config)?;
+
+    // Collect client authorization keys before bootstrapping, so bad input fails fast
+    let mut client_keys = Vec::new();
+    if let Some(dir) = &connect_args.client_auth_dir {
+        let entries = keygen::read_auth_private_dir(dir)
+            .map_err(localize)
+            .context(Failure::Config)?;
+        for entry in entries {
+            client_keys.push((entry.onion_address, entry.secret));
+        }
+    }
+    if let Some(client_key) = &connect_args.client_key {
+        let secret = keygen::parse_client_secret(client_key)
+            .map_err(localize)
+            .context(Failure::Config)?;
+        client_keys.push((onion_host.clone(), Secret::new(secret)));
+    }
+
+    let runtime = PreferredRuntime::current()?;
+    let tor_client = tor::start_tor_client(runtime.clone(), tor_config)
+        .await
+        .map_err(localize)
+        .context(Failure::Bootstrap)?;
+
+    for (onion_address, secret) in client_keys {
+        tor::add_client_auth_key(&tor_client, &onion_address, *secret).map_err(localize)?;
+    }
+
+    if let Some(socks_addr) = socks {
+        spawn_socks_proxy(runtime.clone(), tor_client.clone(), socks_addr);
+    }
+
+    info!(
+        "{}",
+        t!(
+            "connect.listening",
+            local = connect_args.listen,
+            target = connect_args.target
+        )
+    );
+    // Bootstrapped: a --daemon parent may exit
+    daemon::report_to_parent(0);
+
+    let dial = move || {
+        let client = tor_client.clone();
+        let host = onion_host.clone();
+        async move { tor::connect_onion(&client, &host, onion_port).await }
+    };
 
-    // Run the proxy loop and handle Ctrl+C
     tokio::select! {
-        _ = proxy::run_proxy_loop(runtime, requests, &target_address) => {
+        res = connect::run_connect_loop(runtime, connect_args.listen, dial) => {
+            res.map_err(localize)?;
             error!("{}", t!("main.errors.loop_crashed"));
         }
         _ = signal::ctrl_c() => {
             info!("{}", t!("main.quit"));
-            // Graceful shutdown can be handled here if needed
         }
     }
 
     Ok(())
 }
+
+/// Returns the password of the control port: `--hashed-password`, or the plain
+/// password in [`torcontrol::PASSWORD_ENV`].
+fn control_port_password(control_port_args: &ControlPortArgs) -> Result<Option<ControlPassword>> {
+    let plain = std::env::var(torcontrol::PASSWORD_ENV)
+        .ok()
+        .filter(|password| !password.is_empty());
+    match (&control_port_args.hashed_password, plain) {
+        (Some(_), Some(_)) => anyhow::bail!(t!(
+            "control_port.errors.two_passwords",
+            env = torcontrol::PASSWORD_ENV
+        )),
+        (Some(hashed), None) => Ok(Some(
+            ControlPassword::from_hashed(hashed).map_err(localize)?,
+        )),
+        (None, Some(plain)) => Ok(Some(ControlPassword::new(&plain))),
+        (None, None) => Ok(None),
+    }
+}
+
+/// Runs the `control-port` subcommand: services are added and removed by
+/// Tor controller libraries through `ADD_ONION` and `DEL_ONION`.
+async fn run_control_port(
+    args: &Args,
+    control_port_args: &ControlPortArgs,
+    proxy_options: ProxyOptions,
+    tor_config: Option<TorClientConfig>,
+) -> Result<()> {
+    let cookie_file = match &control_port_args.cookie_file {
+        Some(path) => path.clone(),
+        None => directories::ProjectDirs::from("", "", "arti-onion-proxy")
+            .context(t!("tor.errors.dirs"))?
+            .data_dir()
+            .join("control_auth_cookie"),
+    };
+    let password = control_port_password(control_port_args).context(Failure::Config)?;
+    let auth = ControlPortAuth::with_new_cookie(&cookie_file, password)
+        .map_err(localize)
+        .context(Failure::Config)?;
+    info!(
+        "{}",
+        t!("control_port.cookie_written", path = cookie_file.display())
+    );
+
+    let runtime = PreferredRuntime::current()?;
+
+    let notifier = Notifier::from_env();
+    spawn_watchdog(runtime.clone(), notifier.clone());
+    notifier.status(&t!("daemon.status.bootstrapping"));
+
+    let tor_status = TorStatus::new();
+    if let Some(metrics_addr) = args.metrics_listen {
+        spawn_metrics_server(
+            runtime.clone(),
+            metrics_addr,
+            proxy_options.stats.clone(),
+            tor_status.clone(),
+        );
+    }
+
+    let tor_client = tor::create_tor_client(runtime.clone(), tor_config)
+        .map_err(localize)
+        .context(Failure::Bootstrap)?;
+    tokio::spawn(tor::watch_bootstrap(tor_client.clone(), tor_status.clone()));
+    tor::bootstrap_tor_client(&tor_client)
+        .await
+        .map_err(localize)
+        .context(Failure::Bootstrap)?;
+
+    if let Some(socks_addr) = args.socks {
+        spawn_socks_proxy(runtime.clone(), tor_client.clone(), socks_addr);
+    }
+
+    let shutdown = ShutdownHandle::new();
+    let registry = Arc::new(OnionRegistry::new(shutdown.clone()));
+
+    let launch_rt = runtime.clone();
+    let launch = move |spec, stop| {
+        let runtime = launch_rt.clone();
+        let client = tor_client.clone();
+        let options = proxy_options.clone();
+        async move { torcontrol::launch_proxied_service(runtime, client, spec, options, stop).await }
+    };
+
+    notifier.ready(&t!(
+        "control_port.listening",
+        local = control_port_args.listen
+    ));
+
+    tokio::select! {
+        res = torcontrol::run_control_port(
+            runtime.clone(),
+            control_port_args.listen,
+            Arc::new(auth),
+            registry,
+            launch,
+        ) => {
+            res.map_err(localize)?;
+            error!("{}", t!("main.errors.loop_crashed"));
+        }
+        _ = shutdown_signal() => {
+            info!("{}", t!("main.quit"));
+        }
+    }
+
+    let grace = Duration::from_secs(args.grace_period);
+    drain_streams(&runtime, &shutdown, grace, &notifier).await;
+    let _ = std::fs::remove_file(&cookie_file);
+
+    Ok(())
+}
+
+/// Runs the `ctl` subcommand: sends one request to a running service and prints the result.
+async fn run_ctl(ctl_args: &CtlArgs, socket_path: Option<&Path>) -> Result<()> {
+    let socket_path = socket_path.ok_or_else(|| anyhow::anyhow!(t!("control.errors.no_socket")))?;
+
+    let runtime = PreferredRuntime::current()?;
+    let response = control::send_request(&runtime, socket_path, &ctl_args.action.request())
+        .await
+        .map_err(localize)?;
+
+    if !response.ok {
+        anyhow::bail!(response.error.unwrap_or_default());
+    }
+    if let Some(result) = response.result {
+        println!("{}", serde_json::to_string_pretty(&result)?);
+    }
+    Ok(())
+}
+
+/// Runs `--keygen`: prints a client authorization keypair, derived from a seed or a
+/// passphrase if one is given, and optionally writes a service identity.
+fn run_keygen(args: &Args) -> Result<()> {
+    let input = if let Some(seed) = &args.from_seed {
+        Some(keygen::parse_seed(seed).map_err(localize)?)
+    } else if args.from_passphrase {
+        // Not echoed on a terminal, read as a line when piped
+        let passphrase = if std::io::stdin().is_terminal() {
+            Zeroizing::new(
+                rpassword::prompt_password(t!("keygen.passphrase_prompt"))
+                    .context(t!("keygen.errors.passphrase"))?,
+            )
+        } else {
+            let mut passphrase = Zeroizing::new(String::new());
+            std::io::stdin()
+                .read_line(&mut passphrase)
+                .context(t!("keygen.errors.passphrase"))?;
+            passphrase
+        };
+        Some(Secret::new(
+            passphrase
+                .trim_end_matches(['\r', '\n'])
+                .as_bytes()
+                .to_vec(),
+        ))
+    } else {
+        None
+    };
+    let params = args.kdf_params();
+
+    let keys = match &input {
+        Some(input) => keygen::derive_keys(input, &params).map_err(localize)?,
+        None => keygen::generate_keys(),
+    };
+    keygen::print_keypair(&keys);
+
+    if let Some(file) = &args.identity_file {
+        let identity = match &input {
+            Some(input) => keygen::derive_service_identity(input, &params).map_err(localize)?,
+            None => keygen::generate_service_identity(),
+        };
+        identity.write_ctor_secret_key(file).map_err(localize)?;
+        println!(
+            "\n{}",
+            t!(
+                "keygen.identity",
+                addr = identity.onion_address,
+                file = file.display()
+            )
+        );
+    }
+    Ok(())
+}
+
+/// Prints a QR code of `data` with the message `key`, in the most readable style
+/// that fits the terminal.
+fn print_qr(data: &str, key: &str) -> Result<()> {
+    let code = qr::encode(data).map_err(localize)?;
+    let style = match console::Term::stdout().size_checked() {
+        Some((_rows, columns)) => qr::TerminalStyle::fitting(&code, columns.into()),
+        // Not a terminal, e.g. redirected to a file: the compact style
+        None => Some(qr::TerminalStyle::Dense),
+    };
+
+    match style {
+        Some(style) => println!("{}", t!(key, image = qr::render_terminal(&code, style))),
+        None => warn!("{}", t!("main.qr_too_wide")),
+    }
+    Ok(())
+}
+
+/// With `--encrypt-state`, asks for the passphrase and decrypts the Tor state.
+///
+/// The first time, the file is created with the keys of the unencrypted state.
+fn open_keystore(args: &Args) -> Result<Option<EncryptedKeystore>> {
+    if !args.encrypt_state {
+        return Ok(None);
+    }
+    let file = keystore::default_file().map_err(localize)?;
+    let passphrase = keystore::read_passphrase(keystore::PASSPHRASE_ENV, !file.exists())
+        .map_err(localize)
+        .context(Failure::Config)?;
+    let keystore = EncryptedKeystore::open(&file, passphrase)
+        .map_err(localize)
+        .context(Failure::Config)?;
+
+    if keystore.is_new() {
+        let plain_dir = tor::project_dirs()
+            .map_err(localize)?
+            .data_dir()
+            .to_path_buf();
+        if keystore.import_keys(&plain_dir).map_err(localize)? {
+            info!(
+                "{}",
+                t!(
+                    "keystore.imported",
+                    dir = plain_dir.join("keystore").display()
+                )
+            );
+        }
+        keystore.save().map_err(localize)?;
+        info!("{}", t!("keystore.created", file = file.display()));
+    }
+    Ok(Some(keystore))
+}
+
+/// The Tor configuration using the decrypted state of `keystore`, if any.
+fn tor_config(keystore: Option<&EncryptedKeystore>) -> Result<Option<TorClientConfig>> {
+    let Some(keystore) = keystore else {
+        return Ok(None);
+    };
+    let dirs = tor::project_dirs().map_err(localize)?;
+    Ok(Some(tor::storage_config(
+        dirs.cache_dir(),
+        keystore.state_dir(),
+    )))
+}
+
+/// Runs `keys rekey`: changes the passphrase of the encrypted Tor state.
+fn run_rekey(file: Option<&Path>) -> Result<()> {
+    let file = match file {
+        Some(file) => file.to_path_buf(),
+        None => keystore::default_file().map_err(localize)?,
+    };
+    if !file.is_file() {
+        return Err(anyhow::anyhow!(t!(
+            "keystore.errors.missing",
+            file = file.display()
+        )))
+        .context(Failure::Config);
+    }
+
+    let passphrase = keystore::read_passphrase(keystore::PASSPHRASE_ENV, false)
+        .map_err(localize)
+        .context(Failure::Config)?;
+    let mut keystore = EncryptedKeystore::open(&file, passphrase)
+        .map_err(localize)
+        .context(Failure::Config)?;
+    let new_passphrase = keystore::read_passphrase(keystore::NEW_PASSPHRASE_ENV, true)
+        .map_err(localize)
+        .context(Failure::Config)?;
+    keystore.rekey(new_passphrase).map_err(localize)?;
+
+    println!("{}", t!("keystore.rekeyed", file = file.display()));
+    Ok(())
+}
+
+/// Runs the `keys` subcommand: checks every key and reports it, without printing secrets.
+fn run_keys(keys_args: &KeysArgs, json: bool) -> Result<()> {
+    let (inputs, public) = match &keys_args.action {
+        KeysAction::Inspect { inputs, public } => (inputs, public),
+        KeysAction::Rekey { file } => return run_rekey(file.as_deref()),
+    };
+
+    // Every key string with where it comes from; arguments are not echoed
+    let mut entries = Vec::new();
+    for (index, input) in inputs.iter().enumerate() {
+        let path = Path::new(input);
+        if path.is_file() {
+            let content = std::fs::read_to_string(path)
+                .with_context(|| t!("keys.errors.read", file = path.display()))
+                .context(Failure::Config)?;
+            for (line_number, line) in content.lines().enumerate() {
+                let line = line.trim();
+                if !line.is_empty() && !line.starts_with('#') {
+                    entries.push((
+                        format!("{}:{}", path.display(), line_number + 1),
+                        line.to_string(),
+                    ));
+                }
+            }
+        } else {
+            entries.push((
+                t!("keys.argument", index = index + 1).into_owned(),
+                input.clone(),
+            ));
+        }
+    }
+
+    let mut problems = 0;
+    for (source, key) in entries {
+        let (info, error) = match keygen::inspect_key(&key) {
+            Ok(info) => (Some(info), None),
+            Err(e) => (None, Some(format!("{:#}", localize(e)))),
+        };
+        let matches_public = info
+            .as_ref()
+            .filter(|info| info.has_secret)
+            .zip(public.as_deref())
+            .map(|(info, public)| info.matches(public));
+        let mismatched = info
+            .as_ref()
+            .is_some_and(|info| info.mismatched_public_key.is_some());
+        if error.is_some() || mismatched || matches_public == Some(false) {
+            problems += 1;
+        }
+
+        if json {
+            let report = serde_json::json!({
+                "source": source,
+                "key": info,
+                "matches_public": matches_public,
+                "error": error,
+            });
+            println!("{report}");
+            continue;
+        }
+
+        println!("{source}");
+        if let Some(error) = error {
+            println!("  ❌ {error}");
+            continue;
+        }
+        let Some(info) = info else { continue };
+        let kind = match info.kind {
+            KeyKind::PublicKey => t!("keys.kind.public_key"),
+            KeyKind::SecretKey => t!("keys.kind.secret_key"),
+            KeyKind::ClientString => t!("keys.kind.client_string"),
+            KeyKind::AuthPrivate => t!("keys.kind.auth_private"),
+        };
+        println!("  {}", t!("keys.format", kind = kind));
+        if let Some(address) = &info.onion_address {
+            println!("  {}", t!("keys.onion_address", addr = address));
+        }
+        println!("  {}", t!("keys.public_key", key = info.public_key));
+        if let Some(written) = &info.mismatched_public_key {
+            println!("  ❌ {}", t!("keys.mismatch_written", key = written));
+        }
+        match matches_public {
+            Some(true) => println!("  ✅ {}", t!("keys.matches_public")),
+            Some(false) => println!("  ❌ {}", t!("keys.mismatch_public")),
+            None => {}
+        }
+    }
+
+    if problems > 0 {
+        return Err(anyhow::anyhow!(t!(
+            "keys.errors.problems",
+            count = problems
+        )))
+        .context(Failure::Config);
+    }
+    Ok(())
+}
+
+/// Runs the local SOCKS proxy in the background for the lifetime of the process.
+fn spawn_socks_proxy(
+    runtime: PreferredRuntime,
+    tor_client: TorClient<PreferredRuntime>,
+    socks_addr: SocketAddr,
+) {
+    let dial = move |target| {
+        let client = tor_client.clone();
+        async move { socks::connect_via_tor(&client, target).await }
+    };
+
+    tokio::spawn(async move {
+        if let Err(e) = socks::run_socks_loop(runtime, socks_addr, dial).await {
+            error!("{:#}", localize(e));
+        }
+    });
+}
+
+/// Serves Prometheus metrics in the background.
+fn spawn_metrics_server(
+    runtime: PreferredRuntime,
+    metrics_addr: SocketAddr,
+    stats: TrafficStats,
+    tor_status: TorStatus,
+) {
+    tokio::spawn(async move {
+        if let Err(e) = metrics::run_metrics_server(runtime, metrics_addr, stats, tor_status).await
+        {
+            error!("{:#}", localize(e));
+        }
+    });
+}
+
+/// Serves the control API in the background.
+fn spawn_control_server(
+    runtime: PreferredRuntime,
+    socket_path: PathBuf,
+    controller: Arc<Controller>,
+) {
+    tokio::spawn(async move {
+        if let Err(e) = control::run_control_server(runtime, &socket_path, controller).await {
+            error!("{:#}", localize(e));
+        }
+    });
+}
+
+/// Runs the self-test in the background once the service is published.
+fn spawn_self_test(tor_client: TorClient<PreferredRuntime>, service: Arc<RunningOnionService>) {
+    tokio::spawn(async move {
+        info!("{}", t!("selftest.started"));
+        match selftest::run_self_test(&tor_client, &service, SELF_TEST_PORT).await {
+            Ok(report) => {
+                info!(
+                    "{}",
+                    t!(
+                        "selftest.success",
+                        connect_ms = report.connect_time.as_millis()
+                    )
+                );
+                match &report.response {
+                    Some(response) => info!(
+                        "{}",
+                        t!(
+                            "selftest.response",
+                            response_ms = response.time.as_millis(),
+                            status = response.status_line
+                        )
+                    ),
+                    None => info!("{}", t!("selftest.no_response")),
+                }
+                events::emit(&report.to_event());
+            }
+            Err(e) => {
+                let e = localize(e);
+                warn!("{}", t!("selftest.failed", error = format!("{e:#}")));
+                events::emit(&Event::SelfTest {
+                    success: false,
+                    connect_ms: None,
+                    response_ms: None,
+                    status_line: None,
+                    error: Some(format!("{e:#}")),
+                });
+            }
+        }
+    });
+}
+
+/// Translates a library error for the user, keeping its underlying error as the cause.
+fn localize(err: onionize::Error) -> anyhow::Error {
+    use onionize::Error as E;
+
+    let (message, source): (String, Option<anyhow::Error>) = match err {
+        E::ProjectDirs => (t!("tor.errors.dirs").into(), None),
+        E::Bootstrap(e) => (t!("tor.errors.bootstrap").into(), Some(e.into())),
+        E::InvalidNickname(nick) => (
+            format!("{}: {nick}", t!("tor.errors.invalid_nickname")),
+            None,
+        ),
+        E::InvalidClientNickname(nick) => (
+            t!("tor.errors.invalid_client_nickname", nick = nick).into(),
+            None,
+        ),
+        E::InvalidAuthKey(_) => (t!("cli.auth_error").into(), None),
+        E::InvalidKey => (t!("keygen.errors.invalid_key").into(), None),
+        E::KeyLength(len) => (t!("keygen.errors.key_length", len = len).into(), None),
+        E::KeyCharacter {
+            character,
+            position,
+        } => (
+            t!(
+                "keygen.errors.key_character",
+                character = format!("{character:?}"),
+                position = position
+            )
+            .into(),
+            None,
+        ),
+        E::InvalidAuthPrivate => (t!("keygen.errors.invalid_auth_private").into(), None),
+        E::InvalidSeed => (t!("keygen.errors.invalid_seed").into(), None),
+        E::InvalidKdfParams(reason) => {
+            (t!("keygen.errors.kdf_params", reason = reason).into(), None)
+        }
+        E::KeystorePassphrase(file) => (
+            t!("keystore.errors.passphrase", file = file.display()).into(),
+            None,
+        ),
+        E::KeystoreLocked(file) => (
+            t!("keystore.errors.locked", file = file.display()).into(),
+            None,
+        ),
+        E::Keystore { file, source } => (
+            t!("keystore.errors.file", file = file.display()).into(),
+            Some(source.into()),
+        ),
+        E::ReadPassphrase(source) => (
+            t!("keystore.errors.read_passphrase").into(),
+            Some(source.into()),
+        ),
+        E::PassphraseMismatch => (t!("keystore.errors.mismatch").into(), None),
+        E::EmptyPassphrase => (t!("keystore.errors.empty").into(), None),
+        E::QrCode(e) => (t!("main.long").into(), Some(e.into())),
+        E::QrFormat(file) => (t!("main.qr_format", file = file.display()).into(), None),
+        E::QrFile { file, source } => (
+            t!("main.qr_file", file = file.display()).into(),
+            Some(source.into()),
+        ),
+        E::WriteKeyFile { file, source } => (
+            t!("keygen.errors.write_file", file = file.display()).into(),
+            Some(source.into()),
+        ),
+        E::InvalidOnionAddress(addr) => (t!("tor.errors.invalid_onion", addr = addr).into(), None),
+        E::InvalidTarget { target, source } => (
+            t!("proxy.errors.local_address", target = target).into(),
+            Some(source.into()),
+        ),
+        E::NoTarget => (t!("service.errors.no_target").into(), None),
+        E::Runtime(e) => (t!("service.errors.runtime").into(), Some(e.into())),
+        E::ServiceConfig(e) => (t!("tor.errors.service_config").into(), Some(e.into())),
+        E::LaunchService(e) => (t!("tor.errors.launch_service").into(), e.map(Into::into)),
+        E::Reconfigure(e) => (t!("tor.errors.reconfigure").into(), Some(e.into())),
+        E::RemoveServiceKeys { nickname, source } => (
+            t!("tor.errors.remove_keys", nick = nickname).into(),
+            Some(anyhow::Error::from_boxed(source)),
+        ),
+        E::Connect { target, source } => (
+            t!("tor.errors.connect_onion", target = target).into(),
+            Some(source.into()),
+        ),
+        E::ClientKey { address, source } => (
+            t!("tor.errors.client_key", addr = address).into(),
+            Some(source.into()),
+        ),
+        E::UnmappedPort(port) => (t!("proxy.errors.unmapped_port", port = port).into(), None),
+        E::StreamRejected(port) => (t!("proxy.errors.rejected", port = port).into(), None),
+        E::BackendUnreachable { target, source } => (
+            t!("errors.service_unreachable", target = target).into(),
+            Some(source.into()),
+        ),
+        E::ConnectTimeout(target) => (
+            t!("proxy.errors.connect_timeout", target = target).into(),
+            None,
+        ),
+        E::RemoteClosed(e) => (t!("errors.remote_closed").into(), Some(e.into())),
+        E::ReadAuthDir { dir, source } => (
+            t!("keygen.errors.read_dir", dir = dir.display()).into(),
+            Some(source.into()),
+        ),
+        E::ReadAuthFile { file, source } => (
+            t!("keygen.errors.read_file", file = file.display()).into(),
+            Some(localize(*source)),
+        ),
+        E::InvalidOnionTarget(target) => (
+            t!("connect.errors.invalid_target", target = target).into(),
+            None,
+        ),
+        E::Listen { address, source } => (
+            t!("connect.errors.listen", local = address).into(),
+            Some(source.into()),
+        ),
+        E::SocksListen { address, source } => (
+            t!("socks.errors.listen", local = address).into(),
+            Some(source.into()),
+        ),
+        E::MetricsListen { address, source } => (
+            t!("metrics.errors.listen", local = address).into(),
+            Some(source.into()),
+        ),
+        E::Socks(e) => (t!("socks.errors.handshake").into(), Some(e.into())),
+        E::UnsupportedSocksCommand(command) => {
+            (t!("socks.errors.command", command = command).into(), None)
+        }
+        E::OpenStream { target, source } => (
+            t!("socks.errors.connect", target = target).into(),
+            Some(source.into()),
+        ),
+        E::PublishTimeout => (t!("selftest.errors.publish_timeout").into(), None),
+        E::SelfTestTimeout => (t!("selftest.errors.connect_timeout").into(), None),
+        E::ServiceNotRunning(problem) => {
+            (t!("selftest.errors.broken", problem = problem).into(), None)
+        }
+        E::NoResponse => (t!("selftest.errors.no_response").into(), None),
+        E::NotProxy => (t!("control.errors.not_proxy").into(), None),
+        E::PortNotMapped(port) => (
+            t!("control.errors.port_not_mapped", port = port).into(),
+            None,
+        ),
+        E::ClientExists(nick) => (t!("control.errors.client_exists", nick = nick).into(), None),
+        E::UnknownClient(nick) => (
+            t!("control.errors.unknown_client", nick = nick).into(),
+            None,
+        ),
+        E::BadControlRequest(e) => (t!("control.errors.bad_request").into(), Some(e.into())),
+        E::ControlSocket { path, source } => (
+            t!("control.errors.listen", path = path.display()).into(),
+            Some(source.into()),
+        ),
+        E::ControlConnect { path, source } => (
+            t!("control.errors.connect", path = path.display()).into(),
+            Some(source.into()),
+        ),
+        E::NotASocket(path) => (
+            t!("control.errors.not_socket", path = path.display()).into(),
+            None,
+        ),
+        E::ControlSocketInUse(path) => (
+            t!("control.errors.in_use", path = path.display()).into(),
+            None,
+        ),
+        E::NoControlResponse(e) => (t!("control.errors.no_response").into(), e.map(Into::into)),
+        E::Json(e) => (t!("control.errors.json").into(), Some(e.into())),
+        E::ControlSocketUnsupported => (t!("control.errors.unsupported").into(), None),
+        E::MalformedUpload => (t!("receive.errors.malformed").into(), None),
+        E::IncompleteRequest => (t!("receive.errors.unexpected_eof").into(), None),
+        E::CreateUpload { file, source } => (
+            t!("receive.errors.create_file", file = file.display()).into(),
+            Some(source.into()),
+        ),
+        E::WriteUpload { file, source } => (
+            t!("receive.errors.write_file", file = file).into(),
+            Some(source.into()),
+        ),
+        E::InvalidHashedPassword => (t!("control_port.errors.hashed_password").into(), None),
+        E::ControlCookie { file, source } => (
+            t!("control_port.errors.cookie", path = file.display()).into(),
+            Some(source.into()),
+        ),
+        E::MaintenancePage { file, source } => (
+            t!("maintenance.errors.read", path = file.display()).into(),
+            Some(source.into()),
+        ),
+        E::Spawn(e) => (t!("errors.spawn").into(), Some(e.into())),
+        E::Io(e) => return e.into(),
+        other => return other.into(),
+    };
+
+    match source {
+        Some(source) => source.context(message),
+        None => anyhow::anyhow!(message),
+    }
+}
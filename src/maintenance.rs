@@ -0,0 +1,102 @@
+// src/maintenance.rs
+use crate::error::{Error, Result};
+use futures::{AsyncWrite, AsyncWriteExt};
+use rust_i18n::t;
+use std::path::Path;
+
+/// A `502 Bad Gateway` response sent to visitors when the local service is down.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MaintenancePage {
+    /// Value of the `Content-Type` header.
+    pub content_type: String,
+    /// The response body.
+    pub body: Vec<u8>,
+}
+
+impl MaintenancePage {
+    /// Returns the built-in HTML page, in the current locale.
+    pub fn html() -> Self {
+        let body = format!(
+            "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{title}</title></head>\n<body>\n<h1>{title}</h1>\n<p>{message}</p>\n</body>\n</html>\n",
+            title = t!("maintenance.title"),
+            message = t!("maintenance.message"),
+        );
+        Self {
+            content_type: "text/html; charset=utf-8".to_string(),
+            body: body.into_bytes(),
+        }
+    }
+
+    /// Returns the built-in JSON body, for API backends.
+    pub fn json() -> Self {
+        let body = serde_json::json!({
+            "error": "service_unavailable",
+            "message": t!("maintenance.message"),
+        });
+        Self {
+            content_type: "application/json".to_string(),
+            body: body.to_string().into_bytes(),
+        }
+    }
+
+    /// Loads a page from `path`. The content type is guessed from the file extension.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::MaintenancePage`] if the file cannot be read.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let body = std::fs::read(path).map_err(|source| Error::MaintenancePage {
+            file: path.to_path_buf(),
+            source,
+        })?;
+
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let content_type = match extension.as_str() {
+            "html" | "htm" => "text/html; charset=utf-8",
+            "json" => "application/json",
+            _ => "text/plain; charset=utf-8",
+        };
+
+        Ok(Self {
+            content_type: content_type.to_string(),
+            body,
+        })
+    }
+
+    /// Parses the value of `--maintenance`: `html`, `json` or the path to a file.
+    ///
+    /// The keywords always select the built-in pages; a file named like one of them
+    /// is given as a path, e.g. `./html`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read.
+    pub fn from_arg(value: &str) -> Result<Self> {
+        match value {
+            "html" => Ok(Self::html()),
+            "json" => Ok(Self::json()),
+            path => Self::from_file(Path::new(path)),
+        }
+    }
+
+    /// Writes the page as a complete HTTP response to `stream` and closes it.
+    pub async fn send<S>(&self, stream: &mut S) -> Result<()>
+    where
+        S: AsyncWrite + Unpin,
+    {
+        let head = format!(
+            "HTTP/1.1 502 Bad Gateway\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+            self.content_type,
+            self.body.len()
+        );
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(&self.body).await?;
+        stream.flush().await?;
+        stream.close().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,301 @@
+// src/metrics.rs
+use crate::error::{Error, Result};
+use crate::proxy::CloseReason;
+use crate::stats::{StatsSnapshot, TrafficStats};
+use crate::tor::{SERVICE_STATES, TorStatus, TorStatusSnapshot};
+use futures::task::SpawnExt;
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, StreamExt};
+use rust_i18n::t;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use tor_rtcompat::{NetStreamListener, Runtime};
+use tracing::{debug, info, warn};
+
+/// Maximum size of a scrape request head.
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+
+/// Content type of the Prometheus text exposition format.
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Serves the metrics in Prometheus text format at `/metrics` on `local_addr`.
+///
+/// # Arguments
+///
+/// * `runtime` - The runtime used to listen locally and spawn tasks.
+/// * `local_addr` - The local address to listen on (e.g., "127.0.0.1:9464").
+/// * `stats` - Traffic counters of the service.
+/// * `tor` - Status of the Tor client and of the onion service.
+///
+/// # Errors
+///
+/// Returns an error if the local listener cannot be created.
+pub async fn run_metrics_server<R: Runtime>(
+    runtime: R,
+    local_addr: SocketAddr,
+    stats: TrafficStats,
+    tor: TorStatus,
+) -> Result<()> {
+    let listener = runtime
+        .listen(&local_addr)
+        .await
+        .map_err(|source| Error::MetricsListen {
+            address: local_addr,
+            source,
+        })?;
+
+    info!("{}", t!("metrics.listening", local = local_addr));
+
+    let mut incoming = listener.incoming();
+
+    while let Some(accepted) = incoming.next().await {
+        let (stream, peer) = match accepted {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("{}", t!("metrics.errors.accept", err = e));
+                continue;
+            }
+        };
+
+        debug!("Metrics request from {}", peer);
+
+        let stats = stats.clone();
+        let tor = tor.clone();
+        let spawn_res = runtime.spawn(async move {
+            if let Err(e) = handle_scrape(stream, &stats, &tor).await {
+                debug!("Metrics request failed: {:#}", e);
+            }
+        });
+
+        if let Err(e) = spawn_res {
+            warn!("{}: {}", t!("metrics.errors.task"), e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Answers a single HTTP request: the metrics for `GET /metrics`, 404 otherwise.
+async fn handle_scrape<S>(mut stream: S, stats: &TrafficStats, tor: &TorStatus) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 1024];
+
+    let path = loop {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+
+        let mut headers = [httparse::EMPTY_HEADER; 32];
+        let mut request = httparse::Request::new(&mut headers);
+        match request.parse(&buf) {
+            Ok(httparse::Status::Complete(_)) => {
+                break request.path.unwrap_or_default().to_string();
+            }
+            Ok(httparse::Status::Partial) if buf.len() < MAX_REQUEST_SIZE => continue,
+            Ok(httparse::Status::Partial) => return Ok(()),
+            Err(e) => {
+                debug!("Malformed metrics request: {}", e);
+                return Ok(());
+            }
+        }
+    };
+
+    let (status, content_type, body) = if path == "/metrics" || path.starts_with("/metrics?") {
+        (
+            "200 OK",
+            CONTENT_TYPE,
+            render(&stats.snapshot(), &tor.snapshot()),
+        )
+    } else {
+        (
+            "404 Not Found",
+            "text/plain; charset=utf-8",
+            "Not Found\n".to_string(),
+        )
+    };
+
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.flush().await?;
+    stream.close().await?;
+    Ok(())
+}
+
+/// Renders the metrics in Prometheus text format.
+pub fn render(stats: &StatsSnapshot, tor: &TorStatusSnapshot) -> String {
+    let mut out = String::new();
+
+    metric(
+        &mut out,
+        "onionize_circuits_active",
+        "gauge",
+        "Rendezvous circuits that are currently open.",
+        &[("", stats.circuits_active as f64)],
+    );
+    metric(
+        &mut out,
+        "onionize_circuits_total",
+        "counter",
+        "Rendezvous circuits opened since launch.",
+        &[("", stats.circuits_total as f64)],
+    );
+    metric(
+        &mut out,
+        "onionize_streams_active",
+        "gauge",
+        "Streams that are currently open.",
+        &[("", stats.streams_active as f64)],
+    );
+    metric(
+        &mut out,
+        "onionize_streams_accepted_total",
+        "counter",
+        "Streams accepted since launch.",
+        &[("", stats.streams_total as f64)],
+    );
+    metric(
+        &mut out,
+        "onionize_requests_rejected_total",
+        "counter",
+        "Rendezvous or stream requests that could not be accepted.",
+        &[("", stats.requests_rejected as f64)],
+    );
+
+    let closed: Vec<(String, f64)> = [
+        CloseReason::Completed,
+        CloseReason::IdleTimeout,
+        CloseReason::LifetimeExceeded,
+        CloseReason::ConnectTimeout,
+        CloseReason::BackendUnreachable,
+        CloseReason::Error,
+    ]
+    .iter()
+    .map(|reason| {
+        let count = stats.closed.get(reason).copied().unwrap_or_default();
+        (format!("reason=\"{reason}\""), count as f64)
+    })
+    .collect();
+    metric(
+        &mut out,
+        "onionize_streams_closed_total",
+        "counter",
+        "Closed streams by reason.",
+        &labelled(&closed),
+    );
+
+    metric(
+        &mut out,
+        "onionize_bytes_total",
+        "counter",
+        "Bytes transferred; up is from Tor clients to the local service.",
+        &[
+            ("direction=\"up\"", stats.bytes_up as f64),
+            ("direction=\"down\"", stats.bytes_down as f64),
+        ],
+    );
+
+    let connect_failures = [CloseReason::ConnectTimeout, CloseReason::BackendUnreachable]
+        .iter()
+        .filter_map(|reason| stats.closed.get(reason))
+        .sum::<u64>();
+    metric(
+        &mut out,
+        "onionize_backend_connect_failures_total",
+        "counter",
+        "Failed or timed out connections to the local service.",
+        &[("", connect_failures as f64)],
+    );
+
+    let _ = writeln!(
+        out,
+        "# HELP onionize_backend_connect_seconds Time to connect to the local service."
+    );
+    let _ = writeln!(out, "# TYPE onionize_backend_connect_seconds summary");
+    let _ = writeln!(
+        out,
+        "onionize_backend_connect_seconds_sum {}",
+        stats.backend_connect_total_ms as f64 / 1000.0
+    );
+    let _ = writeln!(
+        out,
+        "onionize_backend_connect_seconds_count {}",
+        stats.backend_connects
+    );
+
+    metric(
+        &mut out,
+        "onionize_tor_bootstrap_ratio",
+        "gauge",
+        "Bootstrap progress of the Tor client, from 0 to 1.",
+        &[("", f64::from(tor.bootstrap_fraction))],
+    );
+    metric(
+        &mut out,
+        "onionize_tor_ready",
+        "gauge",
+        "Whether the Tor client is ready for traffic.",
+        &[("", if tor.ready { 1.0 } else { 0.0 })],
+    );
+
+    let states: Vec<(String, f64)> = SERVICE_STATES
+        .iter()
+        .map(|state| {
+            let value = if tor.service_state == Some(*state) {
+                1.0
+            } else {
+                0.0
+            };
+            (format!("state=\"{state}\""), value)
+        })
+        .collect();
+    metric(
+        &mut out,
+        "onionize_service_state",
+        "gauge",
+        "Current state of the onion service (1 for the current state).",
+        &labelled(&states),
+    );
+    metric(
+        &mut out,
+        "onionize_service_reachable_transitions_total",
+        "counter",
+        "Times the onion service became reachable (running or degraded_reachable).",
+        &[("", tor.reachable_transitions as f64)],
+    );
+    metric(
+        &mut out,
+        "onionize_descriptor_upload_failures_total",
+        "counter",
+        "Times descriptor uploads started failing.",
+        &[("", tor.descriptor_upload_failures as f64)],
+    );
+
+    out
+}
+
+fn labelled(samples: &[(String, f64)]) -> Vec<(&str, f64)> {
+    samples
+        .iter()
+        .map(|(labels, value)| (labels.as_str(), *value))
+        .collect()
+}
+
+/// Writes one metric family. Every sample is a pair of labels (without braces) and a value.
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, samples: &[(&str, f64)]) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+    for (labels, value) in samples {
+        if labels.is_empty() {
+            let _ = writeln!(out, "{name} {value}");
+        } else {
+            let _ = writeln!(out, "{name}{{{labels}}} {value}");
+        }
+    }
+}
@@ -1,14 +1,36 @@
 // src/proxy.rs
-use anyhow::{Context, Result};
+use crate::backend::{Backend, TcpBackend};
+use crate::error::{Error, Result};
+use crate::events::{Event, EventBus};
+use crate::hooks::{Hooks, StreamDecision, StreamSummary};
+use crate::maintenance::MaintenancePage;
+use crate::ratelimit::{BandwidthLimits, Direction, RateLimiter};
+use crate::shutdown::ShutdownHandle;
+use crate::stats::TrafficStats;
+use arti_client::DataStream;
+use futures::future::{self, Either};
+use futures::stream::BoxStream;
 use futures::task::SpawnExt;
-use futures::{AsyncRead, AsyncReadExt, AsyncWrite, Stream, StreamExt};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, Stream, StreamExt};
 use rust_i18n::t;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
 use std::net::SocketAddr;
+use std::pin::pin;
 use std::str::FromStr;
-use tor_hsservice::RendRequest;
-use tor_rtcompat::Runtime;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tor_cell::relaycell::msg::{Connected, End, EndReason};
+use tor_hsservice::{ClientError, RendRequest, StreamRequest};
+use tor_proto::client::stream::IncomingStreamRequest;
+use tor_rtcompat::{Runtime, SleepProvider, SleepProviderExt};
 use tracing::{debug, info, warn};
 
+/// Size of the buffer used to copy data in each direction.
+const COPY_BUF_SIZE: usize = 16 * 1024;
+
 /// Runs the main proxy loop, accepting incoming Tor connections.
 ///
 /// This function continuously listens for incoming rendezvous requests from the Tor network,
@@ -18,52 +40,228 @@ use tracing::{debug, info, warn};
 ///
 /// * `runtime` - The runtime used to spawn tasks and connect to local sockets.
 /// * `rendezvous_requests` - The stream of incoming requests from the Onion Service.
-/// * `local_target` - The local address to forward traffic to (e.g., "127.0.0.1:8080").
-pub async fn run_proxy_loop<R>(
+/// * `local_target` - The local address to forward traffic to (e.g., "127.0.0.1:8080"),
+///   unless [`ProxyOptions::ports`] maps the requested virtual port elsewhere.
+/// * `options` - Time limits and other settings applied to every stream.
+/// * `shutdown` - Stops the loop from accepting new streams and tracks active ones.
+pub async fn run_proxy_loop<R, C>(
     runtime: R,
-    mut rendezvous_requests: impl Stream<Item = RendRequest> + Unpin,
+    rendezvous_requests: impl Stream<Item = C> + Unpin,
     local_target: &str,
+    options: ProxyOptions,
+    shutdown: ShutdownHandle,
+) where
+    R: Runtime,
+    C: PendingCircuit,
+{
+    let backend = Arc::new(TcpBackend::new(runtime.clone(), local_target));
+    run_backend_loop(runtime, rendezvous_requests, backend, options, shutdown).await;
+}
+
+/// Runs the proxy loop like [`run_proxy_loop`], sending streams to `backend` instead
+/// of a TCP address.
+///
+/// Virtual ports mapped in [`ProxyOptions::ports`] are still forwarded to their TCP targets,
+/// and [`ProxyOptions::hooks`] can reject a stream or remap it to another TCP target.
+/// Rejected streams, and streams to unmapped ports with [`ProxyOptions::mapped_ports_only`],
+/// are refused before they are accepted, so the visitor never gets `CONNECTED`.
+pub async fn run_backend_loop<R, C>(
+    runtime: R,
+    rendezvous_requests: impl Stream<Item = C> + Unpin,
+    backend: Arc<dyn Backend>,
+    options: ProxyOptions,
+    shutdown: ShutdownHandle,
+) where
+    R: Runtime,
+    C: PendingCircuit,
+{
+    let rt_proxy = runtime.clone();
+
+    let stats = options.stats.clone();
+
+    run_routed_loop(
+        runtime,
+        rendezvous_requests,
+        shutdown,
+        stats,
+        move |circuit_stats| {
+            // Every rendezvous circuit gets its own buckets and counters on top of the global ones
+            let mut circuit_options = options.clone();
+            circuit_options.rate_limiter = options.rate_limiter.child(options.circuit_limits);
+            circuit_options.hooks = options
+                .hooks
+                .for_circuit(circuit_stats.circuit_id().unwrap_or_default());
+            circuit_options.stats = circuit_stats;
+
+            let rt_proxy = rt_proxy.clone();
+            let backend = backend.clone();
+            move |port| {
+                let mut options = circuit_options.clone();
+                options.hooks = circuit_options.hooks.for_stream(port);
+                let target = match options.hooks.stream_request() {
+                    StreamDecision::Accept => options.ports.get(port),
+                    StreamDecision::Reject => {
+                        return Err(report_refused(&options, Error::StreamRejected(port)));
+                    }
+                    StreamDecision::Remap(target) => Some(target),
+                };
+                if target.is_none() && options.mapped_ports_only {
+                    return Err(report_refused(&options, Error::UnmappedPort(port)));
+                }
+
+                let rt = rt_proxy.clone();
+                let backend = backend.clone();
+                Ok(move |tor_stream: StreamOf<C>| async move {
+                    match target {
+                        Some(target) => {
+                            handle_connection_with_options(rt, tor_stream, &target, &options)
+                                .await?
+                        }
+                        None => {
+                            handle_connection_with_backend(rt, tor_stream, &*backend, &options)
+                                .await?
+                        }
+                    };
+                    Ok(())
+                })
+            }
+        },
+    )
+    .await;
+}
+
+/// Accepts incoming Tor streams and passes each one to a handler in its own task.
+///
+/// This is the shared accept loop behind [`run_proxy_loop`] and the other serving
+/// modes: every rendezvous request is accepted, registered as a circuit in `stats`
+/// and gets its own handler from `make_handler`, which can hold per-circuit state.
+/// Every stream opened on the circuit is accepted and handed to that handler.
+/// Errors returned by the handler are logged, except for [`Error::RemoteClosed`],
+/// which only means the visitor went away.
+///
+/// Once `shutdown` is triggered, the loop returns and no new streams are accepted;
+/// handlers that are already running are counted as active streams until they finish.
+///
+/// # Arguments
+///
+/// * `runtime` - The runtime used to spawn tasks.
+/// * `rendezvous_requests` - The stream of incoming requests from the Onion Service.
+/// * `shutdown` - Stops the loop and tracks active streams.
+/// * `stats` - Counts circuits and rejected requests.
+/// * `make_handler` - Called once per rendezvous circuit with the circuit's statistics;
+///   the returned handler is called once per accepted Tor stream, with the virtual
+///   port the visitor asked for.
+pub async fn run_stream_loop<R, C, M, F, Fut>(
+    runtime: R,
+    rendezvous_requests: impl Stream<Item = C> + Unpin,
+    shutdown: ShutdownHandle,
+    stats: TrafficStats,
+    make_handler: M,
+) where
+    R: Runtime,
+    C: PendingCircuit,
+    M: Fn(TrafficStats) -> F,
+    F: Fn(StreamOf<C>, u16) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    run_routed_loop(
+        runtime,
+        rendezvous_requests,
+        shutdown,
+        stats,
+        move |circuit_stats| {
+            let handler = Arc::new(make_handler(circuit_stats));
+            move |port| {
+                let handler = handler.clone();
+                Ok(move |tor_stream| handler(tor_stream, port))
+            }
+        },
+    )
+    .await;
+}
+
+/// Accepts incoming Tor streams like [`run_stream_loop`], deciding what to do with
+/// each stream before it is accepted.
+///
+/// `make_router` is called once per rendezvous circuit with the circuit's statistics.
+/// The returned router is called with the virtual port of every stream request: it
+/// returns the handler of the stream, which is then accepted, or an error, in which
+/// case the request is refused with an `END` cell and counted as rejected.
+pub async fn run_routed_loop<R, C, M, F, H, Fut>(
+    runtime: R,
+    rendezvous_requests: impl Stream<Item = C> + Unpin,
+    shutdown: ShutdownHandle,
+    stats: TrafficStats,
+    make_router: M,
 ) where
     R: Runtime,
+    C: PendingCircuit,
+    M: Fn(TrafficStats) -> F,
+    F: Fn(u16) -> Result<H> + Send + 'static,
+    H: FnOnce(StreamOf<C>) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
 {
-    let target_addr = local_target.to_string();
+    let stop = shutdown.clone();
+    let mut rendezvous_requests =
+        pin!(rendezvous_requests.take_until(async move { stop.wait().await }));
 
     while let Some(rendezvous_req) = rendezvous_requests.next().await {
-        let mut stream_requests = match rendezvous_req.accept().await {
+        let stream_requests = match rendezvous_req.accept().await {
             Ok(stream) => stream,
             Err(e) => {
                 debug!("{}", t!("proxy.errors.stream_req", req_err = e));
+                stats.request_rejected();
                 continue;
             }
         };
 
-        let target = target_addr.clone();
+        let circuit_stats = stats.for_circuit();
+        let router = make_router(circuit_stats.clone());
         let rt_clone = runtime.clone();
+        let shutdown = shutdown.clone();
 
         let spawn_res = runtime.spawn(async move {
+            let stop = shutdown.clone();
+            let mut stream_requests =
+                pin!(stream_requests.take_until(async move { stop.wait().await }));
+
             while let Some(stream_req) = stream_requests.next().await {
                 warn!("{}", t!("proxy.connect"));
 
-                let tor_stream = match stream_req
-                    .accept(tor_cell::relaycell::msg::Connected::new_empty())
-                    .await
-                {
+                let port = stream_req.port();
+                let handler = match router(port) {
+                    Ok(handler) => handler,
+                    Err(e) => {
+                        debug!("{}", t!("proxy.errors.proxy_error", error = e));
+                        circuit_stats.request_rejected();
+                        if let Err(e) = stream_req.reject().await {
+                            debug!("{}", t!("proxy.errors.client_error", err = e));
+                        }
+                        continue;
+                    }
+                };
+
+                let tor_stream = match stream_req.accept().await {
                     Ok(s) => s,
                     Err(e) => {
                         warn!("{}", t!("proxy.errors.client_error", err = e));
+                        circuit_stats.request_rejected();
                         continue;
                     }
                 };
 
-                let t_addr = target.clone();
-                let rt_inner = rt_clone.clone();
+                let connection = handler(tor_stream);
+                let active = shutdown.track();
 
                 let inner_spawn_res = rt_clone.spawn(async move {
-                    if let Err(e) = handle_connection(rt_inner, tor_stream, &t_addr).await
-                        && !e.to_string().contains("END cell with reason MISC")
-                    {
-                        warn!("{}", t!("proxy.errors.proxy_error", error = e));
+                    match connection.await {
+                        Ok(()) => {}
+                        Err(e @ Error::RemoteClosed(_)) => {
+                            debug!("{}", t!("proxy.errors.proxy_error", error = e));
+                        }
+                        Err(e) => warn!("{}", t!("proxy.errors.proxy_error", error = e)),
                     }
+                    drop(active);
                 });
 
                 if let Err(e) = inner_spawn_res {
@@ -78,10 +276,94 @@ pub async fn run_proxy_loop<R>(
     }
 }
 
+/// A visitor's request to open a rendezvous circuit, such as a [`RendRequest`].
+///
+/// The accept loops are generic over it, so they can be driven without the Tor network.
+pub trait PendingCircuit: Send + 'static {
+    /// The stream requests made on the circuit.
+    type Stream: PendingStream;
+    /// Why the circuit could not be established.
+    type Error: fmt::Display;
+
+    /// Accepts the request and returns the stream requests of the circuit.
+    fn accept(
+        self,
+    ) -> impl Future<Output = std::result::Result<BoxStream<'static, Self::Stream>, Self::Error>> + Send;
+}
+
+/// A visitor's request to open a stream on a rendezvous circuit, such as a [`StreamRequest`].
+pub trait PendingStream: Send + 'static {
+    /// The stream once accepted.
+    type Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+    /// Why the request could not be answered.
+    type Error: fmt::Display + Send;
+
+    /// Returns the virtual port the visitor asked for.
+    fn port(&self) -> u16;
+
+    /// Accepts the request, sending `CONNECTED` to the visitor.
+    fn accept(self) -> impl Future<Output = std::result::Result<Self::Stream, Self::Error>> + Send;
+
+    /// Refuses the request, sending `END` to the visitor.
+    fn reject(self) -> impl Future<Output = std::result::Result<(), Self::Error>> + Send;
+}
+
+/// The stream handed to the handlers of the accept loops for circuits `C`.
+pub type StreamOf<C> = <<C as PendingCircuit>::Stream as PendingStream>::Stream;
+
+impl PendingCircuit for RendRequest {
+    type Stream = StreamRequest;
+    type Error = ClientError;
+
+    async fn accept(self) -> std::result::Result<BoxStream<'static, StreamRequest>, ClientError> {
+        Ok(RendRequest::accept(self).await?.boxed())
+    }
+}
+
+impl PendingStream for StreamRequest {
+    type Stream = DataStream;
+    type Error = ClientError;
+
+    fn port(&self) -> u16 {
+        match self.request() {
+            IncomingStreamRequest::Begin(begin) => begin.port(),
+            _ => 0,
+        }
+    }
+
+    async fn accept(self) -> std::result::Result<DataStream, ClientError> {
+        StreamRequest::accept(self, Connected::new_empty()).await
+    }
+
+    async fn reject(self) -> std::result::Result<(), ClientError> {
+        // Other onion service implementations only send `DONE`
+        StreamRequest::reject(self, End::new_with_reason(EndReason::DONE)).await
+    }
+}
+
+/// Reports a stream refused before it was accepted, as closed with
+/// [`CloseReason::Rejected`], and returns `error`.
+fn report_refused(options: &ProxyOptions, error: Error) -> Error {
+    options.events.publish(&Event::StreamClosed {
+        reason: CloseReason::Rejected,
+        bytes_up: 0,
+        bytes_down: 0,
+        duration_ms: 0,
+    });
+    options.hooks.stream_closed(&StreamSummary {
+        reason: CloseReason::Rejected,
+        bytes_up: 0,
+        bytes_down: 0,
+        duration: Duration::ZERO,
+    });
+    error
+}
+
 /// Handles a single connection by bridging a Tor stream and a local TCP socket.
 ///
 /// This function establishes a connection to the `local_target` and copies data
-/// bidirectionally between the Tor stream and the local socket until one side closes.
+/// bidirectionally between the Tor stream and the local socket until both sides close.
+/// No time limits are applied; see [`handle_connection_with_timeouts`].
 ///
 /// # Arguments
 ///
@@ -93,26 +375,501 @@ where
     R: Runtime,
     S: AsyncRead + AsyncWrite + Unpin,
 {
-    debug!("Proxing to {}... ", local_target);
-    let addr: SocketAddr = SocketAddr::from_str(local_target)
-        .with_context(|| t!("proxy.errors.local_address", target = local_target))?;
+    handle_connection_with_timeouts(
+        runtime,
+        tor_stream,
+        local_target,
+        &StreamTimeouts::default(),
+    )
+    .await
+    .map(|_| ())
+}
+
+/// Handles a single connection like [`handle_connection`], enforcing `timeouts`.
+///
+/// All limits are measured with the runtime's clock, so they can be driven
+/// by a mock runtime in tests.
+///
+/// # Returns
+///
+/// Returns why the stream was closed: normally, or because of the idle or lifetime limit.
+///
+/// # Errors
+///
+/// Returns an error if the local service cannot be reached in time or the copy fails.
+pub async fn handle_connection_with_timeouts<R, S>(
+    runtime: R,
+    tor_stream: S,
+    local_target: &str,
+    timeouts: &StreamTimeouts,
+) -> Result<CloseReason>
+where
+    R: Runtime,
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let options = ProxyOptions {
+        timeouts: *timeouts,
+        ..Default::default()
+    };
+    handle_connection_with_options(runtime, tor_stream, local_target, &options).await
+}
+
+/// Handles a single connection like [`handle_connection_with_timeouts`], applying all `options`.
+///
+/// If the local service cannot be reached and [`ProxyOptions::maintenance`] is set,
+/// the page is sent to the visitor before the error is returned.
+///
+/// # Errors
+///
+/// * [`Error::InvalidTarget`] if `local_target` is not an IP address and a port.
+/// * [`Error::BackendUnreachable`] or [`Error::ConnectTimeout`] if the local service
+///   cannot be reached in time.
+/// * [`Error::RemoteClosed`] if the visitor closed the stream abruptly, or
+///   [`Error::Io`] if the copy fails otherwise.
+pub async fn handle_connection_with_options<R, S>(
+    runtime: R,
+    tor_stream: S,
+    local_target: &str,
+    options: &ProxyOptions,
+) -> Result<CloseReason>
+where
+    R: Runtime,
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    SocketAddr::from_str(local_target).map_err(|source| Error::InvalidTarget {
+        target: local_target.to_string(),
+        source,
+    })?;
+    let backend = TcpBackend::new(runtime.clone(), local_target);
+    handle_connection_with_backend(runtime, tor_stream, &backend, options).await
+}
+
+/// Handles a single connection like [`handle_connection_with_options`], opening the
+/// other side of the stream with `backend`.
+///
+/// # Errors
+///
+/// * The error of [`Backend::connect`], or [`Error::ConnectTimeout`] if it takes too long.
+/// * [`Error::RemoteClosed`] if the visitor closed the stream abruptly, or
+///   [`Error::Io`] if the copy fails otherwise.
+pub async fn handle_connection_with_backend<R, S, B>(
+    runtime: R,
+    mut tor_stream: S,
+    backend: &B,
+    options: &ProxyOptions,
+) -> Result<CloseReason>
+where
+    R: SleepProvider,
+    S: AsyncRead + AsyncWrite + Unpin,
+    B: Backend + ?Sized,
+{
+    let timeouts = &options.timeouts;
+    debug!("Proxing to {}... ", backend.describe());
+
+    let activity = StreamActivity::new(runtime.now(), options);
+
+    let connect = backend.connect();
+    let connected = match timeouts.connect {
+        Some(limit) => match runtime.timeout(limit, connect).await {
+            Ok(connected) => connected,
+            Err(_) => {
+                activity.report(&runtime, CloseReason::ConnectTimeout);
+                send_maintenance_page(&mut tor_stream, options).await;
+                return Err(Error::ConnectTimeout(backend.describe()));
+            }
+        },
+        None => connect.await,
+    };
+
+    let local_stream = match connected {
+        Ok(stream) => {
+            activity.connected(runtime.now(), &backend.describe());
+            stream
+        }
+        Err(e) => {
+            activity.report(&runtime, CloseReason::BackendUnreachable);
+            send_maintenance_page(&mut tor_stream, options).await;
+            return Err(e);
+        }
+    };
+
+    let copy = copy_with_activity(
+        &runtime,
+        tor_stream,
+        local_stream,
+        &activity,
+        &options.rate_limiter,
+    );
+
+    let limits = async {
+        let idle = async {
+            match timeouts.idle {
+                Some(idle) => activity.wait_idle(&runtime, idle).await,
+                None => future::pending().await,
+            }
+        };
+        let lifetime = async {
+            match timeouts.lifetime {
+                Some(lifetime) => runtime.sleep(lifetime).await,
+                None => future::pending().await,
+            }
+        };
+        match future::select(pin!(idle), pin!(lifetime)).await {
+            Either::Left(_) => CloseReason::IdleTimeout,
+            Either::Right(_) => CloseReason::LifetimeExceeded,
+        }
+    };
+
+    let reason = match future::select(pin!(copy), pin!(limits)).await {
+        Either::Left((Ok(()), _)) => CloseReason::Completed,
+        Either::Left((Err(e), _)) => {
+            activity.report(&runtime, CloseReason::Error);
+            return Err(e.into());
+        }
+        Either::Right((reason, _)) => reason,
+    };
+
+    activity.report(&runtime, reason);
+    Ok(reason)
+}
+
+/// Settings applied to every proxied stream.
+#[derive(Debug, Clone, Default)]
+pub struct ProxyOptions {
+    /// Time limits of the stream.
+    pub timeouts: StreamTimeouts,
+    /// Page sent to visitors when the local service is down.
+    pub maintenance: Option<Arc<MaintenancePage>>,
+    /// Bandwidth limits shared by every stream using these options.
+    pub rate_limiter: RateLimiter,
+    /// Bandwidth limits applied to each rendezvous circuit separately.
+    pub circuit_limits: BandwidthLimits,
+    /// Traffic counters updated by every stream.
+    pub stats: TrafficStats,
+    /// Targets of individual virtual ports, overriding the default target.
+    pub ports: PortMap,
+    /// Refuse streams to virtual ports missing from `ports` instead of using the default target.
+    pub mapped_ports_only: bool,
+    /// Receives a `stream_closed` event for every stream.
+    pub events: EventBus,
+    /// Called on every circuit and stream; they can reject or remap streams.
+    pub hooks: Hooks,
+}
+
+/// Local targets of individual virtual ports of the onion service.
+///
+/// Clones share the same mappings, so they can be changed while the service is running;
+/// new streams use the updated mappings.
+#[derive(Debug, Clone, Default)]
+pub struct PortMap {
+    inner: Arc<RwLock<BTreeMap<u16, String>>>,
+}
+
+impl PortMap {
+    /// Creates an empty map: every virtual port goes to the default target.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the target of virtual `port`, if it is mapped.
+    pub fn get(&self, port: u16) -> Option<String> {
+        self.inner
+            .read()
+            .expect("poisoned lock")
+            .get(&port)
+            .cloned()
+    }
+
+    /// Maps virtual `port` to `target` (e.g., "127.0.0.1:8080") and returns the previous target.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidTarget`] if `target` is not an IP address and a port.
+    pub fn insert(&self, port: u16, target: &str) -> Result<Option<String>> {
+        SocketAddr::from_str(target).map_err(|source| Error::InvalidTarget {
+            target: target.to_string(),
+            source,
+        })?;
+        Ok(self
+            .inner
+            .write()
+            .expect("poisoned lock")
+            .insert(port, target.to_string()))
+    }
+
+    /// Removes the mapping of virtual `port` and returns its target.
+    pub fn remove(&self, port: u16) -> Option<String> {
+        self.inner.write().expect("poisoned lock").remove(&port)
+    }
+
+    /// Returns every mapping, ordered by port.
+    pub fn list(&self) -> Vec<(u16, String)> {
+        self.inner
+            .read()
+            .expect("poisoned lock")
+            .iter()
+            .map(|(port, target)| (*port, target.clone()))
+            .collect()
+    }
+}
+
+/// Sends the maintenance page, if configured. Failures are only logged.
+async fn send_maintenance_page<S>(tor_stream: &mut S, options: &ProxyOptions)
+where
+    S: AsyncWrite + Unpin,
+{
+    if let Some(page) = &options.maintenance
+        && let Err(e) = page.send(tor_stream).await
+    {
+        debug!("Failed to send maintenance page: {}", e);
+    }
+}
+
+/// Time limits applied to every proxied stream. `None` disables a limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamTimeouts {
+    /// Maximum time to establish the connection to the local service.
+    pub connect: Option<Duration>,
+    /// Close the stream when no bytes flow in either direction for this long.
+    pub idle: Option<Duration>,
+    /// Close the stream once it has been open for this long.
+    pub lifetime: Option<Duration>,
+}
+
+/// Why a proxied stream was closed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CloseReason {
+    /// Both sides finished normally.
+    Completed,
+    /// No data flowed for longer than the idle timeout.
+    IdleTimeout,
+    /// The stream reached its maximum lifetime.
+    LifetimeExceeded,
+    /// The local service did not accept the connection in time.
+    ConnectTimeout,
+    /// The local service refused the connection.
+    BackendUnreachable,
+    /// An I/O error occurred while copying data.
+    Error,
+    /// The stream was refused before it was accepted, by a hook or because
+    /// its virtual port is not mapped.
+    Rejected,
+}
+
+impl fmt::Display for CloseReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            CloseReason::Completed => "completed",
+            CloseReason::IdleTimeout => "idle_timeout",
+            CloseReason::LifetimeExceeded => "lifetime_exceeded",
+            CloseReason::ConnectTimeout => "connect_timeout",
+            CloseReason::BackendUnreachable => "backend_unreachable",
+            CloseReason::Error => "error",
+            CloseReason::Rejected => "rejected",
+        };
+        f.write_str(reason)
+    }
+}
+
+/// Byte counters and the time of the last transfer of a proxied stream.
+struct StreamActivity {
+    opened: Instant,
+    last_transfer: Mutex<Instant>,
+    up: AtomicU64,
+    down: AtomicU64,
+    stats: TrafficStats,
+    events: EventBus,
+    hooks: Hooks,
+}
+
+impl StreamActivity {
+    fn new(now: Instant, options: &ProxyOptions) -> Self {
+        options.stats.stream_opened();
+        Self {
+            opened: now,
+            last_transfer: Mutex::new(now),
+            up: AtomicU64::new(0),
+            down: AtomicU64::new(0),
+            stats: options.stats.clone(),
+            events: options.events.clone(),
+            hooks: options.hooks.clone(),
+        }
+    }
+
+    fn connected(&self, now: Instant, target: &str) {
+        let latency = now.saturating_duration_since(self.opened);
+        self.stats.backend_connected(latency);
+        self.hooks.backend_connected(target, latency);
+    }
+
+    fn record(&self, now: Instant, direction: Direction, bytes: usize) {
+        let counter = match direction {
+            Direction::Upload => &self.up,
+            Direction::Download => &self.down,
+        };
+        counter.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.stats.transferred(direction, bytes);
+        self.hooks.transferred(direction, bytes);
+        *self.last_transfer.lock().expect("poisoned lock") = now;
+    }
+
+    /// Resolves once nothing has been transferred for `idle`.
+    async fn wait_idle<R: SleepProvider>(&self, runtime: &R, idle: Duration) {
+        loop {
+            let last = *self.last_transfer.lock().expect("poisoned lock");
+            let elapsed = runtime.now().saturating_duration_since(last);
+            if elapsed >= idle {
+                return;
+            }
+            runtime.sleep(idle - elapsed).await;
+        }
+    }
 
-    //
-    let local_stream = runtime
-        .connect(&addr)
-        .await
-        .with_context(|| t!("errors.service_unreachable", target = local_target))?;
+    /// Logs the closure of the stream, emits a `stream_closed` event and calls the hooks.
+    fn report<R: SleepProvider>(&self, runtime: &R, reason: CloseReason) {
+        let up = self.up.load(Ordering::Relaxed);
+        let down = self.down.load(Ordering::Relaxed);
+        let duration = runtime.now().saturating_duration_since(self.opened);
+        self.stats.stream_closed(reason);
+
+        info!(
+            "{}",
+            t!("proxy.closed", reason = reason, up = up, down = down)
+        );
+        self.events.publish(&Event::StreamClosed {
+            reason,
+            bytes_up: up,
+            bytes_down: down,
+            duration_ms: duration.as_millis() as u64,
+        });
+        self.hooks.stream_closed(&StreamSummary {
+            reason,
+            bytes_up: up,
+            bytes_down: down,
+            duration,
+        });
+    }
+}
 
+/// Copies data in both directions, recording every transfer in `activity`
+/// and throttling it with `limiter`.
+async fn copy_with_activity<R, A, B>(
+    runtime: &R,
+    tor_stream: A,
+    local_stream: B,
+    activity: &StreamActivity,
+    limiter: &RateLimiter,
+) -> std::io::Result<()>
+where
+    R: SleepProvider,
+    A: AsyncRead + AsyncWrite + Unpin,
+    B: AsyncRead + AsyncWrite + Unpin,
+{
     let (mut r_tor, mut w_tor) = tor_stream.split();
     let (mut r_loc, mut w_loc) = local_stream.split();
 
-    // futures::io::copy work with AsyncRead/AsyncWrite
-    let client_to_server = futures::io::copy(&mut r_tor, &mut w_loc);
-    let server_to_client = futures::io::copy(&mut r_loc, &mut w_tor);
+    let client_to_server = copy_counted(
+        runtime,
+        &mut r_tor,
+        &mut w_loc,
+        activity,
+        limiter,
+        Direction::Upload,
+    );
+    let server_to_client = copy_counted(
+        runtime,
+        &mut r_loc,
+        &mut w_tor,
+        activity,
+        limiter,
+        Direction::Download,
+    );
 
     // Run both directions concurrently
-    let (up, down) = futures::future::try_join(client_to_server, server_to_client).await?;
-
-    info!("Stream closed. Up: {} B, Down: {} B", up, down);
+    futures::future::try_join(client_to_server, server_to_client).await?;
     Ok(())
 }
+
+/// Copies data in both directions between two streams until both sides finish.
+///
+/// When one side reaches EOF, the write half of the other side is closed
+/// (half-close), while data keeps flowing in the opposite direction.
+///
+/// Returns the number of bytes copied from `a` to `b` and from `b` to `a`.
+///
+/// # Arguments
+///
+/// * `a` - The first stream (e.g., the Tor stream).
+/// * `b` - The second stream (e.g., the local socket).
+pub async fn copy_bidirectional<A, B>(a: A, b: B) -> Result<(u64, u64)>
+where
+    A: AsyncRead + AsyncWrite + Unpin,
+    B: AsyncRead + AsyncWrite + Unpin,
+{
+    let (mut r_a, mut w_a) = a.split();
+    let (mut r_b, mut w_b) = b.split();
+
+    let a_to_b = copy_half(&mut r_a, &mut w_b, COPY_BUF_SIZE, |_| future::ready(()));
+    let b_to_a = copy_half(&mut r_b, &mut w_a, COPY_BUF_SIZE, |_| future::ready(()));
+
+    // Run both directions concurrently
+    let (up, down) = futures::future::try_join(a_to_b, b_to_a).await?;
+
+    Ok((up, down))
+}
+
+/// Copies `reader` into `writer` until EOF, recording and throttling every chunk.
+async fn copy_counted<R, Rd, Wr>(
+    runtime: &R,
+    reader: &mut Rd,
+    writer: &mut Wr,
+    activity: &StreamActivity,
+    limiter: &RateLimiter,
+    direction: Direction,
+) -> std::io::Result<u64>
+where
+    R: SleepProvider,
+    Rd: AsyncRead + Unpin,
+    Wr: AsyncWrite + Unpin,
+{
+    let chunk = limiter.max_chunk(direction, COPY_BUF_SIZE);
+
+    copy_half(reader, writer, chunk, |n| {
+        activity.record(runtime.now(), direction, n);
+        limiter.throttle(runtime, direction, n)
+    })
+    .await
+}
+
+/// Copies `reader` into `writer` until EOF, then closes `writer`.
+///
+/// Closing the write half propagates the EOF to the peer (a TCP `FIN` or a Tor
+/// `END` cell), so protocols that rely on half-close do not hang.
+/// At most `chunk` bytes are read at once, and `on_transfer` is awaited with
+/// the size of every chunk written.
+async fn copy_half<Rd, Wr, F, Fut>(
+    reader: &mut Rd,
+    writer: &mut Wr,
+    chunk: usize,
+    mut on_transfer: F,
+) -> std::io::Result<u64>
+where
+    Rd: AsyncRead + Unpin,
+    Wr: AsyncWrite + Unpin,
+    F: FnMut(usize) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let mut buf = vec![0u8; chunk.max(1)];
+    let mut total = 0u64;
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            writer.close().await?;
+            return Ok(total);
+        }
+        writer.write_all(&buf[..n]).await?;
+        total += n as u64;
+        on_transfer(n).await;
+    }
+}
@@ -0,0 +1,131 @@
+// src/qr.rs
+use crate::error::{Error, Result};
+use crate::utils::write_private_file;
+use image::Luma;
+use qrcode::QrCode;
+use qrcode::render::{svg, unicode};
+use std::io::Cursor;
+use std::path::Path;
+
+/// Blank modules around a QR code on each side, which scanners need.
+const QUIET_ZONE: usize = 4;
+
+/// Minimum width and height of the images written by [`write_file`], in pixels.
+const IMAGE_SIZE: u32 = 512;
+
+/// Encodes `data`, e.g. an onion URL or an `.auth_private` line, as a QR code.
+///
+/// # Errors
+///
+/// Returns [`Error::QrCode`] if `data` does not fit in a QR code.
+pub fn encode(data: &str) -> Result<QrCode> {
+    QrCode::new(data).map_err(Error::QrCode)
+}
+
+/// How a QR code is drawn in a terminal, see [`render_terminal`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TerminalStyle {
+    /// Two full blocks per module: square modules, the easiest to scan.
+    FullBlock,
+    /// Half blocks, two modules per character: half as wide and high.
+    Dense,
+}
+
+impl TerminalStyle {
+    /// Returns the number of columns `code` takes in this style.
+    pub fn width(self, code: &QrCode) -> usize {
+        let modules = code.width() + 2 * QUIET_ZONE;
+        match self {
+            TerminalStyle::FullBlock => 2 * modules,
+            TerminalStyle::Dense => modules,
+        }
+    }
+
+    /// Picks the most readable style in which `code` fits in `columns`, or `None`
+    /// if it does not fit at all.
+    pub fn fitting(code: &QrCode, columns: usize) -> Option<Self> {
+        [TerminalStyle::FullBlock, TerminalStyle::Dense]
+            .into_iter()
+            .find(|style| style.width(code) <= columns)
+    }
+}
+
+/// Draws `code` with text in `style`, for a terminal with a dark background.
+pub fn render_terminal(code: &QrCode, style: TerminalStyle) -> String {
+    match style {
+        TerminalStyle::FullBlock => code
+            .render::<char>()
+            .module_dimensions(2, 1)
+            .dark_color(' ')
+            .light_color('█')
+            .build(),
+        TerminalStyle::Dense => code
+            .render::<unicode::Dense1x2>()
+            .dark_color(unicode::Dense1x2::Light)
+            .light_color(unicode::Dense1x2::Dark)
+            .build(),
+    }
+}
+
+/// Image formats of [`write_file`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// A PNG image, for sending over chat.
+    Png,
+    /// An SVG image, which scales to any size.
+    Svg,
+}
+
+impl ImageFormat {
+    /// Returns the format matching the extension of `file`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::QrFormat`] if the extension is not `.png` or `.svg`.
+    pub fn from_path(file: &Path) -> Result<Self> {
+        let extension = file
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some("png") => Ok(ImageFormat::Png),
+            Some("svg") => Ok(ImageFormat::Svg),
+            _ => Err(Error::QrFormat(file.to_path_buf())),
+        }
+    }
+}
+
+/// Writes `code` as a PNG or SVG image, by the extension of `file`.
+///
+/// The file is readable only by the current user, since it may hold a credential.
+///
+/// # Errors
+///
+/// Returns [`Error::QrFormat`] for other extensions and [`Error::QrFile`] if the
+/// image cannot be written.
+pub fn write_file(code: &QrCode, file: &Path) -> Result<()> {
+    let write_error = |source| Error::QrFile {
+        file: file.to_path_buf(),
+        source,
+    };
+
+    let content = match ImageFormat::from_path(file)? {
+        ImageFormat::Png => {
+            let image = code
+                .render::<Luma<u8>>()
+                .min_dimensions(IMAGE_SIZE, IMAGE_SIZE)
+                .build();
+            let mut png = Vec::new();
+            image
+                .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+                .map_err(|e| write_error(std::io::Error::other(e)))?;
+            png
+        }
+        ImageFormat::Svg => code
+            .render::<svg::Color>()
+            .min_dimensions(IMAGE_SIZE, IMAGE_SIZE)
+            .build()
+            .into_bytes(),
+    };
+    write_private_file(file, &content).map_err(write_error)
+}
@@ -0,0 +1,149 @@
+// src/ratelimit.rs
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tor_rtcompat::SleepProvider;
+
+/// Bandwidth limits in bytes per second. `None` means unlimited.
+///
+/// Directions are named from the visitor's point of view: `upload` is data sent
+/// from the Tor client to the local service, `download` is the reverse.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BandwidthLimits {
+    /// Maximum rate from the Tor client to the local service.
+    pub upload: Option<u64>,
+    /// Maximum rate from the local service to the Tor client.
+    pub download: Option<u64>,
+}
+
+/// Direction of a transfer, see [`BandwidthLimits`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// From the Tor client to the local service.
+    Upload,
+    /// From the local service to the Tor client.
+    Download,
+}
+
+/// A token bucket holding up to one second worth of bytes.
+///
+/// Transfers may take more tokens than available; the bucket then goes into debt
+/// and the caller sleeps until it is paid off, so large chunks are still
+/// limited to the configured average rate.
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: u64,
+    state: Mutex<BucketState>,
+}
+
+#[derive(Debug)]
+struct BucketState {
+    tokens: f64,
+    last_refill: Option<Instant>,
+}
+
+impl TokenBucket {
+    /// Creates a full bucket refilled at `rate` bytes per second.
+    pub fn new(rate: u64) -> Self {
+        let rate = rate.max(1);
+        Self {
+            rate,
+            state: Mutex::new(BucketState {
+                tokens: rate as f64,
+                last_refill: None,
+            }),
+        }
+    }
+
+    /// Returns the refill rate in bytes per second.
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    /// Takes `bytes` tokens and returns how long the caller must wait to stay within the rate.
+    pub fn take(&self, now: Instant, bytes: usize) -> Duration {
+        let mut state = self.state.lock().expect("poisoned lock");
+
+        if let Some(last) = state.last_refill {
+            let elapsed = now.saturating_duration_since(last).as_secs_f64();
+            state.tokens = (state.tokens + elapsed * self.rate as f64).min(self.rate as f64);
+        }
+        state.last_refill = Some(now);
+        state.tokens -= bytes as f64;
+
+        if state.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-state.tokens / self.rate as f64)
+        }
+    }
+}
+
+/// A set of token buckets that every transfer of a stream must pass through.
+///
+/// Clones share the same buckets, so a limiter created once and cloned into every
+/// stream limits their combined rate.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimiter {
+    upload: Vec<Arc<TokenBucket>>,
+    download: Vec<Arc<TokenBucket>>,
+}
+
+impl RateLimiter {
+    /// Creates a limiter enforcing `limits`.
+    pub fn new(limits: BandwidthLimits) -> Self {
+        Self::default().child(limits)
+    }
+
+    /// Returns a limiter that enforces both the limits of `self` and `limits`.
+    ///
+    /// The buckets of `self` stay shared with the new limiter, while the new ones
+    /// belong to it alone (e.g., one per rendezvous circuit).
+    pub fn child(&self, limits: BandwidthLimits) -> Self {
+        let mut child = self.clone();
+        if let Some(rate) = limits.upload {
+            child.upload.push(Arc::new(TokenBucket::new(rate)));
+        }
+        if let Some(rate) = limits.download {
+            child.download.push(Arc::new(TokenBucket::new(rate)));
+        }
+        child
+    }
+
+    /// Returns the largest chunk that should be transferred at once in `direction`.
+    ///
+    /// Keeping chunks within one second worth of the slowest rate makes the
+    /// traffic smooth instead of bursty.
+    pub fn max_chunk(&self, direction: Direction, default: usize) -> usize {
+        self.buckets(direction)
+            .iter()
+            .map(|bucket| usize::try_from(bucket.rate()).unwrap_or(usize::MAX))
+            .fold(default, usize::min)
+    }
+
+    /// Accounts for `bytes` transferred in `direction`, sleeping as long as the limits require.
+    pub async fn throttle<R: SleepProvider>(
+        &self,
+        runtime: &R,
+        direction: Direction,
+        bytes: usize,
+    ) {
+        let now = runtime.now();
+        let wait = self
+            .buckets(direction)
+            .iter()
+            .map(|bucket| bucket.take(now, bytes))
+            .max()
+            .unwrap_or_default();
+
+        if !wait.is_zero() {
+            runtime.sleep(wait).await;
+        }
+    }
+
+    fn buckets(&self, direction: Direction) -> &[Arc<TokenBucket>] {
+        match direction {
+            Direction::Upload => &self.upload,
+            Direction::Download => &self.download,
+        }
+    }
+}
@@ -0,0 +1,547 @@
+// src/receive.rs
+use crate::error::{Error, Result};
+use crate::shutdown::ShutdownHandle;
+use crate::stats::TrafficStats;
+use futures::channel::oneshot;
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, Stream};
+use rust_i18n::t;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use tor_hsservice::RendRequest;
+use tor_rtcompat::{Blocking, Runtime};
+use tracing::{debug, info};
+
+/// Maximum size of the HTTP request head (request line and headers).
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+/// Maximum size of the headers of a single multipart part.
+const MAX_PART_HEAD_SIZE: usize = 8 * 1024;
+
+/// Maximum length (in characters) of a stored file name.
+const MAX_FILENAME_LEN: usize = 100;
+
+/// Size of the chunks read from the Tor stream.
+const READ_CHUNK: usize = 16 * 1024;
+
+/// Settings for the receive-files mode.
+#[derive(Debug, Clone)]
+pub struct ReceiveConfig {
+    /// Directory where uploaded files are stored.
+    pub dir: PathBuf,
+    /// Maximum size of a single upload request in bytes.
+    pub max_size: u64,
+}
+
+/// Runs the receive loop, serving the upload form on every incoming Tor stream.
+///
+/// # Arguments
+///
+/// * `runtime` - The runtime used to spawn tasks.
+/// * `rendezvous_requests` - The stream of incoming requests from the Onion Service.
+/// * `config` - Where to store uploads and how large they may be.
+/// * `shutdown` - Stops the loop from accepting new streams and tracks active ones.
+/// * `stats` - Counts circuits and rejected requests.
+pub async fn run_receive_loop<R>(
+    runtime: R,
+    rendezvous_requests: impl Stream<Item = RendRequest> + Unpin,
+    config: ReceiveConfig,
+    shutdown: ShutdownHandle,
+    stats: TrafficStats,
+) where
+    R: Runtime,
+{
+    crate::proxy::run_stream_loop(
+        runtime.clone(),
+        rendezvous_requests,
+        shutdown,
+        stats,
+        move |_| {
+            let runtime = runtime.clone();
+            let config = config.clone();
+            move |tor_stream, _port| {
+                let runtime = runtime.clone();
+                let config = config.clone();
+                async move { handle_receive_connection(&runtime, tor_stream, &config).await }
+            }
+        },
+    )
+    .await;
+}
+
+/// Serves a single HTTP request on `stream`.
+///
+/// `GET /` returns the upload form, `POST /` accepts a `multipart/form-data` body and
+/// streams every file it contains into [`ReceiveConfig::dir`]. The connection is closed
+/// after the response. File system work runs on the blocking pool of `runtime`.
+///
+/// # Errors
+///
+/// Returns an error if the stream fails, or if an upload cannot be written to disk.
+pub async fn handle_receive_connection<R, S>(
+    runtime: &R,
+    mut stream: S,
+    config: &ReceiveConfig,
+) -> Result<()>
+where
+    R: Blocking,
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let Some((head, rest)) = read_head(&mut stream).await? else {
+        return respond(&mut stream, "431 Request Header Fields Too Large", "").await;
+    };
+
+    let mut headers = [httparse::EMPTY_HEADER; 64];
+    let mut request = httparse::Request::new(&mut headers);
+    if !matches!(request.parse(&head), Ok(httparse::Status::Complete(_))) {
+        return respond(&mut stream, "400 Bad Request", "").await;
+    }
+
+    let method = request.method.unwrap_or_default();
+    let path = request.path.unwrap_or_default();
+    debug!("Receive: {} {}", method, path);
+
+    match (method, path) {
+        ("GET", "/") => respond(&mut stream, "200 OK", &form_page()).await,
+        ("POST", "/") => {
+            let content_length = header(request.headers, "content-length")
+                .and_then(|v| v.trim().parse::<u64>().ok());
+            let Some(content_length) = content_length else {
+                return respond(&mut stream, "411 Length Required", "").await;
+            };
+            if content_length > config.max_size {
+                info!(
+                    "{}",
+                    t!(
+                        "receive.too_large",
+                        size = content_length,
+                        limit = config.max_size
+                    )
+                );
+                let page = message_page(&t!("receive.page.too_large"));
+                return respond(&mut stream, "413 Content Too Large", &page).await;
+            }
+            let Some(boundary) = header(request.headers, "content-type").and_then(boundary) else {
+                return respond(&mut stream, "400 Bad Request", "").await;
+            };
+
+            let mut body = BodyReader {
+                stream: &mut stream,
+                buf: rest,
+                remaining: content_length,
+            };
+            body.clamp();
+
+            match receive_multipart(runtime, &mut body, &boundary, config).await {
+                Ok(saved) => {
+                    let page = message_page(&t!("receive.page.received", count = saved.len()));
+                    respond(&mut stream, "200 OK", &page).await
+                }
+                Err(e) => {
+                    let _ = respond(&mut stream, "400 Bad Request", "").await;
+                    Err(e)
+                }
+            }
+        }
+        (_, "/") => respond(&mut stream, "405 Method Not Allowed", "").await,
+        _ => respond(&mut stream, "404 Not Found", "").await,
+    }
+}
+
+/// Turns a client-supplied file name into a safe name inside the upload directory.
+///
+/// Directory components are dropped, characters other than letters, digits, `.`, `-`
+/// and `_` are replaced with `_`, leading dots are removed and the result is truncated.
+/// An empty result becomes `upload`.
+pub fn sanitize_filename(name: &str) -> String {
+    let base = name.rsplit(['/', '\\']).next().unwrap_or_default();
+
+    let cleaned: String = base
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, '.' | '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    let truncated: String = cleaned
+        .trim_start_matches('.')
+        .chars()
+        .take(MAX_FILENAME_LEN)
+        .collect();
+
+    if truncated.is_empty() {
+        "upload".to_string()
+    } else {
+        truncated
+    }
+}
+
+/// Reads from `stream` until the end of the HTTP head.
+///
+/// Returns the head and any body bytes read past it, or `None` if the head is too large.
+async fn read_head<S>(stream: &mut S) -> Result<Option<(Vec<u8>, Vec<u8>)>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 1024];
+
+    loop {
+        if let Some(pos) = find(&buf, b"\r\n\r\n") {
+            let rest = buf.split_off(pos + 4);
+            return Ok(Some((buf, rest)));
+        }
+        if buf.len() > MAX_HEAD_SIZE {
+            return Ok(None);
+        }
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(Error::IncompleteRequest);
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
+
+/// The request body, limited to the announced `Content-Length`.
+struct BodyReader<'a, S> {
+    stream: &'a mut S,
+    buf: Vec<u8>,
+    remaining: u64,
+}
+
+impl<S: AsyncRead + Unpin> BodyReader<'_, S> {
+    /// Accounts for the body bytes that arrived together with the head.
+    fn clamp(&mut self) {
+        let already = self.buf.len() as u64;
+        if already > self.remaining {
+            self.buf.truncate(self.remaining as usize);
+        }
+        self.remaining = self.remaining.saturating_sub(already);
+    }
+
+    /// Reads more of the body into the buffer. Fails if the body has ended.
+    async fn fill(&mut self) -> Result<()> {
+        if self.remaining == 0 {
+            return Err(Error::MalformedUpload);
+        }
+        let mut chunk = vec![0u8; READ_CHUNK.min(self.remaining as usize)];
+        let n = self.stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(Error::IncompleteRequest);
+        }
+        self.buf.extend_from_slice(&chunk[..n]);
+        self.remaining -= n as u64;
+        Ok(())
+    }
+
+    /// Makes sure at least `len` bytes are buffered.
+    async fn require(&mut self, len: usize) -> Result<()> {
+        while self.buf.len() < len {
+            self.fill().await?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a `multipart/form-data` body and stores every file part.
+///
+/// Returns the names of the stored files.
+async fn receive_multipart<R, S>(
+    runtime: &R,
+    body: &mut BodyReader<'_, S>,
+    boundary: &str,
+    config: &ReceiveConfig,
+) -> Result<Vec<String>>
+where
+    R: Blocking,
+    S: AsyncRead + Unpin,
+{
+    let dash_boundary = format!("--{boundary}").into_bytes();
+    let delimiter = format!("\r\n--{boundary}").into_bytes();
+    let mut saved = Vec::new();
+
+    // Skip the preamble up to the first boundary.
+    loop {
+        if let Some(pos) = find(&body.buf, &dash_boundary) {
+            body.buf.drain(..pos + dash_boundary.len());
+            break;
+        }
+        let keep = body.buf.len().saturating_sub(dash_boundary.len());
+        body.buf.drain(..keep);
+        body.fill().await?;
+    }
+
+    loop {
+        // A boundary is followed either by "--" (end of body) or by CRLF and a part.
+        body.require(2).await?;
+        if body.buf.starts_with(b"--") {
+            return Ok(saved);
+        }
+        if !body.buf.starts_with(b"\r\n") {
+            return Err(Error::MalformedUpload);
+        }
+        body.buf.drain(..2);
+
+        let part_head = loop {
+            if let Some(pos) = find(&body.buf, b"\r\n\r\n") {
+                let head: Vec<u8> = body.buf.drain(..pos + 4).collect();
+                break String::from_utf8_lossy(&head).into_owned();
+            }
+            if body.buf.len() > MAX_PART_HEAD_SIZE {
+                return Err(Error::MalformedUpload);
+            }
+            body.fill().await?;
+        };
+
+        let mut upload = match part_filename(&part_head) {
+            Some(name) if !name.is_empty() => {
+                Some(Upload::create(runtime, &config.dir, &name).await?)
+            }
+            _ => None,
+        };
+
+        // Stream the part content until the next delimiter.
+        loop {
+            if let Some(pos) = find(&body.buf, &delimiter) {
+                if let Some(upload) = upload.as_mut() {
+                    upload.write(runtime, &body.buf[..pos]).await?;
+                }
+                body.buf.drain(..pos + delimiter.len());
+                break;
+            }
+            let keep = delimiter.len() - 1;
+            if body.buf.len() > keep {
+                let flush = body.buf.len() - keep;
+                if let Some(upload) = upload.as_mut() {
+                    upload.write(runtime, &body.buf[..flush]).await?;
+                }
+                body.buf.drain(..flush);
+            }
+            body.fill().await?;
+        }
+
+        if let Some(upload) = upload {
+            saved.push(upload.finish(runtime).await?);
+        }
+    }
+}
+
+/// A file being written to the upload directory.
+///
+/// Data goes to a `.part` file which is renamed once the upload is complete,
+/// and removed if the upload is abandoned. The file calls block, so they run
+/// on the blocking pool of the runtime rather than on the proxy tasks.
+struct Upload {
+    writer: Option<BufWriter<File>>,
+    part_path: PathBuf,
+    final_path: PathBuf,
+    name: String,
+    size: u64,
+    finished: bool,
+}
+
+impl Upload {
+    async fn create<R: Blocking>(runtime: &R, dir: &Path, original: &str) -> Result<Self> {
+        let sanitized = sanitize_filename(original);
+        let dir = dir.to_path_buf();
+
+        blocking(runtime, move || {
+            let (stem, ext) = match sanitized.rsplit_once('.') {
+                Some((stem, ext)) if !stem.is_empty() => (stem.to_string(), format!(".{ext}")),
+                _ => (sanitized.clone(), String::new()),
+            };
+
+            let mut counter = 0u32;
+            let (name, final_path, part_path) = loop {
+                let name = if counter == 0 {
+                    sanitized.clone()
+                } else {
+                    format!("{stem}-{counter}{ext}")
+                };
+                let final_path = dir.join(&name);
+                let part_path = dir.join(format!("{name}.part"));
+                if !final_path.exists() && !part_path.exists() {
+                    break (name, final_path, part_path);
+                }
+                counter += 1;
+            };
+
+            let file = File::create_new(&part_path).map_err(|source| Error::CreateUpload {
+                file: part_path.clone(),
+                source,
+            })?;
+
+            Ok(Self {
+                writer: Some(BufWriter::new(file)),
+                part_path,
+                final_path,
+                name,
+                size: 0,
+                finished: false,
+            })
+        })
+        .await
+    }
+
+    async fn write<R: Blocking>(&mut self, runtime: &R, data: &[u8]) -> Result<()> {
+        let Some(mut writer) = self.writer.take() else {
+            return Ok(());
+        };
+        let data = data.to_vec();
+        let len = data.len() as u64;
+
+        let (writer, result) = blocking(runtime, move || {
+            let result = writer.write_all(&data);
+            (writer, result)
+        })
+        .await;
+        self.writer = Some(writer);
+
+        result.map_err(|source| Error::WriteUpload {
+            file: self.name.clone(),
+            source,
+        })?;
+        self.size += len;
+        Ok(())
+    }
+
+    async fn finish<R: Blocking>(mut self, runtime: &R) -> Result<String> {
+        let writer = self.writer.take();
+        let part_path = self.part_path.clone();
+        let final_path = self.final_path.clone();
+
+        blocking(runtime, move || {
+            if let Some(writer) = writer {
+                writer
+                    .into_inner()
+                    .map_err(|e| e.into_error())
+                    .and_then(|file| file.sync_all())?;
+            }
+            std::fs::rename(&part_path, &final_path)
+        })
+        .await
+        .map_err(|source| Error::WriteUpload {
+            file: self.name.clone(),
+            source,
+        })?;
+        self.finished = true;
+
+        info!(
+            "{}",
+            t!("receive.saved", file = self.name, size = self.size)
+        );
+        Ok(std::mem::take(&mut self.name))
+    }
+}
+
+impl Drop for Upload {
+    fn drop(&mut self) {
+        // Also covers uploads dropped while a write was still running on the blocking pool.
+        if !self.finished {
+            let _ = std::fs::remove_file(&self.part_path);
+        }
+    }
+}
+
+/// Runs the blocking `f` on the blocking pool of `runtime` and waits for its result.
+///
+/// The result comes back over a channel, so the waiting future stays `Send`
+/// whatever the runtime's thread handle is.
+async fn blocking<R, T, F>(runtime: &R, f: F) -> T
+where
+    R: Blocking,
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let (tx, rx) = oneshot::channel();
+    drop(runtime.spawn_blocking(move || {
+        let _ = tx.send(f());
+    }));
+    rx.await.expect("blocking file task panicked")
+}
+
+/// Extracts the `filename` parameter from the `Content-Disposition` of a part.
+fn part_filename(part_head: &str) -> Option<String> {
+    let disposition = part_head.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.trim()
+            .eq_ignore_ascii_case("content-disposition")
+            .then_some(value)
+    })?;
+
+    let start = disposition.find("filename=\"")? + "filename=\"".len();
+    let end = disposition[start..].find('"')?;
+    Some(disposition[start..start + end].to_string())
+}
+
+/// Extracts the multipart boundary from a `Content-Type` header value.
+fn boundary(content_type: &str) -> Option<String> {
+    let mut parts = content_type.split(';');
+    if !parts
+        .next()?
+        .trim()
+        .eq_ignore_ascii_case("multipart/form-data")
+    {
+        return None;
+    }
+    parts.find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        key.trim()
+            .eq_ignore_ascii_case("boundary")
+            .then(|| value.trim().trim_matches('"').to_string())
+            .filter(|b| !b.is_empty())
+    })
+}
+
+/// Looks up a header value by (case-insensitive) name.
+fn header<'a>(headers: &[httparse::Header<'a>], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|h| h.name.eq_ignore_ascii_case(name))
+        .and_then(|h| std::str::from_utf8(h.value).ok())
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+async fn respond<S>(stream: &mut S, status: &str, body: &str) -> Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.flush().await?;
+    stream.close().await?;
+    Ok(())
+}
+
+fn form_page() -> String {
+    page(&format!(
+        "<h1>{title}</h1>\n<form method=\"post\" action=\"/\" enctype=\"multipart/form-data\">\n<input type=\"file\" name=\"file\" multiple required>\n<button type=\"submit\">{button}</button>\n</form>",
+        title = t!("receive.page.title"),
+        button = t!("receive.page.button"),
+    ))
+}
+
+fn message_page(message: &str) -> String {
+    page(&format!(
+        "<p>{message}</p>\n<p><a href=\"/\">{back}</a></p>",
+        back = t!("receive.page.back")
+    ))
+}
+
+fn page(content: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Onionize</title></head>\n<body>\n{content}\n</body>\n</html>\n"
+    )
+}
@@ -0,0 +1,148 @@
+// src/secret.rs
+use rust_i18n::t;
+use std::fmt;
+use std::ops::Deref;
+use std::sync::Once;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::warn;
+use zeroize::Zeroize;
+
+/// Whether new secrets are locked in memory, see [`set_memory_locking`].
+static LOCK_MEMORY: AtomicBool = AtomicBool::new(false);
+
+/// Failing to lock memory is only reported once.
+static LOCK_WARNING: Once = Once::new();
+
+/// Enables or disables locking new [`Secret`]s in memory, so they are never
+/// written to swap. Used by the CLI flag `--mlock`.
+///
+/// Only supported on Unix. When the system refuses (e.g., because of
+/// `RLIMIT_MEMLOCK`), a warning is logged and secrets are still wiped on drop.
+pub fn set_memory_locking(enabled: bool) {
+    LOCK_MEMORY.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns `true` if new secrets are locked in memory.
+pub fn memory_locking() -> bool {
+    LOCK_MEMORY.load(Ordering::Relaxed)
+}
+
+/// Secret key material, wiped from memory when dropped.
+///
+/// `Debug` prints `[REDACTED]` and `Display` is not implemented, so a secret does
+/// not end up in a log by accident; dereference it where the value is needed.
+/// The value lives on the heap, so moving a `Secret` does not leave copies behind.
+///
+/// ```
+/// use onionize::secret::Secret;
+///
+/// let key = Secret::new(String::from("hunter2"));
+/// assert_eq!(format!("{key:?}"), "Secret([REDACTED])");
+/// assert_eq!(key.as_str(), "hunter2");
+/// ```
+pub struct Secret<T: Zeroize + AsRef<[u8]>> {
+    value: Box<T>,
+    locked: bool,
+}
+
+impl<T: Zeroize + AsRef<[u8]>> Secret<T> {
+    /// Takes ownership of `value`, locking it in memory if [`memory_locking`] is on.
+    pub fn new(value: T) -> Self {
+        let value = Box::new(value);
+        let locked = memory_locking() && {
+            let bytes = (*value).as_ref();
+            match lock(bytes) {
+                Ok(()) => true,
+                Err(e) => {
+                    LOCK_WARNING.call_once(|| warn!("{}", t!("secret.lock_failed", error = e)));
+                    false
+                }
+            }
+        };
+        Self { value, locked }
+    }
+}
+
+impl<T: Zeroize + AsRef<[u8]>> Deref for Secret<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T: Zeroize + AsRef<[u8]>> Drop for Secret<T> {
+    fn drop(&mut self) {
+        let bytes = (*self.value).as_ref();
+        let region = (bytes.as_ptr(), bytes.len());
+        self.value.zeroize();
+        if self.locked {
+            // Locks are per page and do not nest, so this may also unlock a
+            // neighbouring secret; it stays wiped on drop either way
+            unlock(region.0, region.1);
+        }
+    }
+}
+
+impl<T: Zeroize + AsRef<[u8]> + Clone> Clone for Secret<T> {
+    fn clone(&self) -> Self {
+        Self::new((*self.value).clone())
+    }
+}
+
+impl<T: Zeroize + AsRef<[u8]>> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret([REDACTED])")
+    }
+}
+
+impl<T: Zeroize + AsRef<[u8]> + PartialEq> PartialEq for Secret<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl<T: Zeroize + AsRef<[u8]> + PartialEq> PartialEq<T> for Secret<T> {
+    fn eq(&self, other: &T) -> bool {
+        *self.value == *other
+    }
+}
+
+impl<T: Zeroize + AsRef<[u8]> + Eq> Eq for Secret<T> {}
+
+impl<T: Zeroize + AsRef<[u8]>> From<T> for Secret<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+#[cfg(unix)]
+fn lock(bytes: &[u8]) -> std::io::Result<()> {
+    if bytes.is_empty() {
+        return Ok(());
+    }
+    // SAFETY: the range is a live allocation owned by the caller
+    match unsafe { libc::mlock(bytes.as_ptr().cast(), bytes.len()) } {
+        0 => Ok(()),
+        _ => Err(std::io::Error::last_os_error()),
+    }
+}
+
+#[cfg(unix)]
+fn unlock(ptr: *const u8, len: usize) {
+    if len > 0 {
+        // SAFETY: munlock only changes the paging of the range, which is still
+        // allocated; it fails harmlessly otherwise
+        unsafe {
+            libc::munlock(ptr.cast(), len);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn lock(_bytes: &[u8]) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(not(unix))]
+fn unlock(_ptr: *const u8, _len: usize) {}
@@ -0,0 +1,199 @@
+// src/selftest.rs
+use crate::error::{Error, Result};
+use crate::events::Event;
+use arti_client::TorClient;
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, StreamExt};
+use safelog::DisplayRedacted;
+use std::time::Duration;
+use tor_hsservice::RunningOnionService;
+use tor_hsservice::status::State;
+use tor_rtcompat::{Runtime, SleepProvider, SleepProviderExt};
+use tracing::debug;
+
+/// How long to wait for the service descriptor to be published.
+const PUBLISH_TIMEOUT: Duration = Duration::from_secs(180);
+
+/// How long to wait for a stream to our own service.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How long to wait for the backend to answer the probe request.
+pub const RESPONSE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Maximum length of the response status line we keep.
+const MAX_STATUS_LINE: usize = 256;
+
+/// The outcome of a successful self-test.
+#[derive(Debug, Clone)]
+pub struct SelfTestReport {
+    /// Time taken to open a stream to the service through Tor.
+    pub connect_time: Duration,
+    /// The backend's answer to the probe request, if it sent one in time.
+    pub response: Option<ProbeResponse>,
+}
+
+/// The first response received from the backend.
+#[derive(Debug, Clone)]
+pub struct ProbeResponse {
+    /// Time from sending the request to receiving the first line of the response.
+    pub time: Duration,
+    /// The first line of the response (e.g., `HTTP/1.1 200 OK`).
+    pub status_line: String,
+}
+
+impl SelfTestReport {
+    /// Converts the report into an event for JSON output.
+    pub fn to_event(&self) -> Event {
+        Event::SelfTest {
+            success: true,
+            connect_ms: Some(self.connect_time.as_millis() as u64),
+            response_ms: self.response.as_ref().map(|r| r.time.as_millis() as u64),
+            status_line: self.response.as_ref().map(|r| r.status_line.clone()),
+            error: None,
+        }
+    }
+}
+
+/// Checks that the service is reachable through Tor.
+///
+/// Waits until the service descriptor is published, then opens a stream to the
+/// service's own onion address on a separate, isolated circuit of `client`,
+/// and sends an HTTP `HEAD` request through it (see [`probe_http`]).
+///
+/// # Arguments
+///
+/// * `client` - The bootstrapped `TorClient` that launched the service.
+/// * `service` - The running onion service.
+/// * `port` - The virtual port to connect to.
+///
+/// # Errors
+///
+/// Returns [`Error::PublishTimeout`] or [`Error::SelfTestTimeout`] if the service does
+/// not become reachable in time, [`Error::ServiceNotRunning`] if it breaks, and
+/// [`Error::NoResponse`] if the stream is closed without any response from the backend.
+pub async fn run_self_test<R: Runtime>(
+    client: &TorClient<R>,
+    service: &RunningOnionService,
+    port: u16,
+) -> Result<SelfTestReport> {
+    let runtime = client.runtime().clone();
+
+    runtime
+        .timeout(PUBLISH_TIMEOUT, wait_until_reachable(service))
+        .await
+        .map_err(|_| Error::PublishTimeout)??;
+
+    let host = service
+        .onion_address()
+        .ok_or(Error::LaunchService(None))?
+        .display_unredacted()
+        .to_string();
+
+    // Use separate circuits, so the test does not ride on the service's own ones.
+    let isolated = client.isolated_client();
+
+    let start = runtime.now();
+    let stream = runtime
+        .timeout(
+            CONNECT_TIMEOUT,
+            crate::tor::connect_onion(&isolated, &host, port),
+        )
+        .await
+        .map_err(|_| Error::SelfTestTimeout)??;
+    let connect_time = runtime.now().saturating_duration_since(start);
+    debug!("Self-test: stream opened in {:?}", connect_time);
+
+    let response = probe_http(&runtime, stream, &host).await?;
+
+    Ok(SelfTestReport {
+        connect_time,
+        response,
+    })
+}
+
+/// Sends an HTTP `HEAD /` request on `stream` and waits for the first response line.
+///
+/// Returns `None` if the backend stays silent for [`RESPONSE_TIMEOUT`] (e.g., it is
+/// not an HTTP server). Any response, even a non-HTTP one, counts as a successful round-trip.
+///
+/// # Errors
+///
+/// Returns an error if the stream fails, or [`Error::NoResponse`] if it is closed
+/// without a response, which usually means the backend is unreachable.
+pub async fn probe_http<R, S>(
+    runtime: &R,
+    mut stream: S,
+    host: &str,
+) -> Result<Option<ProbeResponse>>
+where
+    R: SleepProvider,
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let request = format!("HEAD / HTTP/1.0\r\nHost: {host}\r\nConnection: close\r\n\r\n");
+    stream.write_all(request.as_bytes()).await?;
+    stream.flush().await?;
+
+    let start = runtime.now();
+
+    let status_line = match runtime
+        .timeout(RESPONSE_TIMEOUT, read_first_line(&mut stream))
+        .await
+    {
+        Ok(line) => line?,
+        Err(_) => return Ok(None),
+    };
+
+    Ok(Some(ProbeResponse {
+        time: runtime.now().saturating_duration_since(start),
+        status_line,
+    }))
+}
+
+/// Resolves once the service is believed to be reachable.
+async fn wait_until_reachable(service: &RunningOnionService) -> Result<()> {
+    let mut events = service.status_events();
+    let mut status = service.status();
+
+    loop {
+        match status.state() {
+            State::Running | State::DegradedReachable => return Ok(()),
+            State::Broken | State::Shutdown => {
+                let problem = status
+                    .current_problem()
+                    .map(|p| format!("{p:?}"))
+                    .unwrap_or_default();
+                return Err(Error::ServiceNotRunning(problem));
+            }
+            _ => {}
+        }
+
+        status = events
+            .next()
+            .await
+            .ok_or_else(|| Error::ServiceNotRunning(String::new()))?;
+    }
+}
+
+/// Reads up to the end of the first line (or [`MAX_STATUS_LINE`] bytes).
+async fn read_first_line<S>(stream: &mut S) -> Result<String>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut line = Vec::new();
+    let mut byte = [0u8; 1];
+
+    while line.len() < MAX_STATUS_LINE {
+        if stream.read(&mut byte).await? == 0 {
+            break;
+        }
+        if byte[0] == b'\n' {
+            break;
+        }
+        line.push(byte[0]);
+    }
+
+    if line.is_empty() {
+        return Err(Error::NoResponse);
+    }
+
+    Ok(String::from_utf8_lossy(&line).trim_end().to_string())
+}
@@ -0,0 +1,504 @@
+// src/service.rs
+use crate::backend::{Backend, TcpBackend};
+use crate::error::{Error, Result};
+use crate::events::{Event, EventBus};
+use crate::hooks::{Hooks, StreamHook};
+use crate::keygen;
+use crate::maintenance::MaintenancePage;
+use crate::proxy::{self, PortMap, ProxyOptions, StreamTimeouts};
+use crate::ratelimit::{BandwidthLimits, RateLimiter};
+use crate::shutdown::ShutdownHandle;
+use crate::stats::{StatsSnapshot, TrafficStats};
+use crate::tor::{self, RestrictedClient, TorStatus, TorStatusSnapshot};
+use arti_client::{TorClient, TorClientConfig};
+use futures::future;
+use futures::task::SpawnExt;
+use futures::{Stream, StreamExt};
+use rust_i18n::t;
+use safelog::DisplayRedacted;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tor_hsservice::{RendRequest, RunningOnionService};
+use tor_rtcompat::PreferredRuntime;
+use tracing::{info, warn};
+
+/// Default time [`ServiceHandle::shutdown`] lets active streams finish, as `--grace-period`.
+const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(30);
+
+/// An onion service exposing local targets, ready to be launched.
+///
+/// This is the library counterpart of the `onionize` command: it bootstraps Tor,
+/// launches the service and proxies its streams in the background.
+///
+/// ```no_run
+/// # async fn example() -> anyhow::Result<()> {
+/// use onionize::Onionizer;
+///
+/// let service = Onionizer::builder()
+///     .target("127.0.0.1:8080")
+///     .build()?
+///     .launch()
+///     .await?;
+/// println!("Serving at http://{}", service.onion_address());
+///
+/// service.shutdown().await;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Launching must happen inside a Tokio runtime.
+#[derive(Clone)]
+pub struct Onionizer {
+    target: Target,
+    ports: Vec<(u16, String)>,
+    mapped_ports_only: bool,
+    nickname: Option<String>,
+    clients: Vec<RestrictedClient>,
+    tor_config: Option<TorClientConfig>,
+    tor_client: Option<TorClient<PreferredRuntime>>,
+    timeouts: StreamTimeouts,
+    bandwidth_limits: BandwidthLimits,
+    circuit_limits: BandwidthLimits,
+    maintenance: Option<Arc<MaintenancePage>>,
+    hooks: Hooks,
+    grace_period: Duration,
+}
+
+impl Onionizer {
+    /// Returns a builder for a service.
+    pub fn builder() -> OnionizerBuilder {
+        OnionizerBuilder::default()
+    }
+
+    /// Bootstraps Tor (unless a client was given), launches the onion service and
+    /// starts proxying its streams.
+    ///
+    /// Each call launches a separate service; without a nickname, each one gets a new address.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no Tokio runtime, Tor cannot bootstrap or the
+    /// service cannot be launched.
+    pub async fn launch(&self) -> Result<ServiceHandle> {
+        let runtime = PreferredRuntime::current().map_err(Error::Runtime)?;
+
+        let tor_client = match &self.tor_client {
+            Some(tor_client) => tor_client.clone(),
+            None => tor::create_tor_client(runtime.clone(), self.tor_config.clone())?,
+        };
+        // Stops the proxy loop and the watchers below; a shared client outlives the
+        // service, so watching its bootstrap has to stop with the service too
+        let shutdown = ShutdownHandle::new();
+        // Watched before bootstrapping, so the status shows the progress
+        let tor_status = TorStatus::new();
+        let watch = tor::watch_bootstrap(tor_client.clone(), tor_status.clone());
+        let stopped = shutdown.clone();
+        runtime.spawn(async move {
+            future::select(Box::pin(watch), Box::pin(stopped.wait())).await;
+        })?;
+        if self.tor_client.is_none()
+            && let Err(e) = tor::bootstrap_tor_client(&tor_client).await
+        {
+            shutdown.shutdown();
+            return Err(e);
+        }
+
+        // Checked before launching, so an invalid target leaves no keys behind
+        let ports = PortMap::new();
+        for (port, target) in &self.ports {
+            if let Err(e) = ports.insert(*port, target) {
+                shutdown.shutdown();
+                return Err(e);
+            }
+        }
+
+        // Without a nickname, the identity only lives as long as the service
+        let ephemeral = self.nickname.is_none();
+        let nickname = self.nickname.clone().unwrap_or_else(|| {
+            let random_bytes = rand::random::<[u8; 3]>();
+            format!("onionize-{}", hex::encode(random_bytes))
+        });
+        let launched = if ephemeral {
+            let identity = keygen::generate_service_identity();
+            tor::launch_onion_service_with_identity(&tor_client, &nickname, &self.clients, identity)
+                .await
+                .map(|(service, requests)| (service, requests.boxed()))
+        } else {
+            tor::launch_onion_service_with_clients(&tor_client, &nickname, &self.clients)
+                .await
+                .map(|(service, requests)| (service, requests.boxed()))
+        };
+
+        let events = EventBus::new();
+        let stats = TrafficStats::new();
+        let options = ProxyOptions {
+            timeouts: self.timeouts,
+            maintenance: self.maintenance.clone(),
+            rate_limiter: RateLimiter::new(self.bandwidth_limits),
+            circuit_limits: self.circuit_limits,
+            stats: stats.clone(),
+            ports: ports.clone(),
+            mapped_ports_only: self.mapped_ports_only,
+            events: events.clone(),
+            hooks: self.hooks.clone(),
+        };
+        let started = match launched {
+            Ok((service, requests)) => self
+                .start(
+                    &runtime,
+                    &service,
+                    requests,
+                    options,
+                    &shutdown,
+                    &tor_status,
+                )
+                .map(|onion_address| (service, onion_address)),
+            Err(e) => Err(e),
+        };
+        // The service, if any, is dropped before its keys are removed
+        let (service, onion_address) = match started {
+            Ok(started) => started,
+            Err(e) => {
+                shutdown.shutdown();
+                if ephemeral {
+                    remove_ephemeral_keys(&tor_client, &nickname);
+                }
+                return Err(e);
+            }
+        };
+
+        info!("{}", t!("main.o_created", o_addr = onion_address));
+        events.publish(&Event::ServiceReady {
+            onion_address: onion_address.clone(),
+            credential: None,
+        });
+
+        Ok(ServiceHandle {
+            onion_address,
+            nickname,
+            ephemeral,
+            service: Some(service),
+            tor_client,
+            runtime,
+            shutdown,
+            stats,
+            tor_status,
+            ports,
+            events,
+            grace_period: self.grace_period,
+        })
+    }
+
+    /// Starts watching and proxying the launched `service`, and returns its onion address.
+    fn start(
+        &self,
+        runtime: &PreferredRuntime,
+        service: &Arc<RunningOnionService>,
+        requests: impl Stream<Item = RendRequest> + Send + Unpin + 'static,
+        options: ProxyOptions,
+        shutdown: &ShutdownHandle,
+        tor_status: &TorStatus,
+    ) -> Result<String> {
+        runtime.spawn(tor::watch_service(service.clone(), tor_status.clone()))?;
+
+        let onion_address = service
+            .onion_address()
+            .ok_or(Error::LaunchService(None))?
+            .display_unredacted()
+            .to_string();
+
+        let backend: Arc<dyn Backend> = match &self.target {
+            Target::Address(address) => Arc::new(TcpBackend::new(runtime.clone(), address)),
+            Target::Backend(backend) => backend.clone(),
+        };
+        let loop_rt = runtime.clone();
+        let loop_shutdown = shutdown.clone();
+        runtime.spawn(async move {
+            proxy::run_backend_loop(loop_rt, requests, backend, options, loop_shutdown).await;
+        })?;
+
+        Ok(onion_address)
+    }
+}
+
+/// Builder of an [`Onionizer`], see [`Onionizer::builder`].
+#[derive(Default)]
+pub struct OnionizerBuilder {
+    target: Option<Target>,
+    ports: Vec<(u16, String)>,
+    mapped_ports_only: bool,
+    nickname: Option<String>,
+    client_keys: Vec<String>,
+    tor_config: Option<TorClientConfig>,
+    tor_client: Option<TorClient<PreferredRuntime>>,
+    timeouts: StreamTimeouts,
+    bandwidth_limits: BandwidthLimits,
+    circuit_limits: BandwidthLimits,
+    maintenance: Option<MaintenancePage>,
+    hooks: Hooks,
+    grace_period: Option<Duration>,
+}
+
+impl OnionizerBuilder {
+    /// Sets the local address every virtual port is forwarded to (e.g., "127.0.0.1:8080").
+    pub fn target(mut self, target: impl Into<String>) -> Self {
+        self.target = Some(Target::Address(target.into()));
+        self
+    }
+
+    /// Sends every virtual port to `backend` instead of a local address, e.g. a
+    /// [`HandlerBackend`](crate::backend::HandlerBackend) serving streams in this process.
+    ///
+    /// Replaces the target set with [`Self::target`].
+    pub fn backend(mut self, backend: impl Backend + 'static) -> Self {
+        self.target = Some(Target::Backend(Arc::new(backend)));
+        self
+    }
+
+    /// Forwards virtual `port` to `target` instead of the default target.
+    ///
+    /// Without a default target, only the mapped ports are served.
+    pub fn map_port(mut self, port: u16, target: impl Into<String>) -> Self {
+        self.ports.push((port, target.into()));
+        self
+    }
+
+    /// Refuses streams to virtual ports that are not mapped with [`Self::map_port`].
+    pub fn mapped_ports_only(mut self, enabled: bool) -> Self {
+        self.mapped_ports_only = enabled;
+        self
+    }
+
+    /// Sets the nickname of the service.
+    ///
+    /// The keys of the service are kept in Tor's state directory under this nickname,
+    /// so launching with the same nickname gives the same onion address. Without a
+    /// nickname, the service gets a new identity on every launch, which is removed
+    /// from the keystore once the service stops.
+    pub fn nickname(mut self, nickname: impl Into<String>) -> Self {
+        self.nickname = Some(nickname.into());
+        self
+    }
+
+    /// Keeps Tor's state (including the service keys) and cache in `dir`
+    /// instead of the user's data directories.
+    pub fn state_dir(mut self, dir: impl AsRef<Path>) -> Self {
+        let dir = dir.as_ref();
+        self.tor_config = Some(tor::storage_config(&dir.join("cache"), &dir.join("state")));
+        self
+    }
+
+    /// Uses `config` to start the Tor client.
+    pub fn tor_config(mut self, config: TorClientConfig) -> Self {
+        self.tor_config = Some(config);
+        self
+    }
+
+    /// Uses an already bootstrapped Tor client, e.g. to launch several services.
+    pub fn tor_client(mut self, client: TorClient<PreferredRuntime>) -> Self {
+        self.tor_client = Some(client);
+        self
+    }
+
+    /// Authorizes a client (format: `descriptor:x25519:<key>`), which restricts
+    /// discovery of the service to the authorized clients.
+    pub fn authorize(mut self, key: impl Into<String>) -> Self {
+        self.client_keys.push(key.into());
+        self
+    }
+
+    /// Sets the time limits of every stream.
+    pub fn timeouts(mut self, timeouts: StreamTimeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Limits the bandwidth of the whole service.
+    pub fn bandwidth_limits(mut self, limits: BandwidthLimits) -> Self {
+        self.bandwidth_limits = limits;
+        self
+    }
+
+    /// Limits the bandwidth of each rendezvous circuit.
+    pub fn circuit_limits(mut self, limits: BandwidthLimits) -> Self {
+        self.circuit_limits = limits;
+        self
+    }
+
+    /// Sends `page` to visitors when the local target is down.
+    pub fn maintenance(mut self, page: MaintenancePage) -> Self {
+        self.maintenance = Some(page);
+        self
+    }
+
+    /// Adds a hook called on every circuit and stream, after the hooks added before.
+    pub fn hook(mut self, hook: impl StreamHook + 'static) -> Self {
+        self.hooks.add(hook);
+        self
+    }
+
+    /// Sets how long [`ServiceHandle::shutdown`] lets active streams finish (30 seconds by default).
+    pub fn grace_period(mut self, grace_period: Duration) -> Self {
+        self.grace_period = Some(grace_period);
+        self
+    }
+
+    /// Checks the settings and returns the service, ready to be launched.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no target, a target is not an IP address and a
+    /// port, or a client key is invalid.
+    pub fn build(self) -> Result<Onionizer> {
+        for (_, target) in &self.ports {
+            check_target(target)?;
+        }
+        let (target, mapped_ports_only) = match self.target {
+            Some(target) => {
+                if let Target::Address(address) = &target {
+                    check_target(address)?;
+                }
+                (target, self.mapped_ports_only)
+            }
+            // Only the mapped ports are served
+            None => match self.ports.first() {
+                Some((_, target)) => (Target::Address(target.clone()), true),
+                None => return Err(Error::NoTarget),
+            },
+        };
+
+        let clients = self
+            .client_keys
+            .iter()
+            .map(|key| RestrictedClient::new(key, None))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Onionizer {
+            target,
+            ports: self.ports,
+            mapped_ports_only,
+            nickname: self.nickname,
+            clients,
+            tor_config: self.tor_config,
+            tor_client: self.tor_client,
+            timeouts: self.timeouts,
+            bandwidth_limits: self.bandwidth_limits,
+            circuit_limits: self.circuit_limits,
+            maintenance: self.maintenance.map(Arc::new),
+            hooks: self.hooks,
+            grace_period: self.grace_period.unwrap_or(DEFAULT_GRACE_PERIOD),
+        })
+    }
+}
+
+/// Where the streams of a service go by default.
+#[derive(Clone)]
+enum Target {
+    /// A local TCP address.
+    Address(String),
+    /// Any other backend.
+    Backend(Arc<dyn Backend>),
+}
+
+/// Checks that `target` is an IP address and a port.
+fn check_target(target: &str) -> Result<()> {
+    target
+        .parse::<SocketAddr>()
+        .map_err(|source| Error::InvalidTarget {
+            target: target.to_string(),
+            source,
+        })?;
+    Ok(())
+}
+
+/// A running onion service, returned by [`Onionizer::launch`].
+///
+/// Dropping the handle without calling [`ServiceHandle::shutdown`] stops accepting
+/// streams and publishing the service right away; active streams are not waited for.
+pub struct ServiceHandle {
+    onion_address: String,
+    nickname: String,
+    /// The identity was generated for this launch and is removed on drop.
+    ephemeral: bool,
+    service: Option<Arc<RunningOnionService>>,
+    tor_client: TorClient<PreferredRuntime>,
+    runtime: PreferredRuntime,
+    shutdown: ShutdownHandle,
+    stats: TrafficStats,
+    tor_status: TorStatus,
+    ports: PortMap,
+    events: EventBus,
+    grace_period: Duration,
+}
+
+impl ServiceHandle {
+    /// Returns the address of the service, ending with `.onion`.
+    pub fn onion_address(&self) -> &str {
+        &self.onion_address
+    }
+
+    /// Returns the nickname the service was launched with.
+    pub fn nickname(&self) -> &str {
+        &self.nickname
+    }
+
+    /// Returns a stream of the events of the service (e.g., closed streams) from now on.
+    pub fn events(&self) -> impl Stream<Item = Event> + use<> {
+        self.events.subscribe()
+    }
+
+    /// Returns the traffic counters of the service.
+    pub fn stats(&self) -> StatsSnapshot {
+        self.stats.snapshot()
+    }
+
+    /// Returns the bootstrap progress and the state of the service.
+    pub fn tor_status(&self) -> TorStatusSnapshot {
+        self.tor_status.snapshot()
+    }
+
+    /// Returns the port mappings, which can be changed while the service is running.
+    pub fn ports(&self) -> &PortMap {
+        &self.ports
+    }
+
+    /// Returns the number of streams being proxied.
+    pub fn active_streams(&self) -> usize {
+        self.shutdown.active_streams()
+    }
+
+    /// Returns the Tor client, e.g. to launch another service with [`OnionizerBuilder::tor_client`].
+    pub fn tor_client(&self) -> &TorClient<PreferredRuntime> {
+        &self.tor_client
+    }
+
+    /// Stops accepting streams, waits up to the grace period for the active ones
+    /// to finish and stops the service.
+    ///
+    /// Returns `false` if streams were still active when the grace period expired.
+    pub async fn shutdown(self) -> bool {
+        self.shutdown.shutdown();
+        // The service stops when the handle is dropped
+        self.shutdown.drain(&self.runtime, self.grace_period).await
+    }
+}
+
+impl Drop for ServiceHandle {
+    fn drop(&mut self) {
+        // Stops the proxy loop, which would otherwise keep serving streams
+        self.shutdown.shutdown();
+        drop(self.service.take());
+        if self.ephemeral {
+            remove_ephemeral_keys(&self.tor_client, &self.nickname);
+        }
+    }
+}
+
+/// Removes the keys of a service launched without a nickname, logging a failure.
+fn remove_ephemeral_keys(tor_client: &TorClient<PreferredRuntime>, nickname: &str) {
+    if let Err(e) = tor::remove_service_keys(tor_client, nickname) {
+        warn!("{}", t!("service.errors.remove_keys", err = e));
+    }
+}
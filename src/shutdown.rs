@@ -0,0 +1,142 @@
+// src/shutdown.rs
+use futures::FutureExt;
+use futures::channel::oneshot;
+use futures::future::{self, Shared};
+use rust_i18n::t;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tor_rtcompat::SleepProvider;
+use tracing::info;
+
+/// How often the remaining grace period is reported while draining.
+const DRAIN_TICK: Duration = Duration::from_secs(1);
+
+/// Stops the serving loops and keeps track of active streams.
+///
+/// Clones share the same state: calling [`ShutdownHandle::shutdown`] on any clone
+/// stops every loop that was given one of them.
+#[derive(Clone, Debug)]
+pub struct ShutdownHandle {
+    trigger: Arc<Mutex<Option<oneshot::Sender<()>>>>,
+    signal: Shared<oneshot::Receiver<()>>,
+    active: Arc<AtomicUsize>,
+    parent: Option<Box<ShutdownHandle>>,
+}
+
+/// Marks a stream as active until dropped.
+#[derive(Debug)]
+pub struct ActiveStream {
+    active: Arc<AtomicUsize>,
+}
+
+impl Drop for ActiveStream {
+    fn drop(&mut self) {
+        self.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Default for ShutdownHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShutdownHandle {
+    /// Creates a new handle with no active streams.
+    pub fn new() -> Self {
+        let (tx, rx) = oneshot::channel();
+        Self {
+            trigger: Arc::new(Mutex::new(Some(tx))),
+            signal: rx.shared(),
+            active: Arc::new(AtomicUsize::new(0)),
+            parent: None,
+        }
+    }
+
+    /// Creates a handle that can be shut down on its own (e.g., to stop a single service).
+    ///
+    /// The child is also shut down along with `self`, and its active streams count
+    /// towards `self`, so draining `self` waits for them too.
+    pub fn child(&self) -> Self {
+        let (tx, rx) = oneshot::channel();
+        Self {
+            trigger: Arc::new(Mutex::new(Some(tx))),
+            signal: rx.shared(),
+            active: self.active.clone(),
+            parent: Some(Box::new(self.clone())),
+        }
+    }
+
+    /// Requests shutdown: the serving loops stop accepting new streams.
+    ///
+    /// Streams that are already active keep running.
+    pub fn shutdown(&self) {
+        if let Some(tx) = self.trigger.lock().expect("poisoned lock").take() {
+            let _ = tx.send(());
+        }
+    }
+
+    /// Returns `true` if shutdown has been requested.
+    pub fn is_shutdown(&self) -> bool {
+        self.trigger.lock().expect("poisoned lock").is_none()
+            || self
+                .parent
+                .as_ref()
+                .is_some_and(|parent| parent.is_shutdown())
+    }
+
+    /// Resolves once shutdown has been requested.
+    pub async fn wait(&self) {
+        match &self.parent {
+            Some(parent) => {
+                let _ = future::select(self.signal.clone(), Box::pin(parent.wait())).await;
+            }
+            None => {
+                let _ = self.signal.clone().await;
+            }
+        }
+    }
+
+    /// Returns the number of streams that are still active.
+    pub fn active_streams(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    /// Registers an active stream. It counts as active until the guard is dropped.
+    pub fn track(&self) -> ActiveStream {
+        self.active.fetch_add(1, Ordering::SeqCst);
+        ActiveStream {
+            active: self.active.clone(),
+        }
+    }
+
+    /// Waits up to `grace` for active streams to finish, reporting the countdown.
+    ///
+    /// Returns `true` if every stream finished in time.
+    pub async fn drain<R: SleepProvider>(&self, runtime: &R, grace: Duration) -> bool {
+        let deadline = runtime.now() + grace;
+
+        loop {
+            let active = self.active_streams();
+            if active == 0 {
+                return true;
+            }
+
+            let remaining = deadline.saturating_duration_since(runtime.now());
+            if remaining.is_zero() {
+                return false;
+            }
+
+            info!(
+                "{}",
+                t!(
+                    "shutdown.draining",
+                    active = active,
+                    secs = remaining.as_secs_f64().ceil() as u64
+                )
+            );
+            runtime.sleep(remaining.min(DRAIN_TICK)).await;
+        }
+    }
+}
@@ -0,0 +1,206 @@
+// src/socks.rs
+use crate::error::{Error, Result};
+use arti_client::isolation::IsolationHelper;
+use arti_client::{DataStream, StreamPrefs, TorClient};
+use futures::task::SpawnExt;
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, StreamExt};
+use rust_i18n::t;
+use std::net::SocketAddr;
+use tor_rtcompat::{NetStreamListener, Runtime};
+use tor_socksproto::{
+    Handshake, NextStep, SocksAuth, SocksCmd, SocksProxyHandshake, SocksRequest, SocksStatus,
+};
+use tracing::{debug, info, warn};
+
+/// Stream isolation derived from the SOCKS credentials of a request.
+///
+/// Streams opened with different username/password pairs never share a circuit,
+/// which mirrors the `IsolateSOCKSAuth` behaviour of C-tor and Tor Browser.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SocksIsolation(SocksAuth);
+
+impl IsolationHelper for SocksIsolation {
+    fn compatible_same_type(&self, other: &Self) -> bool {
+        self == other
+    }
+
+    fn join_same_type(&self, other: &Self) -> Option<Self> {
+        self.compatible_same_type(other).then(|| self.clone())
+    }
+}
+
+/// A stream requested by a SOCKS client.
+#[derive(Clone, Debug)]
+pub struct SocksTarget {
+    /// The hostname (or IP address) to connect to.
+    pub host: String,
+    /// The port to connect to.
+    pub port: u16,
+    /// Which other streams may share a circuit with this one.
+    pub isolation: SocksIsolation,
+}
+
+/// Runs a SOCKS proxy on `local_addr`.
+///
+/// For every accepted client, the SOCKS handshake is performed, the requested
+/// stream is opened via `dial` and data is copied in both directions.
+///
+/// # Arguments
+///
+/// * `runtime` - The runtime used to listen locally and spawn tasks.
+/// * `local_addr` - The local address to listen on (e.g., "127.0.0.1:9150").
+/// * `dial` - Opens the requested stream (see [`connect_via_tor`]).
+///
+/// # Errors
+///
+/// Returns an error if the local listener cannot be created.
+pub async fn run_socks_loop<R, D, Fut, S>(runtime: R, local_addr: SocketAddr, dial: D) -> Result<()>
+where
+    R: Runtime,
+    D: Fn(SocksTarget) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = Result<S>> + Send + 'static,
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let listener = runtime
+        .listen(&local_addr)
+        .await
+        .map_err(|source| Error::SocksListen {
+            address: local_addr,
+            source,
+        })?;
+
+    info!("{}", t!("socks.listening", local = local_addr));
+
+    let mut incoming = listener.incoming();
+
+    while let Some(accepted) = incoming.next().await {
+        let (local_stream, peer) = match accepted {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("{}", t!("socks.errors.accept", err = e));
+                continue;
+            }
+        };
+
+        debug!("SOCKS connection from {}", peer);
+
+        let dial = dial.clone();
+        let spawn_res = runtime.spawn(async move {
+            if let Err(e) = handle_socks_connection(local_stream, dial).await {
+                debug!(
+                    "{}",
+                    t!(
+                        "socks.errors.connection",
+                        error = format!("{:#}", anyhow::Error::from(e))
+                    )
+                );
+            }
+        });
+
+        if let Err(e) = spawn_res {
+            warn!("{}: {}", t!("socks.errors.task"), e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Opens the stream requested by a SOCKS client through Tor.
+///
+/// Streams are isolated from each other according to [`SocksTarget::isolation`].
+pub async fn connect_via_tor<R: Runtime>(
+    client: &TorClient<R>,
+    target: SocksTarget,
+) -> Result<DataStream> {
+    let mut prefs = StreamPrefs::new();
+    prefs.set_isolation(target.isolation);
+
+    client
+        .connect_with_prefs((target.host.as_str(), target.port), &prefs)
+        .await
+        .map_err(|source| Error::OpenStream {
+            target: format!("{}:{}", target.host, target.port),
+            source,
+        })
+}
+
+/// Serves a single SOCKS client: handshake, dial, reply and bidirectional copy.
+async fn handle_socks_connection<S, D, Fut, T>(mut local_stream: S, dial: D) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    D: Fn(SocksTarget) -> Fut,
+    Fut: Future<Output = Result<T>>,
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let request = read_request(&mut local_stream).await?;
+
+    if request.command() != SocksCmd::CONNECT {
+        send_reply(
+            &mut local_stream,
+            &request,
+            SocksStatus::COMMAND_NOT_SUPPORTED,
+        )
+        .await?;
+        return Err(Error::UnsupportedSocksCommand(
+            request.command().to_string(),
+        ));
+    }
+
+    let target = SocksTarget {
+        host: request.addr().to_string(),
+        port: request.port(),
+        isolation: SocksIsolation(request.auth().clone()),
+    };
+    debug!("SOCKS request for {}:{}", target.host, target.port);
+
+    let remote_stream = match dial(target).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            send_reply(&mut local_stream, &request, SocksStatus::GENERAL_FAILURE).await?;
+            return Err(e);
+        }
+    };
+
+    send_reply(&mut local_stream, &request, SocksStatus::SUCCEEDED).await?;
+
+    let (up, down) = crate::proxy::copy_bidirectional(local_stream, remote_stream).await?;
+    debug!("SOCKS stream closed. Up: {} B, Down: {} B", up, down);
+    Ok(())
+}
+
+/// Performs the server side of the SOCKS handshake and returns the client's request.
+async fn read_request<S>(stream: &mut S) -> Result<SocksRequest>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut handshake = SocksProxyHandshake::new();
+    let mut buf = tor_socksproto::Buffer::new_precise();
+
+    loop {
+        match handshake.step(&mut buf).map_err(Error::Socks)? {
+            NextStep::Send(data) => {
+                stream.write_all(&data).await?;
+                stream.flush().await?;
+            }
+            NextStep::Recv(mut recv) => {
+                let n = stream.read(recv.buf()).await?;
+                recv.note_received(n).map_err(Error::Socks)?;
+            }
+            NextStep::Finished(finished) => {
+                return finished.into_output().map_err(|e| Error::Socks(e.into()));
+            }
+        }
+    }
+}
+
+async fn send_reply<S>(stream: &mut S, request: &SocksRequest, status: SocksStatus) -> Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let reply = request
+        .reply(status, None)
+        .map_err(|e| Error::Socks(tor_socksproto::Error::Bug(e.into())))?;
+    stream.write_all(&reply).await?;
+    stream.flush().await?;
+    Ok(())
+}
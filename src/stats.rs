@@ -0,0 +1,239 @@
+// src/stats.rs
+use crate::proxy::CloseReason;
+use crate::ratelimit::Direction;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Traffic counters of a service and of its rendezvous circuits.
+///
+/// Clones share the same counters. A clone returned by [`TrafficStats::for_circuit`]
+/// additionally updates the counters of its circuit, which is removed from the
+/// statistics once the last such clone is dropped.
+#[derive(Debug, Clone, Default)]
+pub struct TrafficStats {
+    service: Arc<ServiceCounters>,
+    circuit: Option<Arc<CircuitGuard>>,
+}
+
+#[derive(Debug, Default)]
+struct ServiceCounters {
+    next_circuit: AtomicU64,
+    circuits_total: AtomicU64,
+    streams_total: AtomicU64,
+    streams_active: AtomicU64,
+    rejected: AtomicU64,
+    bytes_up: AtomicU64,
+    bytes_down: AtomicU64,
+    connects: AtomicU64,
+    connect_total_ms: AtomicU64,
+    connect_max_ms: AtomicU64,
+    closed: Mutex<BTreeMap<CloseReason, u64>>,
+    circuits: Mutex<BTreeMap<u64, CircuitSnapshot>>,
+}
+
+/// Removes its circuit from the statistics when dropped.
+#[derive(Debug)]
+struct CircuitGuard {
+    service: Arc<ServiceCounters>,
+    id: u64,
+}
+
+impl Drop for CircuitGuard {
+    fn drop(&mut self) {
+        self.service
+            .circuits
+            .lock()
+            .expect("poisoned lock")
+            .remove(&self.id);
+    }
+}
+
+/// Point-in-time copy of the statistics.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct StatsSnapshot {
+    /// Rendezvous circuits that are currently open.
+    pub circuits_active: u64,
+    /// Rendezvous circuits opened since launch.
+    pub circuits_total: u64,
+    /// Streams that are currently open.
+    pub streams_active: u64,
+    /// Streams opened since launch.
+    pub streams_total: u64,
+    /// Rendezvous or stream requests that could not be accepted.
+    pub requests_rejected: u64,
+    /// Bytes sent from Tor clients to the local service.
+    pub bytes_up: u64,
+    /// Bytes sent from the local service to Tor clients.
+    pub bytes_down: u64,
+    /// Successful connections to the local service.
+    pub backend_connects: u64,
+    /// Total time spent connecting to the local service, in milliseconds.
+    pub backend_connect_total_ms: u64,
+    /// Average time to connect to the local service, in milliseconds.
+    pub backend_connect_avg_ms: Option<u64>,
+    /// Longest time to connect to the local service, in milliseconds.
+    pub backend_connect_max_ms: u64,
+    /// Closed streams by reason.
+    pub closed: BTreeMap<CloseReason, u64>,
+    /// Open circuits, oldest first.
+    pub circuits: Vec<CircuitSnapshot>,
+}
+
+/// Counters of a single rendezvous circuit.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct CircuitSnapshot {
+    /// Sequential number of the circuit since launch.
+    pub id: u64,
+    /// Streams that are currently open on the circuit.
+    pub streams_active: u64,
+    /// Streams opened on the circuit.
+    pub streams_total: u64,
+    /// Bytes sent from the Tor client to the local service.
+    pub bytes_up: u64,
+    /// Bytes sent from the local service to the Tor client.
+    pub bytes_down: u64,
+}
+
+impl StatsSnapshot {
+    /// Returns the number of streams closed for any reason other than completion.
+    pub fn errors(&self) -> u64 {
+        self.closed
+            .iter()
+            .filter(|(reason, _)| **reason != CloseReason::Completed)
+            .map(|(_, count)| count)
+            .sum()
+    }
+}
+
+impl TrafficStats {
+    /// Creates empty statistics.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a new rendezvous circuit and returns statistics that also count towards it.
+    pub fn for_circuit(&self) -> Self {
+        let id = self.service.next_circuit.fetch_add(1, Ordering::Relaxed) + 1;
+        self.service.circuits_total.fetch_add(1, Ordering::Relaxed);
+        self.service.circuits.lock().expect("poisoned lock").insert(
+            id,
+            CircuitSnapshot {
+                id,
+                ..Default::default()
+            },
+        );
+
+        Self {
+            service: self.service.clone(),
+            circuit: Some(Arc::new(CircuitGuard {
+                service: self.service.clone(),
+                id,
+            })),
+        }
+    }
+
+    /// Returns the sequential number of the circuit, for statistics returned by
+    /// [`Self::for_circuit`].
+    pub fn circuit_id(&self) -> Option<u64> {
+        self.circuit.as_ref().map(|guard| guard.id)
+    }
+
+    /// Records a newly opened stream.
+    pub fn stream_opened(&self) {
+        self.service.streams_total.fetch_add(1, Ordering::Relaxed);
+        self.service.streams_active.fetch_add(1, Ordering::Relaxed);
+        self.update_circuit(|circuit| {
+            circuit.streams_total += 1;
+            circuit.streams_active += 1;
+        });
+    }
+
+    /// Records a rendezvous or stream request that could not be accepted.
+    pub fn request_rejected(&self) {
+        self.service.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a closed stream and why it was closed.
+    pub fn stream_closed(&self, reason: CloseReason) {
+        self.service.streams_active.fetch_sub(1, Ordering::Relaxed);
+        *self
+            .service
+            .closed
+            .lock()
+            .expect("poisoned lock")
+            .entry(reason)
+            .or_default() += 1;
+        self.update_circuit(|circuit| {
+            circuit.streams_active = circuit.streams_active.saturating_sub(1);
+        });
+    }
+
+    /// Records a successful connection to the local service and how long it took.
+    pub fn backend_connected(&self, latency: Duration) {
+        let ms = latency.as_millis() as u64;
+        self.service.connects.fetch_add(1, Ordering::Relaxed);
+        self.service
+            .connect_total_ms
+            .fetch_add(ms, Ordering::Relaxed);
+        self.service.connect_max_ms.fetch_max(ms, Ordering::Relaxed);
+    }
+
+    /// Records `bytes` transferred in `direction`.
+    pub fn transferred(&self, direction: Direction, bytes: usize) {
+        let bytes = bytes as u64;
+        match direction {
+            Direction::Upload => self.service.bytes_up.fetch_add(bytes, Ordering::Relaxed),
+            Direction::Download => self.service.bytes_down.fetch_add(bytes, Ordering::Relaxed),
+        };
+        self.update_circuit(|circuit| match direction {
+            Direction::Upload => circuit.bytes_up += bytes,
+            Direction::Download => circuit.bytes_down += bytes,
+        });
+    }
+
+    /// Returns a copy of the current statistics.
+    pub fn snapshot(&self) -> StatsSnapshot {
+        let service = &self.service;
+        let connects = service.connects.load(Ordering::Relaxed);
+        let connect_total_ms = service.connect_total_ms.load(Ordering::Relaxed);
+        let circuits: Vec<CircuitSnapshot> = service
+            .circuits
+            .lock()
+            .expect("poisoned lock")
+            .values()
+            .cloned()
+            .collect();
+
+        StatsSnapshot {
+            circuits_active: circuits.len() as u64,
+            circuits_total: service.circuits_total.load(Ordering::Relaxed),
+            streams_active: service.streams_active.load(Ordering::Relaxed),
+            streams_total: service.streams_total.load(Ordering::Relaxed),
+            requests_rejected: service.rejected.load(Ordering::Relaxed),
+            bytes_up: service.bytes_up.load(Ordering::Relaxed),
+            bytes_down: service.bytes_down.load(Ordering::Relaxed),
+            backend_connects: connects,
+            backend_connect_total_ms: connect_total_ms,
+            backend_connect_avg_ms: (connects > 0).then(|| connect_total_ms / connects),
+            backend_connect_max_ms: service.connect_max_ms.load(Ordering::Relaxed),
+            closed: service.closed.lock().expect("poisoned lock").clone(),
+            circuits,
+        }
+    }
+
+    fn update_circuit(&self, update: impl FnOnce(&mut CircuitSnapshot)) {
+        if let Some(guard) = &self.circuit
+            && let Some(circuit) = self
+                .service
+                .circuits
+                .lock()
+                .expect("poisoned lock")
+                .get_mut(&guard.id)
+        {
+            update(circuit);
+        }
+    }
+}
@@ -1,29 +1,40 @@
 // src/tor.rs
-use anyhow::{Context, Result};
+use crate::error::{Error, Result};
+use crate::keygen::ServiceIdentity;
 use arti_client::config::CfgPath;
-use arti_client::{TorClient, TorClientConfig};
+use arti_client::{KeystoreSelector, TorClient, TorClientConfig};
 use directories::ProjectDirs;
 use futures::StreamExt;
 use indicatif::{ProgressBar, ProgressStyle};
 use rust_i18n::t;
-use tor_hscrypto::pk::HsClientDescEncKey;
+use tor_hscrypto::pk::{HsClientDescEncKey, HsClientDescEncSecretKey, HsId};
 use tor_hsservice::config::restricted_discovery::HsClientNickname; // Type for the nickname
 //use tor_hsservice::config::restricted_discovery::RestrictedDiscoveryConfigBuilder; // Config builder
-use tor_hsservice::{HsNickname, RunningOnionService, config::OnionServiceConfigBuilder}; // Public key type
+use tor_hsservice::{
+    HsNickname, OnionServiceConfig, RunningOnionService, config::OnionServiceConfigBuilder,
+}; // Public key type
 
 use rand::RngExt;
+use serde::Serialize;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tor_hsservice::status::{Problem, State};
+use tor_keymgr::KeyPathPattern;
+use tor_llcrypto::pk::curve25519;
 use tor_rtcompat::Runtime;
 use tracing::info;
 
 /// Bootstraps a connection to the Tor network.
 ///
 /// This function initializes the Arti client, sets up a progress bar to track the
-/// bootstrap status, and waits until the client is ready for traffic.
+/// bootstrap status, and waits until the client is ready for traffic. It is
+/// [`create_tor_client`] followed by [`bootstrap_tor_client`].
 ///
 /// # Arguments
 ///
 /// * `runtime` - The asynchronous runtime (e.g., Tokio) to use for the client.
-/// * `_config` - Optional configuration overrides (currently unused/reserved).
+/// * `config` - Configuration to use instead of the default one, which keeps the
+///   state and cache in the user's data directories.
 ///
 /// # Returns
 ///
@@ -32,33 +43,52 @@ use tracing::info;
 /// # Errors
 ///
 /// This function will return an error if:
-/// * The project directories cannot be determined.
-/// * The Tor bootstrap process encounters a network or configuration error.
+/// * The project directories cannot be determined ([`Error::ProjectDirs`]).
+/// * The Tor bootstrap process encounters a network or configuration error
+///   ([`Error::Bootstrap`]).
 pub async fn start_tor_client<R: Runtime>(
     runtime: R,
-    _config: Option<TorClientConfig>,
+    config: Option<TorClientConfig>,
 ) -> Result<TorClient<R>> {
-    info!("{}", t!("tor.starting_tor_client"));
-
-    let dirs = ProjectDirs::from("", "", "arti-onion-proxy").context(t!("tor.errors.dirs"))?;
-    // Path -> String -> CfgPath
-    let cache_dir = CfgPath::new(dirs.cache_dir().to_string_lossy().into());
-    let data_dir = CfgPath::new(dirs.data_dir().to_string_lossy().into());
+    let tor_client = create_tor_client(runtime, config)?;
+    bootstrap_tor_client(&tor_client).await?;
+    Ok(tor_client)
+}
 
-    let mut config_builder = TorClientConfig::builder();
-    config_builder
-        .storage()
-        .cache_dir(cache_dir)
-        .state_dir(data_dir);
+/// Creates a Tor client without bootstrapping it, so its progress can be watched
+/// (e.g., with [`watch_bootstrap`]) before [`bootstrap_tor_client`] is called.
+///
+/// # Errors
+///
+/// Returns [`Error::ProjectDirs`] if the default directories cannot be determined,
+/// and [`Error::Bootstrap`] if the configuration is invalid.
+pub fn create_tor_client<R: Runtime>(
+    runtime: R,
+    config: Option<TorClientConfig>,
+) -> Result<TorClient<R>> {
+    info!("{}", t!("tor.starting_tor_client"));
 
-    let config: TorClientConfig = config_builder.build().unwrap_or_default();
+    let config = match config {
+        Some(config) => config,
+        None => {
+            let dirs = project_dirs()?;
+            storage_config(dirs.cache_dir(), dirs.data_dir())
+        }
+    };
 
-    // Create unbootstrapped Tor client
-    let tor_client = TorClient::with_runtime(runtime)
+    TorClient::with_runtime(runtime)
         .config(config)
         .create_unbootstrapped()
-        .with_context(|| t!("tor.errors.bootstrap"))?;
+        .map_err(Error::Bootstrap)
+}
 
+/// Bootstraps `tor_client`, showing the progress in a progress bar, and waits
+/// until it is ready for traffic.
+///
+/// # Errors
+///
+/// Returns [`Error::Bootstrap`] if the bootstrap process fails.
+pub async fn bootstrap_tor_client<R: Runtime>(tor_client: &TorClient<R>) -> Result<()> {
     // Set up progress bar for bootstrap
     let pb = ProgressBar::new(100);
     pb.set_style(
@@ -94,7 +124,7 @@ pub async fn start_tor_client<R: Runtime>(
     // We rely on the event listener to update the progress bar.
     if let Err(e) = tor_client.bootstrap().await {
         pb.abandon_with_message(t!("tor.bootstrap_failed"));
-        return Err(anyhow::anyhow!(t!("tor.errors.bootstrap")).context(e));
+        return Err(Error::Bootstrap(e));
     }
 
     bootstrap_task.abort();
@@ -104,7 +134,32 @@ pub async fn start_tor_client<R: Runtime>(
     pb.set_position(100);
     pb.finish_with_message(t!("tor.tor_client_started"));
 
-    Ok(tor_client)
+    Ok(())
+}
+
+/// Returns the directories where the cache and state of Arti are kept by default.
+///
+/// # Errors
+///
+/// Returns [`Error::ProjectDirs`] if the home directory of the user cannot be found.
+pub fn project_dirs() -> Result<ProjectDirs> {
+    ProjectDirs::from("", "", "arti-onion-proxy").ok_or(Error::ProjectDirs)
+}
+
+/// Builds a client configuration keeping the directory cache in `cache_dir` and
+/// the state, including the keys of onion services, in `state_dir`.
+pub fn storage_config(cache_dir: &Path, state_dir: &Path) -> TorClientConfig {
+    // Path -> String -> CfgPath
+    let cache_dir = CfgPath::new(cache_dir.to_string_lossy().into());
+    let state_dir = CfgPath::new(state_dir.to_string_lossy().into());
+
+    let mut config_builder = TorClientConfig::builder();
+    config_builder
+        .storage()
+        .cache_dir(cache_dir)
+        .state_dir(state_dir);
+
+    config_builder.build().unwrap_or_default()
 }
 
 /// Launches a new Onion Service.
@@ -132,51 +187,407 @@ pub async fn launch_onion_service<R: Runtime>(
     std::sync::Arc<RunningOnionService>,
     impl futures::Stream<Item = tor_hsservice::RendRequest>,
 )> {
+    let clients: Vec<RestrictedClient> = client_auth_str
+        .as_deref()
+        .map(|key| RestrictedClient::new(key, None))
+        .transpose()?
+        .into_iter()
+        .collect();
+
+    launch_onion_service_with_clients(client, nickname_str, &clients).await
+}
+
+/// Launches a new Onion Service like [`launch_onion_service`], authorizing every client in `clients`.
+///
+/// If `clients` is empty, the service is public.
+///
+/// # Errors
+///
+/// Returns [`Error::InvalidNickname`] for a malformed nickname, or
+/// [`Error::LaunchService`] if Arti cannot start the service.
+pub async fn launch_onion_service_with_clients<R: Runtime>(
+    client: &TorClient<R>,
+    nickname_str: &str,
+    clients: &[RestrictedClient],
+) -> Result<(
+    std::sync::Arc<RunningOnionService>,
+    impl futures::Stream<Item = tor_hsservice::RendRequest> + use<R>,
+)> {
+    let service_config = service_config_with_clients(nickname_str, clients)?;
+    let Some((service, requests)) = client
+        .launch_onion_service(service_config)
+        .map_err(|e| Error::LaunchService(Some(e)))?
+    else {
+        return Err(Error::LaunchService(None));
+    };
+
+    Ok((service, requests))
+}
+
+/// Launches a new Onion Service like [`launch_onion_service_with_clients`], with the
+/// address of `identity` instead of the key stored for `nickname_str`.
+///
+/// The key is added to the keystore for as long as the service runs: call
+/// [`remove_service_keys`] once it has stopped, so the identity is not kept.
+///
+/// # Errors
+///
+/// Returns [`Error::InvalidNickname`] for a malformed nickname, or
+/// [`Error::LaunchService`] if Arti cannot start the service, e.g. because a key
+/// is already stored for `nickname_str`.
+pub async fn launch_onion_service_with_identity<R: Runtime>(
+    client: &TorClient<R>,
+    nickname_str: &str,
+    clients: &[RestrictedClient],
+    identity: ServiceIdentity,
+) -> Result<(
+    std::sync::Arc<RunningOnionService>,
+    impl futures::Stream<Item = tor_hsservice::RendRequest> + use<R>,
+)> {
+    let service_config = service_config_with_clients(nickname_str, clients)?;
+    let Some((service, requests)) = client
+        .launch_onion_service_with_hsid(service_config, identity.into_hsid_keypair())
+        .map_err(|e| Error::LaunchService(Some(e)))?
+    else {
+        return Err(Error::LaunchService(None));
+    };
+
+    Ok((service, requests))
+}
+
+/// Builds the configuration of a service launched with `clients`, restricted
+/// unless `clients` is empty.
+fn service_config_with_clients(
+    nickname_str: &str,
+    clients: &[RestrictedClient],
+) -> Result<OnionServiceConfig> {
+    let service_config = build_service_config(nickname_str, clients, !clients.is_empty())?;
+
+    for restricted_client in clients {
+        tracing::info!(
+            "{}",
+            t!("tor.restricted_enabled", nick = restricted_client.nickname)
+        );
+    }
+
+    Ok(service_config)
+}
+
+/// Removes every key of the service `nickname_str` from the keystores of `client`:
+/// its identity and the keys derived from it.
+///
+/// Only call it once the service has stopped.
+///
+/// # Errors
+///
+/// Returns [`Error::RemoveServiceKeys`] if the keystore cannot be listed or a key
+/// cannot be removed.
+pub fn remove_service_keys<R: Runtime>(client: &TorClient<R>, nickname_str: &str) -> Result<()> {
+    let remove_error =
+        |source: Box<dyn std::error::Error + Send + Sync>| Error::RemoveServiceKeys {
+            nickname: nickname_str.to_string(),
+            source,
+        };
+    let keymgr = client.keymgr().map_err(|e| remove_error(e.into()))?;
+
+    // Arti keeps the keys of a service under "hss/<nickname>/", including the
+    // keys of its introduction points under "hss/<nickname>/ipts/"
+    let pattern = KeyPathPattern::Arti(format!("hss/{nickname_str}/**"));
+    let entries = keymgr
+        .list_matching(&pattern)
+        .map_err(|e| remove_error(e.into()))?;
+    for entry in entries {
+        keymgr
+            .remove_entry(&entry)
+            .map_err(|e| remove_error(e.into()))?;
+    }
+    Ok(())
+}
+
+/// Replaces the clients allowed to discover a running onion service.
+///
+/// Restricted discovery is enabled even if `clients` is empty, so revoking the
+/// last client never makes the service public. A new descriptor is published
+/// in the background.
+///
+/// # Arguments
+///
+/// * `service` - The running service.
+/// * `nickname_str` - The nickname the service was launched with.
+/// * `clients` - The complete new list of authorized clients.
+pub fn set_restricted_clients(
+    service: &RunningOnionService,
+    nickname_str: &str,
+    clients: &[RestrictedClient],
+) -> Result<()> {
+    let service_config = build_service_config(nickname_str, clients, true)?;
+    service
+        .reconfigure(service_config, tor_config::Reconfigure::WarnOnFailures)
+        .map_err(Error::Reconfigure)?;
+    Ok(())
+}
+
+/// A client allowed to discover a service in restricted discovery mode.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RestrictedClient {
+    /// Name of the client, unique within the service (e.g., "client-a1b2c3").
+    pub nickname: String,
+    /// Public key of the client (format: `descriptor:x25519:<KEY>`).
+    pub key: String,
+}
+
+impl RestrictedClient {
+    /// Validates a client key and nickname.
+    ///
+    /// If `nickname` is `None`, a random one is generated (e.g., "client-a1b2c3").
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidAuthKey`] or [`Error::InvalidClientNickname`] if the key
+    /// or the nickname is malformed.
+    pub fn new(key: &str, nickname: Option<&str>) -> Result<Self> {
+        use std::str::FromStr;
+
+        key.parse::<HsClientDescEncKey>()
+            .map_err(|_| Error::InvalidAuthKey(key.to_string()))?;
+
+        let nickname = match nickname {
+            Some(nickname) => nickname.to_string(),
+            None => {
+                let random_bytes = rand::rng().random::<[u8; 3]>();
+                format!("client-{}", hex::encode(random_bytes))
+            }
+        };
+        HsClientNickname::from_str(&nickname)
+            .map_err(|_| Error::InvalidClientNickname(nickname.clone()))?;
+
+        Ok(Self {
+            nickname,
+            key: key.to_string(),
+        })
+    }
+}
+
+/// Builds the configuration of an onion service.
+fn build_service_config(
+    nickname_str: &str,
+    clients: &[RestrictedClient],
+    restricted: bool,
+) -> Result<OnionServiceConfig> {
     use std::str::FromStr;
 
     let nickname = HsNickname::new(nickname_str.to_string())
-        .with_context(|| t!("tor.errors.invalid_nickname"))?;
+        .map_err(|_| Error::InvalidNickname(nickname_str.to_string()))?;
 
     let mut service_builder = OnionServiceConfigBuilder::default();
     service_builder.nickname(nickname);
 
     // --- Setup Restricted Discovery ---
-    if let Some(auth_str) = client_auth_str {
-        // parse the client authorization string into a HsClientDescEncKey
-        let key: HsClientDescEncKey = auth_str
-            .parse()
-            .map_err(|_| anyhow::anyhow!(t!("cli.auth_error")))?;
-
-        // let random_bytes = rand::random::<[u8; 3]>();
-        let random_bytes = rand::rng().random::<[u8; 3]>();
-
-        let client_nickname = &format!("client-{}", hex::encode(random_bytes));
-
-        let client_nick = HsClientNickname::from_str(client_nickname)
-            .map_err(|_| anyhow::anyhow!(t!("tor.error.invalid_nickname")))?;
-
+    if restricted {
         // Get access to the restricted_discovery we are take it in service_builder
         let rd = service_builder.restricted_discovery();
 
         // Enable Restricted Discovery
         rd.enabled(true);
 
-        // Note: push takes ownership, so we pass client_nick and key directly
-        rd.static_keys().access().push((client_nick, key));
+        for restricted_client in clients {
+            // Both were validated by RestrictedClient::new
+            let key: HsClientDescEncKey = restricted_client
+                .key
+                .parse()
+                .map_err(|_| Error::InvalidAuthKey(restricted_client.key.clone()))?;
+            let client_nick = HsClientNickname::from_str(&restricted_client.nickname)
+                .map_err(|_| Error::InvalidClientNickname(restricted_client.nickname.clone()))?;
 
-        tracing::info!("{}", t!("tor.restricted_enabled", nick = client_nickname));
+            // Note: push takes ownership, so we pass client_nick and key directly
+            rd.static_keys().access().push((client_nick, key));
+        }
     }
 
-    let service_config = service_builder
-        .build()
-        .with_context(|| t!("tor.errors.service_config"))?;
+    service_builder.build().map_err(Error::ServiceConfig)
+}
 
-    let Some((service, requests)) = client
-        .launch_onion_service(service_config)
-        .with_context(|| t!("tor.errors.launch_service"))?
-    else {
-        return Err(anyhow::anyhow!(t!("tor.errors.launch_service")));
+/// Opens a stream to a remote onion service.
+///
+/// # Arguments
+///
+/// * `client` - A bootstrapped `TorClient`.
+/// * `host` - The `.onion` address of the service.
+/// * `port` - The virtual port of the service.
+///
+/// # Errors
+///
+/// Returns [`Error::Connect`] if the service cannot be reached (e.g., the descriptor
+/// cannot be found or the rendezvous fails).
+pub async fn connect_onion<R: Runtime>(
+    client: &TorClient<R>,
+    host: &str,
+    port: u16,
+) -> Result<arti_client::DataStream> {
+    client
+        .connect((host, port))
+        .await
+        .map_err(|source| Error::Connect {
+            target: format!("{host}:{port}"),
+            source,
+        })
+}
+
+/// Registers a client authorization key for a restricted onion service.
+///
+/// The key is stored in the client's keystore, so the client can decrypt the
+/// descriptor of a service running in restricted discovery mode. A key already
+/// stored for the same service is replaced.
+///
+/// # Arguments
+///
+/// * `client` - The `TorClient` that will connect to the service.
+/// * `onion_address` - The `.onion` address of the service.
+/// * `secret` - The raw x25519 secret key of the client.
+pub fn add_client_auth_key<R: Runtime>(
+    client: &TorClient<R>,
+    onion_address: &str,
+    secret: [u8; 32],
+) -> Result<()> {
+    let hsid: HsId = onion_address
+        .parse()
+        .map_err(|_| Error::InvalidOnionAddress(onion_address.to_string()))?;
+
+    let secret_key = HsClientDescEncSecretKey::from(curve25519::StaticSecret::from(secret));
+
+    // Drop a stale key left over from a previous run, if any.
+    let _ = client.remove_service_discovery_key(KeystoreSelector::Primary, hsid);
+
+    client
+        .insert_service_discovery_key(KeystoreSelector::Primary, hsid, secret_key)
+        .map_err(|source| Error::ClientKey {
+            address: onion_address.to_string(),
+            source,
+        })?;
+
+    info!("{}", t!("tor.client_key_added", addr = onion_address));
+    Ok(())
+}
+
+/// Status of the Tor client and of the onion service, kept up to date for monitoring.
+///
+/// Clones share the same status.
+#[derive(Debug, Clone, Default)]
+pub struct TorStatus {
+    inner: Arc<Mutex<TorStatusSnapshot>>,
+}
+
+/// Point-in-time copy of a [`TorStatus`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct TorStatusSnapshot {
+    /// Bootstrap progress of the Tor client, from 0 to 1.
+    pub bootstrap_fraction: f32,
+    /// Whether the Tor client is ready for traffic.
+    pub ready: bool,
+    /// High-level state of the onion service (e.g., `running`), once launched.
+    pub service_state: Option<&'static str>,
+    /// Times the service became reachable (`running` or `degraded_reachable`) after
+    /// being in another state. This follows the state reported by Arti and does not
+    /// count individual descriptor uploads.
+    pub reachable_transitions: u64,
+    /// Times descriptor uploads started failing.
+    pub descriptor_upload_failures: u64,
+    /// Whether descriptor uploads are currently failing.
+    pub descriptor_failing: bool,
+}
+
+/// Every value of [`TorStatusSnapshot::service_state`].
+pub const SERVICE_STATES: [&str; 8] = [
+    "shutdown",
+    "bootstrapping",
+    "degraded_reachable",
+    "degraded_unreachable",
+    "running",
+    "recovering",
+    "broken",
+    "unknown",
+];
+
+impl TorStatus {
+    /// Creates a status for a client that has not bootstrapped yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a copy of the current status.
+    pub fn snapshot(&self) -> TorStatusSnapshot {
+        self.inner.lock().expect("poisoned lock").clone()
+    }
+
+    /// Records the bootstrap progress of the Tor client.
+    pub fn record_bootstrap(&self, fraction: f32, ready: bool) {
+        let mut status = self.inner.lock().expect("poisoned lock");
+        status.bootstrap_fraction = fraction;
+        status.ready = ready;
+    }
+
+    /// Records a new state of the onion service.
+    ///
+    /// Transitions into a reachable state are counted. `descriptor_failed` tells
+    /// whether the service currently fails to upload its descriptor; only the
+    /// transitions into that problem are counted as failures.
+    pub fn record_service_state(&self, state: State, descriptor_failed: bool) {
+        let name = service_state_name(state);
+        let mut status = self.inner.lock().expect("poisoned lock");
+
+        let was_reachable = matches!(status.service_state, Some("running" | "degraded_reachable"));
+        if matches!(name, "running" | "degraded_reachable") && !was_reachable {
+            status.reachable_transitions += 1;
+        }
+        if descriptor_failed && !status.descriptor_failing {
+            status.descriptor_upload_failures += 1;
+        }
+        status.descriptor_failing = descriptor_failed;
+        status.service_state = Some(name);
+    }
+}
+
+/// Keeps `status` up to date with the bootstrap progress of `client`. Runs until the client is dropped.
+pub async fn watch_bootstrap<R: Runtime>(client: TorClient<R>, status: TorStatus) {
+    let current = client.bootstrap_status();
+    status.record_bootstrap(current.as_frac(), current.ready_for_traffic());
+
+    let mut events = client.bootstrap_events();
+    drop(client);
+    while let Some(event) = events.next().await {
+        status.record_bootstrap(event.as_frac(), event.ready_for_traffic());
+    }
+}
+
+/// Keeps `status` up to date with the state of `service`. Runs until the service is shut down.
+pub async fn watch_service(service: Arc<RunningOnionService>, status: TorStatus) {
+    let mut events = service.status_events();
+    let current = service.status();
+    drop(service);
+
+    let record = |service_status: &tor_hsservice::status::OnionServiceStatus| {
+        let descriptor_failed = matches!(
+            service_status.current_problem(),
+            Some(Problem::DescriptorUpload(_))
+        );
+        status.record_service_state(service_status.state(), descriptor_failed);
     };
 
-    Ok((service, requests))
+    record(&current);
+    while let Some(event) = events.next().await {
+        record(&event);
+    }
+}
+
+fn service_state_name(state: State) -> &'static str {
+    match state {
+        State::Shutdown => "shutdown",
+        State::Bootstrapping => "bootstrapping",
+        State::DegradedReachable => "degraded_reachable",
+        State::DegradedUnreachable => "degraded_unreachable",
+        State::Running => "running",
+        State::Recovering => "recovering",
+        State::Broken => "broken",
+        _ => "unknown",
+    }
 }
@@ -0,0 +1,755 @@
+// src/torcontrol.rs
+use crate::error::{Error, Result};
+use crate::keygen::{self, ServiceIdentity};
+use crate::proxy::{self, PortMap, ProxyOptions};
+use crate::shutdown::ShutdownHandle;
+use crate::tor::{self, RestrictedClient};
+use crate::utils::read_line_limited;
+use arti_client::TorClient;
+use futures::io::BufReader;
+use futures::task::SpawnExt;
+use futures::{AsyncRead, AsyncWrite, AsyncWriteExt, Stream, StreamExt};
+use rust_i18n::t;
+use safelog::DisplayRedacted;
+use sha1::{Digest, Sha1};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tor_hsservice::{RendRequest, RunningOnionService};
+use tor_rtcompat::{NetStreamListener, Runtime};
+use tracing::{debug, info, warn};
+
+/// Version of C-tor reported by `PROTOCOLINFO`.
+///
+/// Clients use it to decide which commands they may send; `ADD_ONION` with
+/// `ClientAuthV3` needs at least 0.4.6.
+pub const TOR_COMPAT_VERSION: &str = "0.4.8.0";
+
+/// Length of the authentication cookie, as in C-tor.
+pub const COOKIE_LEN: usize = 32;
+
+/// Maximum length of a command line; longer lines close the connection.
+pub const MAX_COMMAND_LINE: usize = 64 * 1024;
+
+/// Environment variable holding the password accepted by `AUTHENTICATE`, so it
+/// does not appear in the process list.
+pub const PASSWORD_ENV: &str = "ONIONIZE_CONTROL_PASSWORD";
+
+/// Prefix of a hashed password, as in C-tor's `HashedControlPassword`.
+const HASHED_PASSWORD_PREFIX: &str = "16:";
+
+/// Count byte of the salted and iterated hash C-tor uses, which hashes 65536 bytes.
+const S2K_COUNT: u8 = 96;
+
+/// An error reply of the control protocol (e.g., `512 Missing 'Port' argument`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ControlPortError {
+    /// The status code (e.g., 512 for a syntax error).
+    pub code: u16,
+    /// The human-readable message.
+    pub message: String,
+}
+
+impl ControlPortError {
+    fn new(code: u16, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ControlPortError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for ControlPortError {}
+
+/// A password accepted by `AUTHENTICATE`, kept only as a salted hash like C-tor's
+/// `HashedControlPassword` (the OpenPGP iterated and salted S2K with SHA-1).
+#[derive(Clone, PartialEq, Eq)]
+pub struct ControlPassword {
+    salt: [u8; 8],
+    count: u8,
+    digest: [u8; 20],
+}
+
+impl fmt::Debug for ControlPassword {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ControlPassword([REDACTED])")
+    }
+}
+
+impl ControlPassword {
+    /// Hashes `password` with a random salt.
+    pub fn new(password: &str) -> Self {
+        let salt: [u8; 8] = rand::random();
+        Self {
+            salt,
+            count: S2K_COUNT,
+            digest: s2k(&salt, S2K_COUNT, password.as_bytes()),
+        }
+    }
+
+    /// Parses a hashed password as printed by `tor --hash-password` (e.g., `16:9A3F...`).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidHashedPassword`] if `hashed` is not `16:` followed by
+    /// 29 hexadecimal bytes.
+    pub fn from_hashed(hashed: &str) -> Result<Self> {
+        let bytes = hashed
+            .trim()
+            .strip_prefix(HASHED_PASSWORD_PREFIX)
+            .and_then(|hex_bytes| hex::decode(hex_bytes).ok())
+            .filter(|bytes| bytes.len() == 29)
+            .ok_or(Error::InvalidHashedPassword)?;
+
+        let mut password = Self {
+            salt: [0; 8],
+            count: bytes[8],
+            digest: [0; 20],
+        };
+        password.salt.copy_from_slice(&bytes[..8]);
+        password.digest.copy_from_slice(&bytes[9..]);
+        Ok(password)
+    }
+
+    /// Returns the hashed password in the format of `tor --hash-password`.
+    pub fn hashed(&self) -> String {
+        let mut bytes = self.salt.to_vec();
+        bytes.push(self.count);
+        bytes.extend_from_slice(&self.digest);
+        format!("{HASHED_PASSWORD_PREFIX}{}", hex::encode_upper(bytes))
+    }
+
+    /// Checks `password` against the hash.
+    pub fn matches(&self, password: &[u8]) -> bool {
+        ct_eq(&s2k(&self.salt, self.count, password), &self.digest)
+    }
+}
+
+/// Hashes `secret` with the iterated and salted S2K of OpenPGP (RFC 4880 3.7.1.3),
+/// as C-tor does for control port passwords.
+fn s2k(salt: &[u8; 8], count: u8, secret: &[u8]) -> [u8; 20] {
+    let mut remaining = (16usize + usize::from(count & 15)) << ((count >> 4) + 6);
+    let mut input = zeroize::Zeroizing::new(salt.to_vec());
+    input.extend_from_slice(secret);
+
+    let mut hasher = Sha1::new();
+    while remaining > 0 {
+        let len = remaining.min(input.len());
+        hasher.update(&input[..len]);
+        remaining -= len;
+    }
+    hasher.finalize().into()
+}
+
+/// Credentials accepted by `AUTHENTICATE`.
+#[derive(Debug, Clone)]
+pub struct ControlPortAuth {
+    cookie: [u8; COOKIE_LEN],
+    cookie_file: PathBuf,
+    password: Option<ControlPassword>,
+}
+
+impl ControlPortAuth {
+    /// Creates credentials from a cookie stored in `cookie_file` and an optional password.
+    pub fn new(
+        cookie: [u8; COOKIE_LEN],
+        cookie_file: PathBuf,
+        password: Option<ControlPassword>,
+    ) -> Self {
+        Self {
+            cookie,
+            cookie_file,
+            password,
+        }
+    }
+
+    /// Generates a random cookie and writes it to `cookie_file`, readable only by the current user.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be written.
+    pub fn with_new_cookie(cookie_file: &Path, password: Option<ControlPassword>) -> Result<Self> {
+        let cookie: [u8; COOKIE_LEN] = rand::random();
+        let cookie_error = |source| Error::ControlCookie {
+            file: cookie_file.to_path_buf(),
+            source,
+        };
+
+        if let Some(parent) = cookie_file.parent() {
+            std::fs::create_dir_all(parent).map_err(cookie_error)?;
+        }
+        std::fs::write(cookie_file, cookie).map_err(cookie_error)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(cookie_file, std::fs::Permissions::from_mode(0o600))
+                .map_err(cookie_error)?;
+        }
+
+        Ok(Self::new(cookie, cookie_file.to_path_buf(), password))
+    }
+
+    /// Returns the reply to `PROTOCOLINFO`.
+    fn protocol_info(&self) -> String {
+        let methods = if self.password.is_some() {
+            "COOKIE,HASHEDPASSWORD"
+        } else {
+            "COOKIE"
+        };
+        format!(
+            "250-PROTOCOLINFO 1\r\n250-AUTH METHODS={methods} COOKIEFILE={}\r\n250-VERSION Tor={}\r\n250 OK\r\n",
+            quote(&self.cookie_file.to_string_lossy()),
+            quote(TOR_COMPAT_VERSION)
+        )
+    }
+
+    /// Checks the argument of `AUTHENTICATE`: the hex-encoded cookie, or the password
+    /// either quoted or hex-encoded.
+    fn check(&self, argument: &str) -> bool {
+        let argument = argument.trim();
+        let secret = if argument.starts_with('"') {
+            match unquote(argument) {
+                Some(password) => zeroize::Zeroizing::new(password.into_bytes()),
+                None => return false,
+            }
+        } else {
+            match hex::decode(argument) {
+                Ok(secret) => zeroize::Zeroizing::new(secret),
+                Err(_) => return false,
+            }
+        };
+
+        let cookie_ok = ct_eq(&secret, &self.cookie);
+        let password_ok = self
+            .password
+            .as_ref()
+            .is_some_and(|password| password.matches(&secret));
+        cookie_ok | password_ok
+    }
+}
+
+/// A service requested with `ADD_ONION`.
+#[derive(Debug)]
+pub struct OnionSpec {
+    /// The imported identity (`ED25519-V3:<blob>`), or `None` for a new one (`NEW:BEST`).
+    pub key: Option<ServiceIdentity>,
+    /// Virtual ports and the local addresses they are forwarded to.
+    pub ports: Vec<(u16, String)>,
+    /// Clients allowed to discover the service; empty for a public service.
+    pub clients: Vec<RestrictedClient>,
+    /// Keep the service after the control connection is closed (`Flags=Detach`).
+    pub detach: bool,
+    /// Do not return the private key (`Flags=DiscardPK`).
+    pub discard_pk: bool,
+}
+
+/// Parses the arguments of `ADD_ONION`.
+///
+/// Only ed25519 keys are supported, new (`NEW:BEST` or `NEW:ED25519-V3`) or imported
+/// (`ED25519-V3:<blob>`), with the `Port`, `ClientAuthV3` and `Flags` (`DiscardPK`,
+/// `Detach`, `V3Auth`) arguments.
+///
+/// # Errors
+///
+/// Returns the error reply for malformed or unsupported arguments.
+pub fn parse_add_onion(arguments: &str) -> std::result::Result<OnionSpec, ControlPortError> {
+    let mut words = arguments.split_whitespace();
+
+    let key = words
+        .next()
+        .ok_or_else(|| ControlPortError::new(512, "Missing argument to ADD_ONION"))?;
+    let key = match key.split_once(':') {
+        Some((key_type, blob)) if key_type.eq_ignore_ascii_case("NEW") => {
+            if !(blob.eq_ignore_ascii_case("BEST") || blob.eq_ignore_ascii_case("ED25519-V3")) {
+                return Err(ControlPortError::new(513, "Invalid key type"));
+            }
+            None
+        }
+        Some((key_type, _)) if key_type.eq_ignore_ascii_case("ED25519-V3") => Some(
+            ServiceIdentity::from_ctor_private_key(key)
+                .ok_or_else(|| ControlPortError::new(513, "Failed to decode ED25519-V3 key"))?,
+        ),
+        _ => return Err(ControlPortError::new(513, "Invalid key type")),
+    };
+
+    let mut spec = OnionSpec {
+        key,
+        ports: Vec::new(),
+        clients: Vec::new(),
+        detach: false,
+        discard_pk: false,
+    };
+
+    for word in words {
+        let (name, value) = word
+            .split_once('=')
+            .ok_or_else(|| ControlPortError::new(513, format!("Invalid argument: {word}")))?;
+
+        match name {
+            "Port" => spec.ports.push(parse_port(value)?),
+            "ClientAuthV3" => {
+                let key = format!("descriptor:x25519:{}", value.to_ascii_lowercase());
+                let client = RestrictedClient::new(&key, None).map_err(|_| {
+                    ControlPortError::new(512, "Cannot decode v3 client authorization key")
+                })?;
+                spec.clients.push(client);
+            }
+            "Flags" => {
+                for flag in value.split(',') {
+                    match flag {
+                        "DiscardPK" => spec.discard_pk = true,
+                        "Detach" => spec.detach = true,
+                        // Implied by ClientAuthV3
+                        "V3Auth" => {}
+                        _ => {
+                            return Err(ControlPortError::new(
+                                512,
+                                format!("Invalid 'Flags' argument: {flag}"),
+                            ));
+                        }
+                    }
+                }
+            }
+            _ => {
+                return Err(ControlPortError::new(
+                    513,
+                    format!("Unrecognized argument: {name}"),
+                ));
+            }
+        }
+    }
+
+    if spec.ports.is_empty() {
+        return Err(ControlPortError::new(512, "Missing 'Port' argument"));
+    }
+
+    Ok(spec)
+}
+
+/// Parses a `Port=VIRTPORT[,TARGET]` value. The target defaults to `127.0.0.1:VIRTPORT`.
+fn parse_port(value: &str) -> std::result::Result<(u16, String), ControlPortError> {
+    let invalid = || ControlPortError::new(512, format!("Invalid VIRTPORT/TARGET: {value}"));
+
+    let (virtual_port, target) = match value.split_once(',') {
+        Some((virtual_port, target)) => (virtual_port, Some(target)),
+        None => (value, None),
+    };
+    let virtual_port: u16 = virtual_port.parse().map_err(|_| invalid())?;
+    if virtual_port == 0 {
+        return Err(invalid());
+    }
+
+    let target = match target {
+        None => format!("127.0.0.1:{virtual_port}"),
+        Some(target) if target.starts_with("unix:") => {
+            return Err(ControlPortError::new(
+                513,
+                "Unix socket targets are not supported",
+            ));
+        }
+        Some(target) => match target.parse::<u16>() {
+            Ok(port) if port > 0 => format!("127.0.0.1:{port}"),
+            Ok(_) => return Err(invalid()),
+            Err(_) => {
+                let target = match target.rsplit_once(':') {
+                    Some((host, port)) if host.eq_ignore_ascii_case("localhost") => {
+                        format!("127.0.0.1:{port}")
+                    }
+                    _ => target.to_string(),
+                };
+                target.parse::<SocketAddr>().map_err(|_| invalid())?;
+                target
+            }
+        },
+    };
+
+    Ok((virtual_port, target))
+}
+
+/// Services created with `ADD_ONION`, by service ID (the onion address without `.onion`).
+#[derive(Debug)]
+pub struct OnionRegistry {
+    shutdown: ShutdownHandle,
+    services: Mutex<BTreeMap<String, ShutdownHandle>>,
+}
+
+impl OnionRegistry {
+    /// Creates an empty registry. Every service is stopped along with `shutdown`.
+    pub fn new(shutdown: ShutdownHandle) -> Self {
+        Self {
+            shutdown,
+            services: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Returns the IDs of the running services.
+    pub fn ids(&self) -> Vec<String> {
+        self.services
+            .lock()
+            .expect("poisoned lock")
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    /// Stops the service `id`. Returns `false` if there is no such service.
+    pub fn remove(&self, id: &str) -> bool {
+        let removed = self.services.lock().expect("poisoned lock").remove(id);
+        match removed {
+            Some(stop) => {
+                stop.shutdown();
+                info!("{}", t!("control_port.service_removed", id = id));
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn insert(&self, id: String, stop: ShutdownHandle) {
+        self.services
+            .lock()
+            .expect("poisoned lock")
+            .insert(id, stop);
+    }
+}
+
+/// Runs a control port compatible with a subset of the C-tor control protocol on `local_addr`.
+///
+/// Supported commands are `PROTOCOLINFO`, `AUTHENTICATE` (cookie or password),
+/// `ADD_ONION`, `DEL_ONION` and `QUIT`.
+///
+/// # Arguments
+///
+/// * `runtime` - The runtime used to listen locally and spawn tasks.
+/// * `local_addr` - The local address to listen on (e.g., "127.0.0.1:9051").
+/// * `auth` - The accepted credentials.
+/// * `registry` - The services created through the control port.
+/// * `launch` - Starts a service (see [`launch_proxied_service`]) that stops once
+///   the given handle is shut down, and returns its service ID.
+///
+/// # Errors
+///
+/// Returns an error if the local listener cannot be created.
+pub async fn run_control_port<R, L, Fut>(
+    runtime: R,
+    local_addr: SocketAddr,
+    auth: Arc<ControlPortAuth>,
+    registry: Arc<OnionRegistry>,
+    launch: L,
+) -> Result<()>
+where
+    R: Runtime,
+    L: Fn(OnionSpec, ShutdownHandle) -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = Result<String>> + Send + 'static,
+{
+    let listener = runtime
+        .listen(&local_addr)
+        .await
+        .map_err(|source| Error::Listen {
+            address: local_addr,
+            source,
+        })?;
+
+    info!("{}", t!("control_port.listening", local = local_addr));
+
+    let mut incoming = listener.incoming();
+
+    while let Some(accepted) = incoming.next().await {
+        let (stream, peer) = match accepted {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("{}", t!("control_port.errors.accept", err = e));
+                continue;
+            }
+        };
+
+        debug!("Control port connection from {}", peer);
+
+        let auth = auth.clone();
+        let registry = registry.clone();
+        let launch = launch.clone();
+        let spawn_res = runtime.spawn(async move {
+            if let Err(e) = serve_control_port_connection(stream, &auth, &registry, &launch).await {
+                debug!("Control port connection failed: {:#}", e);
+            }
+        });
+
+        if let Err(e) = spawn_res {
+            warn!("{}: {}", t!("control_port.errors.task"), e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Serves one control port connection until the client quits or disconnects.
+///
+/// Services added without `Flags=Detach` are removed when the connection ends.
+///
+/// # Errors
+///
+/// Returns an error if reading from or writing to `stream` fails, or if a command
+/// is longer than [`MAX_COMMAND_LINE`].
+pub async fn serve_control_port_connection<S, L, Fut>(
+    stream: S,
+    auth: &ControlPortAuth,
+    registry: &OnionRegistry,
+    launch: &L,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    L: Fn(OnionSpec, ShutdownHandle) -> Fut,
+    Fut: Future<Output = Result<String>>,
+{
+    let (reader, mut writer) = futures::AsyncReadExt::split(stream);
+    let mut reader = BufReader::new(reader);
+
+    let mut authenticated = false;
+    let mut owned = Vec::new();
+
+    let result = async {
+        while let Some(line) = read_line_limited(&mut reader, MAX_COMMAND_LINE).await? {
+            let line = line.as_str();
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let (command, arguments) = line.split_once(' ').unwrap_or((line, ""));
+            let command = command.to_ascii_uppercase();
+            debug!("Control port command: {}", command);
+
+            let (reply, close) = match command.as_str() {
+                "PROTOCOLINFO" => (auth.protocol_info(), false),
+                "AUTHENTICATE" => {
+                    if auth.check(arguments) {
+                        authenticated = true;
+                        ("250 OK\r\n".to_string(), false)
+                    } else {
+                        (
+                            "515 Authentication failed: Password did not match HashedControlPassword *or* authentication cookie.\r\n"
+                                .to_string(),
+                            true,
+                        )
+                    }
+                }
+                "QUIT" => ("250 closing connection\r\n".to_string(), true),
+                _ if !authenticated => ("514 Authentication required.\r\n".to_string(), true),
+                "ADD_ONION" => (add_onion(arguments, registry, launch, &mut owned).await, false),
+                "DEL_ONION" => {
+                    let id = arguments.trim().trim_end_matches(".onion");
+                    if registry.remove(id) {
+                        owned.retain(|owned_id| owned_id != id);
+                        ("250 OK\r\n".to_string(), false)
+                    } else {
+                        ("552 Unknown Onion Service id\r\n".to_string(), false)
+                    }
+                }
+                _ => (format!("510 Unrecognized command \"{command}\"\r\n"), false),
+            };
+
+            writer.write_all(reply.as_bytes()).await?;
+            writer.flush().await?;
+            if close {
+                break;
+            }
+        }
+        Ok(())
+    }
+    .await;
+
+    // Services that were not detached belong to this connection
+    for id in owned {
+        registry.remove(&id);
+    }
+
+    result
+}
+
+/// Handles `ADD_ONION` and returns the reply.
+async fn add_onion<L, Fut>(
+    arguments: &str,
+    registry: &OnionRegistry,
+    launch: &L,
+    owned: &mut Vec<String>,
+) -> String
+where
+    L: Fn(OnionSpec, ShutdownHandle) -> Fut,
+    Fut: Future<Output = Result<String>>,
+{
+    let mut spec = match parse_add_onion(arguments) {
+        Ok(spec) => spec,
+        Err(e) => return format!("{e}\r\n"),
+    };
+    let detach = spec.detach;
+
+    // As in C-tor, only a new key is returned, unless the client discards it
+    let new_key = spec.key.is_none();
+    let identity = spec
+        .key
+        .get_or_insert_with(keygen::generate_service_identity);
+    let private_key = (new_key && !spec.discard_pk).then(|| identity.ctor_private_key());
+
+    let stop = registry.shutdown.child();
+    match launch(spec, stop.clone()).await {
+        Ok(id) => {
+            info!("{}", t!("control_port.service_added", id = id));
+            registry.insert(id.clone(), stop);
+            if !detach {
+                owned.push(id.clone());
+            }
+            match private_key {
+                Some(private_key) => format!(
+                    "250-ServiceID={id}\r\n250-PrivateKey={}\r\n250 OK\r\n",
+                    private_key.as_str()
+                ),
+                None => format!("250-ServiceID={id}\r\n250 OK\r\n"),
+            }
+        }
+        Err(e) => {
+            stop.shutdown();
+            warn!(
+                "{}",
+                t!(
+                    "control_port.errors.launch",
+                    err = format!("{:#}", anyhow::Error::from(e))
+                )
+            );
+            "551 Failed to add Onion Service\r\n".to_string()
+        }
+    }
+}
+
+/// Launches an onion service for `spec` and proxies its ports until `stop` is shut down.
+///
+/// The service uses the identity of `spec` (a new one if it has none), which is
+/// removed from the keystore once the service stops, so nothing outlives it.
+/// Streams to virtual ports missing from `spec` are refused before they are
+/// accepted, as in C-tor.
+///
+/// # Returns
+///
+/// Returns the service ID: the onion address without `.onion`.
+///
+/// # Errors
+///
+/// Returns an error if the service cannot be launched.
+pub async fn launch_proxied_service<R: Runtime>(
+    runtime: R,
+    client: TorClient<R>,
+    spec: OnionSpec,
+    options: ProxyOptions,
+    stop: ShutdownHandle,
+) -> Result<String> {
+    // Checked before launching, so an invalid target leaves no keys behind
+    let ports = PortMap::new();
+    for (virtual_port, target) in &spec.ports {
+        ports.insert(*virtual_port, target)?;
+    }
+    let default_target = spec.ports[0].1.clone();
+    let options = ProxyOptions {
+        ports,
+        mapped_ports_only: true,
+        ..options
+    };
+
+    let nickname = format!("ctl-{}", hex::encode(rand::random::<[u8; 3]>()));
+    let identity = spec.key.unwrap_or_else(keygen::generate_service_identity);
+    let launched =
+        tor::launch_onion_service_with_identity(&client, &nickname, &spec.clients, identity).await;
+    let started = match launched {
+        Ok(launched) => start_proxied_service(
+            &runtime,
+            &client,
+            &nickname,
+            launched,
+            default_target,
+            options,
+            stop,
+        ),
+        Err(e) => Err(e),
+    };
+    // Every failure after the keys were created ends up here
+    if started.is_err() {
+        remove_keys(&client, &nickname);
+    }
+    started
+}
+
+/// Proxies the streams of the `launched` service until `stop` is triggered, then
+/// stops it and removes its keys. Returns the service ID.
+fn start_proxied_service<R: Runtime>(
+    runtime: &R,
+    client: &TorClient<R>,
+    nickname: &str,
+    (service, requests): (
+        Arc<RunningOnionService>,
+        impl Stream<Item = RendRequest> + Send + 'static,
+    ),
+    default_target: String,
+    options: ProxyOptions,
+    stop: ShutdownHandle,
+) -> Result<String> {
+    let onion_address = service
+        .onion_address()
+        .ok_or(Error::LaunchService(None))?
+        .display_unredacted()
+        .to_string();
+    let id = onion_address.trim_end_matches(".onion").to_string();
+
+    let rt = runtime.clone();
+    let client = client.clone();
+    let nickname = nickname.to_string();
+    runtime.spawn(async move {
+        let requests = std::pin::pin!(requests);
+        proxy::run_proxy_loop(rt, requests, &default_target, options, stop).await;
+        // Dropping the service stops publishing it
+        drop(service);
+        remove_keys(&client, &nickname);
+    })?;
+
+    Ok(id)
+}
+
+/// Removes the keys of the service `nickname`, logging a failure.
+fn remove_keys<R: Runtime>(client: &TorClient<R>, nickname: &str) {
+    if let Err(e) = tor::remove_service_keys(client, nickname) {
+        let e = anyhow::Error::from(e);
+        warn!(
+            "{}",
+            t!("control_port.errors.remove_keys", err = format!("{e:#}"))
+        );
+    }
+}
+
+/// Quotes `value` as a control protocol string.
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Parses a quoted control protocol string.
+fn unquote(value: &str) -> Option<String> {
+    let inner = value.strip_prefix('"')?.strip_suffix('"')?;
+    let mut out = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => out.push(chars.next()?),
+            '"' => return None,
+            c => out.push(c),
+        }
+    }
+    Some(out)
+}
+
+/// Compares two secrets in constant time.
+fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -1,4 +1,8 @@
 // src/utils.rs
+use futures::{AsyncBufRead, AsyncBufReadExt};
+use rust_i18n::t;
+use std::io::{self, Write};
+use std::path::Path;
 use tracing::info;
 
 /// Sets up the locale for internationalization based on environment variables and system settings
@@ -34,3 +38,90 @@ pub fn setup_locale() {
 
     info!("DEBUG: Current locale: {}", &final_locale);
 }
+
+/// Formats a byte count for humans (e.g., "1.5 MiB").
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Writes `content` to `file`, which is made readable only by the current user
+/// on Unix since it holds secrets, even if it already existed.
+pub fn write_private_file(file: &Path, content: &[u8]) -> io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut handle = options.open(file)?;
+    // The mode above only applies to new files, restrict an existing one before
+    // writing the secret into it
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        handle.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    }
+    handle.write_all(content)
+}
+
+/// Reads a line from `reader` without its line ending, reading at most `limit`
+/// bytes of it, so a peer cannot make the line grow without bound.
+///
+/// Returns `None` at the end of the stream.
+///
+/// # Errors
+///
+/// Returns an [`io::ErrorKind::InvalidData`] error if the line is longer than
+/// `limit` or is not UTF-8, and the error of `reader` if reading fails.
+pub async fn read_line_limited<R>(reader: &mut R, limit: usize) -> io::Result<Option<String>>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut line = Vec::new();
+    loop {
+        let available = reader.fill_buf().await?;
+        if available.is_empty() {
+            if line.is_empty() {
+                return Ok(None);
+            }
+            break;
+        }
+
+        let (chunk, used, done) = match available.iter().position(|b| *b == b'\n') {
+            Some(end) => (&available[..end], end + 1, true),
+            None => (available, available.len(), false),
+        };
+        if line.len() + chunk.len() > limit {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                t!("errors.line_too_long", limit = limit).to_string(),
+            ));
+        }
+        line.extend_from_slice(chunk);
+        reader.consume_unpin(used);
+        if done {
+            break;
+        }
+    }
+
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
@@ -0,0 +1,126 @@
+// tests/backend_test.rs
+
+use futures::{AsyncReadExt, AsyncWriteExt};
+use onionize::Error;
+use onionize::backend::{Backend, HandlerBackend, TcpBackend};
+use onionize::proxy::{self, CloseReason, ProxyOptions};
+use onionize::stats::TrafficStats;
+use std::net::IpAddr;
+use tor_rtcompat::ToplevelBlockOn;
+use tor_rtmock::{MockRuntime, net::MockNetwork};
+
+#[test]
+fn test_handler_backend_serves_in_process() {
+    let runtime = MockRuntime::new();
+
+    runtime.clone().block_on(async move {
+        // Answers PONG to PING and closes
+        let backend = HandlerBackend::new(runtime.clone(), |mut stream| async move {
+            let mut buf = [0u8; 4];
+            stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"PING");
+            stream.write_all(b"PONG").await.unwrap();
+            stream.close().await.unwrap();
+        });
+
+        let stats = TrafficStats::new();
+        let options = ProxyOptions {
+            stats: stats.clone(),
+            ..Default::default()
+        };
+
+        let (mut client_side, stream_dummy_tor) = tor_rtmock::io::stream_pair();
+        client_side.write_all(b"PING").await.unwrap();
+        client_side.close().await.unwrap();
+
+        let reason =
+            proxy::handle_connection_with_backend(runtime, stream_dummy_tor, &backend, &options)
+                .await
+                .unwrap();
+        assert_eq!(reason, CloseReason::Completed);
+
+        let mut response = Vec::new();
+        client_side.read_to_end(&mut response).await.unwrap();
+        assert_eq!(response, b"PONG");
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.bytes_up, 4);
+        assert_eq!(snapshot.bytes_down, 4);
+    });
+}
+
+#[test]
+fn test_tcp_backend_errors() {
+    let network = MockNetwork::new();
+    let local_ip: IpAddr = "127.0.0.1".parse().unwrap();
+    let runtime = network
+        .builder()
+        .add_address(local_ip)
+        .runtime(MockRuntime::new());
+
+    runtime.clone().block_on(async move {
+        let backend = TcpBackend::new(runtime.clone(), "localhost:8080");
+        assert!(matches!(
+            backend.connect().await.err().unwrap(),
+            Error::InvalidTarget { .. }
+        ));
+
+        let backend = TcpBackend::new(runtime.clone(), "127.0.0.1:9999");
+        assert_eq!(backend.describe(), "127.0.0.1:9999");
+        assert!(matches!(
+            backend.connect().await.err().unwrap(),
+            Error::BackendUnreachable { target, .. } if target == "127.0.0.1:9999"
+        ));
+
+        // The visitor's stream is closed as unreachable
+        let (_client_side, stream_dummy_tor) = tor_rtmock::io::stream_pair();
+        let result = proxy::handle_connection_with_backend(
+            runtime,
+            stream_dummy_tor,
+            &backend,
+            &ProxyOptions::default(),
+        )
+        .await;
+        assert!(matches!(result, Err(Error::BackendUnreachable { .. })));
+    });
+}
+
+#[cfg(unix)]
+#[test]
+fn test_unix_backend() {
+    use onionize::backend::UnixBackend;
+    use std::io::{Read, Write};
+    use tor_rtcompat::PreferredRuntime;
+
+    let path = std::env::temp_dir().join(format!("onionize-backend-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+    let server = std::thread::spawn(move || {
+        let (mut socket, _) = listener.accept().unwrap();
+        let mut buf = [0u8; 4];
+        socket.read_exact(&mut buf).unwrap();
+        socket.write_all(b"PONG").unwrap();
+    });
+
+    let tokio_runtime = tokio::runtime::Runtime::new().unwrap();
+    tokio_runtime.block_on(async {
+        let runtime = PreferredRuntime::current().unwrap();
+
+        let missing = UnixBackend::new(runtime.clone(), path.with_extension("missing"));
+        assert!(matches!(
+            missing.connect().await.err().unwrap(),
+            Error::BackendUnreachable { .. }
+        ));
+
+        let backend = UnixBackend::new(runtime, &path);
+        assert!(backend.describe().starts_with("unix:"));
+        let mut stream = backend.connect().await.unwrap();
+        stream.write_all(b"PING").await.unwrap();
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"PONG");
+    });
+
+    server.join().unwrap();
+    let _ = std::fs::remove_file(&path);
+}
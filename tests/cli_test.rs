@@ -46,3 +46,120 @@ fn test_short_flags_combination() {
     assert_eq!(args.nickname, "short-name");
     assert!(args.verbose);
 }
+
+#[test]
+fn test_secret_flags() {
+    let args = Args::parse_from(["binary_name"]);
+    assert!(!args.log_secrets);
+    assert!(!args.mlock);
+
+    let args = Args::parse_from(["binary_name", "--restricted", "--log-secrets", "--mlock"]);
+    assert!(args.log_secrets);
+    assert!(args.mlock);
+}
+
+#[test]
+fn test_qr_file_flag() {
+    let args = Args::parse_from([
+        "binary_name",
+        "--restricted",
+        "--qr",
+        "--qr-file",
+        "share.png",
+    ]);
+    assert!(args.qr);
+    assert_eq!(args.qr_file, Some("share.png".into()));
+}
+
+#[test]
+fn test_keys_rekey_subcommand() {
+    use onionize::args::{Command, KeysAction};
+
+    let args = Args::parse_from(["binary_name", "--encrypt-state", "keys", "rekey"]);
+    assert!(args.encrypt_state);
+    match args.command {
+        Some(Command::Keys(keys)) => {
+            assert!(matches!(keys.action, KeysAction::Rekey { file: None }));
+        }
+        other => panic!("Expected keys subcommand, got {:?}", other),
+    }
+
+    let args = Args::parse_from(["binary_name", "keys", "rekey", "--file", "state.age"]);
+    match args.command {
+        Some(Command::Keys(keys)) => match keys.action {
+            KeysAction::Rekey { file } => assert_eq!(file, Some("state.age".into())),
+            other => panic!("Expected rekey action, got {:?}", other),
+        },
+        other => panic!("Expected keys subcommand, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_receive_subcommand() {
+    use onionize::args::Command;
+
+    let args = Args::parse_from(["binary_name", "receive", "--dir", "./inbox", "--restricted"]);
+
+    assert!(args.restricted);
+    match args.command {
+        Some(Command::Receive(receive)) => {
+            assert_eq!(receive.dir, std::path::PathBuf::from("./inbox"));
+            assert_eq!(receive.max_size_bytes(), 100 * 1024 * 1024);
+        }
+        other => panic!("Expected receive subcommand, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_connect_subcommand_client_auth() {
+    use onionize::args::Command;
+
+    let args = Args::parse_from([
+        "binary_name",
+        "connect",
+        "example.onion:80",
+        "--client-key",
+        "secret",
+        "--client-auth-dir",
+        "./auth",
+    ]);
+
+    match args.command {
+        Some(Command::Connect(connect)) => {
+            assert_eq!(connect.target, "example.onion:80");
+            assert_eq!(connect.listen.to_string(), "127.0.0.1:8080");
+            assert_eq!(connect.client_key.as_deref(), Some("secret"));
+            assert_eq!(
+                connect.client_auth_dir,
+                Some(std::path::PathBuf::from("./auth"))
+            );
+        }
+        other => panic!("Expected connect subcommand, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_keys_inspect_subcommand() {
+    use onionize::args::{Command, KeysAction};
+
+    let args = Args::parse_from([
+        "binary_name",
+        "keys",
+        "inspect",
+        "descriptor:x25519:key",
+        "./alice.auth_private",
+        "--public",
+        "descriptor:x25519:server",
+    ]);
+
+    match args.command {
+        Some(Command::Keys(keys)) => {
+            let KeysAction::Inspect { inputs, public } = keys.action else {
+                panic!("Expected inspect action, got {:?}", keys.action);
+            };
+            assert_eq!(inputs, ["descriptor:x25519:key", "./alice.auth_private"]);
+            assert_eq!(public.as_deref(), Some("descriptor:x25519:server"));
+        }
+        other => panic!("Expected keys subcommand, got {:?}", other),
+    }
+}
@@ -0,0 +1,78 @@
+// tests/connect_test.rs
+
+use futures::task::SpawnExt;
+use futures::{AsyncReadExt, AsyncWriteExt};
+use onionize::connect;
+use std::net::{IpAddr, SocketAddr};
+use tor_rtcompat::{NetStreamProvider, ToplevelBlockOn};
+use tor_rtmock::{MockRuntime, net::MockNetwork};
+
+const ONION: &str = "2gzyxa5ihm7nsggfxnu52rck2vv4rvmdlkiu3zzui5du4xyclen53wid.onion";
+
+#[test]
+fn test_parse_onion_target() {
+    let (host, port) = connect::parse_onion_target(&format!("{}:8080", ONION)).unwrap();
+    assert_eq!(host, ONION);
+    assert_eq!(port, 8080);
+
+    // Port defaults to 80, host is normalized to lowercase
+    let (host, port) = connect::parse_onion_target(&ONION.to_uppercase()).unwrap();
+    assert_eq!(host, ONION);
+    assert_eq!(port, 80);
+}
+
+#[test]
+fn test_parse_onion_target_rejects_invalid() {
+    rust_i18n::set_locale("en");
+
+    assert!(connect::parse_onion_target("example.com:80").is_err());
+    assert!(connect::parse_onion_target(".onion:80").is_err());
+    assert!(connect::parse_onion_target(&format!("{}:0", ONION)).is_err());
+    assert!(connect::parse_onion_target(&format!("{}:http", ONION)).is_err());
+}
+
+#[test]
+fn test_connect_loop_forwards_local_connections() {
+    let network = MockNetwork::new();
+    let local_ip: IpAddr = "127.0.0.1".parse().unwrap();
+    let runtime = network
+        .builder()
+        .add_address(local_ip)
+        .runtime(MockRuntime::new());
+
+    runtime.block_on(async {
+        let listen_addr: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+
+        // Stands in for Tor: every dial yields a stream to an echo service.
+        let rt_remote = runtime.clone();
+        let dial = move || {
+            let (tor_side, mut remote_side) = tor_rtmock::io::stream_pair();
+            rt_remote
+                .spawn(async move {
+                    let mut buf = [0u8; 4];
+                    if remote_side.read_exact(&mut buf).await.is_ok() {
+                        let _ = remote_side.write_all(&buf).await;
+                    }
+                })
+                .unwrap();
+            async move { Ok(tor_side) }
+        };
+
+        let rt_loop = runtime.clone();
+        runtime
+            .spawn(async move {
+                let _ = connect::run_connect_loop(rt_loop, listen_addr, dial).await;
+            })
+            .unwrap();
+
+        // Let the loop bind its listener before connecting
+        runtime.inner().progress_until_stalled().await;
+
+        let mut local_client = runtime.connect(&listen_addr).await.unwrap();
+        local_client.write_all(b"PING").await.unwrap();
+
+        let mut response = [0u8; 4];
+        local_client.read_exact(&mut response).await.unwrap();
+        assert_eq!(&response, b"PING");
+    });
+}
@@ -0,0 +1,312 @@
+// tests/control_test.rs
+
+use futures::io::BufReader;
+use futures::task::SpawnExt;
+use futures::{AsyncBufReadExt, AsyncWriteExt, StreamExt};
+use onionize::control::{
+    ControlRequest, ControlledService, Controller, MAX_REQUEST_LINE, serve_connection,
+};
+use onionize::keygen;
+use onionize::proxy::PortMap;
+use onionize::shutdown::ShutdownHandle;
+use onionize::stats::TrafficStats;
+use onionize::tor::{RestrictedClient, TorStatus};
+use serde_json::{Value, json};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use tor_rtcompat::{NetStreamListener, NetStreamProvider, ToplevelBlockOn};
+use tor_rtmock::{MockRuntime, net::MockNetwork};
+
+/// Creates a controller for a proxy on 127.0.0.1:3000.
+///
+/// Every list of clients applied to the service is recorded in the returned vector.
+fn test_controller(
+    target: Option<&str>,
+    shutdown: ShutdownHandle,
+) -> (Controller, Arc<Mutex<Vec<Vec<RestrictedClient>>>>) {
+    let applied = Arc::new(Mutex::new(Vec::new()));
+    let recorder = applied.clone();
+
+    let controller = Controller::new(
+        ControlledService {
+            nickname: "test-service".to_string(),
+            onion_address: "example.onion".to_string(),
+            target: target.map(str::to_string),
+            ports: PortMap::new(),
+            restricted: false,
+            clients: Vec::new(),
+        },
+        TrafficStats::new(),
+        TorStatus::new(),
+        shutdown,
+        Box::new(move |clients| {
+            recorder.lock().unwrap().push(clients.to_vec());
+            Ok(())
+        }),
+    );
+    (controller, applied)
+}
+
+#[test]
+fn test_port_mappings() {
+    let (controller, _) = test_controller(Some("127.0.0.1:3000"), ShutdownHandle::new());
+
+    let mapped = controller
+        .handle(ControlRequest::MapPort {
+            port: 22,
+            target: "127.0.0.1:2222".to_string(),
+        })
+        .unwrap();
+    assert_eq!(mapped["previous"], Value::Null);
+
+    let listed = controller.handle(ControlRequest::List).unwrap();
+    let service = &listed["services"][0];
+    assert_eq!(service["onion_address"], "example.onion");
+    assert_eq!(service["target"], "127.0.0.1:3000");
+    assert_eq!(
+        service["ports"],
+        json!([{ "port": 22, "target": "127.0.0.1:2222" }])
+    );
+
+    // Targets must be an IP address and a port
+    assert!(
+        controller
+            .handle(ControlRequest::MapPort {
+                port: 80,
+                target: "not-an-address".to_string(),
+            })
+            .is_err()
+    );
+
+    controller
+        .handle(ControlRequest::UnmapPort { port: 22 })
+        .unwrap();
+    assert!(
+        controller
+            .handle(ControlRequest::UnmapPort { port: 22 })
+            .is_err()
+    );
+}
+
+#[test]
+fn test_port_mappings_need_a_proxy() {
+    let (controller, _) = test_controller(None, ShutdownHandle::new());
+
+    let result = controller.handle(ControlRequest::MapPort {
+        port: 22,
+        target: "127.0.0.1:2222".to_string(),
+    });
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_add_and_revoke_clients() {
+    let (controller, applied) = test_controller(Some("127.0.0.1:3000"), ShutdownHandle::new());
+    let key = keygen::generate_keys().server_string;
+
+    let added = controller
+        .handle(ControlRequest::AddClient {
+            key: key.clone(),
+            nickname: Some("alice".to_string()),
+        })
+        .unwrap();
+    assert_eq!(added["nickname"], "alice");
+
+    // Nicknames are unique and keys are validated
+    assert!(
+        controller
+            .handle(ControlRequest::AddClient {
+                key: key.clone(),
+                nickname: Some("alice".to_string()),
+            })
+            .is_err()
+    );
+    assert!(
+        controller
+            .handle(ControlRequest::AddClient {
+                key: "descriptor:x25519:garbage".to_string(),
+                nickname: None,
+            })
+            .is_err()
+    );
+
+    let listed = controller.handle(ControlRequest::List).unwrap();
+    assert_eq!(listed["services"][0]["restricted"], true);
+    assert_eq!(listed["services"][0]["clients"][0]["key"], key.as_str());
+
+    controller
+        .handle(ControlRequest::RevokeClient {
+            nickname: "alice".to_string(),
+        })
+        .unwrap();
+    assert!(
+        controller
+            .handle(ControlRequest::RevokeClient {
+                nickname: "alice".to_string(),
+            })
+            .is_err()
+    );
+
+    // The service stays restricted after revoking the last client
+    let listed = controller.handle(ControlRequest::List).unwrap();
+    assert_eq!(listed["services"][0]["restricted"], true);
+
+    let applied = applied.lock().unwrap();
+    assert_eq!(applied.len(), 2);
+    assert_eq!(applied[0].len(), 1);
+    assert!(applied[1].is_empty());
+}
+
+#[test]
+fn test_failed_reconfiguration_keeps_clients() {
+    let controller = Controller::new(
+        ControlledService {
+            nickname: "test-service".to_string(),
+            onion_address: "example.onion".to_string(),
+            target: None,
+            ports: PortMap::new(),
+            restricted: false,
+            clients: Vec::new(),
+        },
+        TrafficStats::new(),
+        TorStatus::new(),
+        ShutdownHandle::new(),
+        Box::new(|_| {
+            Err(onionize::Error::Io(std::io::Error::other(
+                "reconfiguration failed",
+            )))
+        }),
+    );
+
+    let result = controller.handle(ControlRequest::AddClient {
+        key: keygen::generate_keys().server_string,
+        nickname: None,
+    });
+    assert!(result.is_err());
+
+    let listed = controller.handle(ControlRequest::List).unwrap();
+    assert_eq!(listed["services"][0]["restricted"], false);
+    assert_eq!(listed["services"][0]["clients"], json!([]));
+}
+
+#[test]
+fn test_shutdown_request() {
+    let shutdown = ShutdownHandle::new();
+    let (controller, _) = test_controller(Some("127.0.0.1:3000"), shutdown.clone());
+
+    let result = controller.handle(ControlRequest::Shutdown).unwrap();
+    assert_eq!(result["active_streams"], 0);
+    assert!(shutdown.is_shutdown());
+}
+
+#[test]
+fn test_serve_connection_json_lines() {
+    let network = MockNetwork::new();
+    let local_ip: IpAddr = "127.0.0.1".parse().unwrap();
+    let runtime = network
+        .builder()
+        .add_address(local_ip)
+        .runtime(MockRuntime::new());
+
+    runtime.block_on(async {
+        let addr: SocketAddr = "127.0.0.1:7000".parse().unwrap();
+        let listener = runtime.listen(&addr).await.unwrap();
+        let (controller, _) = test_controller(Some("127.0.0.1:3000"), ShutdownHandle::new());
+
+        runtime
+            .spawn(async move {
+                let (stream, _) = listener.incoming().next().await.unwrap().unwrap();
+                serve_connection(stream, &controller).await.unwrap();
+            })
+            .unwrap();
+
+        let client = runtime.connect(&addr).await.unwrap();
+        let (reader, mut writer) = futures::AsyncReadExt::split(client);
+        let mut lines = BufReader::new(reader).lines();
+
+        writer
+            .write_all(b"{\"cmd\":\"map_port\",\"port\":80,\"target\":\"127.0.0.1:8080\"}\n")
+            .await
+            .unwrap();
+        let response: Value = serde_json::from_str(&lines.next().await.unwrap().unwrap()).unwrap();
+        assert_eq!(response["ok"], true);
+        assert_eq!(response["result"]["target"], "127.0.0.1:8080");
+
+        writer.write_all(b"{\"cmd\":\"reboot\"}\n").await.unwrap();
+        let response: Value = serde_json::from_str(&lines.next().await.unwrap().unwrap()).unwrap();
+        assert_eq!(response["ok"], false);
+        assert!(response["error"].as_str().unwrap().contains("reboot"));
+
+        writer.write_all(b"{\"cmd\":\"stats\"}\n").await.unwrap();
+        let response: Value = serde_json::from_str(&lines.next().await.unwrap().unwrap()).unwrap();
+        assert_eq!(response["result"]["traffic"]["streams_total"], 0);
+        assert_eq!(response["result"]["tor"]["ready"], false);
+    });
+}
+
+#[test]
+fn test_serve_connection_caps_line_length() {
+    MockRuntime::new().block_on(async {
+        let (controller, _) = test_controller(Some("127.0.0.1:3000"), ShutdownHandle::new());
+        let (mut client, server) = tor_rtmock::io::stream_pair();
+
+        let long_line = vec![b'a'; MAX_REQUEST_LINE + 1];
+        let (served, _) = futures::join!(
+            serve_connection(server, &controller),
+            client.write_all(&long_line)
+        );
+        assert!(served.is_err(), "An endless line must close the connection");
+    });
+}
+
+#[cfg(unix)]
+#[test]
+fn test_unix_socket_round_trip() {
+    use onionize::control::{run_control_server, send_request};
+    use tor_rtcompat::{PreferredRuntime, SleepProvider};
+
+    let path = std::env::temp_dir().join(format!("onionize-control-{}.sock", std::process::id()));
+    let runtime = PreferredRuntime::create().unwrap();
+
+    runtime.clone().block_on(async move {
+        let (controller, _) = test_controller(Some("127.0.0.1:3000"), ShutdownHandle::new());
+        let server_path = path.clone();
+        let server_rt = runtime.clone();
+        runtime
+            .spawn(async move {
+                let _ = run_control_server(server_rt, &server_path, Arc::new(controller)).await;
+            })
+            .unwrap();
+
+        // Wait for the socket to appear
+        for _ in 0..100 {
+            if path.exists() {
+                break;
+            }
+            runtime.sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let response = send_request(&runtime, &path, &ControlRequest::List)
+            .await
+            .unwrap();
+        assert!(response.ok);
+        assert_eq!(
+            response.result.unwrap()["services"][0]["nickname"],
+            "test-service"
+        );
+
+        // A second instance must not take over a live socket
+        let (other, _) = test_controller(None, ShutdownHandle::new());
+        assert!(
+            run_control_server(runtime.clone(), &path, Arc::new(other))
+                .await
+                .is_err()
+        );
+
+        let _ = std::fs::remove_file(&path);
+    });
+}
@@ -0,0 +1,140 @@
+// tests/daemon_test.rs
+
+use anyhow::{Context, anyhow};
+use onionize::daemon::{
+    self, EXIT_BOOTSTRAP, EXIT_CONFIG, EXIT_FAILURE, Failure, Notifier, PidFile,
+};
+
+#[test]
+fn test_exit_codes() {
+    let config = Err::<(), _>(anyhow!("bad key"))
+        .context(Failure::Config)
+        .unwrap_err();
+    assert_eq!(daemon::exit_code(&config), EXIT_CONFIG);
+
+    // The failure is found below later context
+    let bootstrap = Err::<(), _>(anyhow!("no consensus"))
+        .context(Failure::Bootstrap)
+        .context("while starting")
+        .unwrap_err();
+    assert_eq!(daemon::exit_code(&bootstrap), EXIT_BOOTSTRAP);
+
+    assert_eq!(daemon::exit_code(&anyhow!("loop crashed")), EXIT_FAILURE);
+}
+
+#[test]
+fn test_pid_file() {
+    let path = std::env::temp_dir().join(format!("onionize-test-{}.pid", std::process::id()));
+
+    let pid_file = PidFile::create(&path).unwrap();
+    assert_eq!(pid_file.path(), path);
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        format!("{}\n", std::process::id())
+    );
+
+    drop(pid_file);
+    assert!(!path.exists());
+}
+
+#[test]
+fn test_disabled_notifier() {
+    let notifier = Notifier::default();
+    assert!(!notifier.is_enabled());
+    notifier.notify("READY=1\n").unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_notifications() {
+    use std::os::unix::net::UnixDatagram;
+
+    let path = std::env::temp_dir().join(format!("onionize-notify-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let socket = UnixDatagram::bind(&path).unwrap();
+    let notifier = Notifier::new(path.to_str().unwrap());
+    assert!(notifier.is_enabled());
+
+    let mut buf = [0u8; 256];
+    let mut receive = || {
+        let len = socket.recv(&mut buf).unwrap();
+        String::from_utf8(buf[..len].to_vec()).unwrap()
+    };
+
+    notifier.ready("Onion service available at example.onion");
+    assert_eq!(
+        receive(),
+        "READY=1\nSTATUS=Onion service available at example.onion\n"
+    );
+
+    // A status must not inject other assignments
+    notifier.status("line one\nREADY=1");
+    assert_eq!(receive(), "STATUS=line one READY=1\n");
+
+    notifier.stopping("Shutting down");
+    assert_eq!(receive(), "STOPPING=1\nSTATUS=Shutting down\n");
+
+    notifier.watchdog();
+    assert_eq!(receive(), "WATCHDOG=1\n");
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_watchdog_pings() {
+    use futures::task::SpawnExt;
+    use std::os::unix::net::UnixDatagram;
+    use std::time::Duration;
+    use tor_rtcompat::ToplevelBlockOn;
+    use tor_rtmock::MockRuntime;
+
+    let path = std::env::temp_dir().join(format!("onionize-watchdog-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let socket = UnixDatagram::bind(&path).unwrap();
+    socket.set_nonblocking(true).unwrap();
+    let notifier = Notifier::new(path.to_str().unwrap());
+
+    let runtime = MockRuntime::new();
+    runtime.clone().block_on(async move {
+        runtime
+            .spawn(daemon::run_watchdog(
+                runtime.clone(),
+                notifier,
+                Duration::from_secs(10),
+            ))
+            .unwrap();
+
+        // One ping right away, then one every 5 seconds
+        runtime.advance_by(Duration::from_secs(11)).await;
+    });
+
+    let mut buf = [0u8; 64];
+    let mut pings = 0;
+    while let Ok(len) = socket.recv(&mut buf) {
+        assert_eq!(&buf[..len], b"WATCHDOG=1\n");
+        pings += 1;
+    }
+    assert_eq!(pings, 3);
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_wait_for_status() {
+    use std::io::Write;
+
+    // The detached child reports the exit code of its start
+    let (reader, mut writer) = std::io::pipe().unwrap();
+    writer.write_all(&[EXIT_CONFIG]).unwrap();
+    drop(writer);
+    assert_eq!(daemon::wait_for_status(reader), EXIT_CONFIG);
+
+    // A child that dies without reporting is a failure
+    let (reader, writer) = std::io::pipe().unwrap();
+    drop(writer);
+    assert_eq!(daemon::wait_for_status(reader), EXIT_FAILURE);
+
+    // Without a detached parent, reporting does nothing
+    daemon::report_to_parent(0);
+}
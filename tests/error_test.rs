@@ -0,0 +1,78 @@
+// tests/error_test.rs
+
+use onionize::maintenance::MaintenancePage;
+use onionize::proxy::PortMap;
+use onionize::tor::RestrictedClient;
+use onionize::torcontrol::ControlPassword;
+use onionize::{Error, connect, keygen};
+use std::error::Error as _;
+use tor_cell::relaycell::msg::EndReason;
+
+#[test]
+fn test_errors_have_variants() {
+    assert!(matches!(
+        keygen::parse_client_secret("not-a-key"),
+        Err(Error::InvalidKey)
+    ));
+    assert!(matches!(
+        keygen::parse_auth_private("no separator"),
+        Err(Error::InvalidAuthPrivate)
+    ));
+    assert!(matches!(
+        RestrictedClient::new("descriptor:x25519:garbage", None),
+        Err(Error::InvalidAuthKey(_))
+    ));
+
+    let key = keygen::generate_keys().server_string;
+    assert!(matches!(
+        RestrictedClient::new(&key, Some("not a nickname")),
+        Err(Error::InvalidClientNickname(nick)) if nick == "not a nickname"
+    ));
+}
+
+#[test]
+fn test_errors_keep_sources() {
+    let err = PortMap::new().insert(80, "localhost:8080").unwrap_err();
+    assert!(matches!(&err, Error::InvalidTarget { target, .. } if target == "localhost:8080"));
+    assert!(err.source().is_some());
+
+    let dir = std::env::temp_dir().join(format!("onionize-missing-{}", std::process::id()));
+    let err = keygen::read_auth_private_dir(&dir).err().unwrap();
+    assert!(matches!(&err, Error::ReadAuthDir { .. }));
+    let source = err.source().unwrap().downcast_ref::<std::io::Error>();
+    assert_eq!(source.unwrap().kind(), std::io::ErrorKind::NotFound);
+}
+
+#[test]
+fn test_remote_close_is_recognized() {
+    let end: std::io::Error = tor_proto::Error::EndReceived(EndReason::MISC).into();
+    assert!(matches!(Error::from(end), Error::RemoteClosed(_)));
+
+    let reset = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+    assert!(matches!(Error::from(reset), Error::Io(_)));
+}
+
+#[test]
+fn test_errors_show_rejected_values() {
+    assert_eq!(
+        Error::InvalidNickname("not a nickname".to_string()).to_string(),
+        "Invalid nickname: not a nickname"
+    );
+}
+
+#[test]
+fn test_serving_errors_have_variants() {
+    assert!(matches!(
+        connect::parse_onion_target("example.com:80"),
+        Err(Error::InvalidOnionTarget(target)) if target == "example.com:80"
+    ));
+    assert!(matches!(
+        ControlPassword::from_hashed("16:not-hex"),
+        Err(Error::InvalidHashedPassword)
+    ));
+
+    let file = std::env::temp_dir().join(format!("onionize-no-page-{}", std::process::id()));
+    let err = MaintenancePage::from_file(&file).unwrap_err();
+    assert!(matches!(&err, Error::MaintenancePage { file: path, .. } if *path == file));
+    assert!(err.source().is_some());
+}
@@ -0,0 +1,303 @@
+// tests/hooks_test.rs
+
+use futures::stream::{self, BoxStream};
+use futures::task::SpawnExt;
+use futures::{AsyncReadExt, AsyncWriteExt, StreamExt};
+use onionize::backend::HandlerBackend;
+use onionize::hooks::{Hooks, PortMetrics, StreamDecision, StreamHook, StreamInfo, StreamSummary};
+use onionize::proxy::{self, CloseReason, PendingCircuit, PendingStream, ProxyOptions};
+use onionize::ratelimit::Direction;
+use onionize::shutdown::ShutdownHandle;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tor_rtcompat::{NetStreamListener, NetStreamProvider, ToplevelBlockOn};
+use tor_rtmock::io::LocalStream;
+use tor_rtmock::{MockRuntime, net::MockNetwork};
+
+/// Records every call as a line of text.
+#[derive(Clone, Default)]
+struct Recorder {
+    calls: Arc<Mutex<Vec<String>>>,
+}
+
+impl Recorder {
+    fn calls(&self) -> Vec<String> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    fn push(&self, call: String) {
+        self.calls.lock().unwrap().push(call);
+    }
+}
+
+impl StreamHook for Recorder {
+    fn on_circuit_opened(&self, circuit_id: u64) {
+        self.push(format!("circuit {circuit_id}"));
+    }
+
+    fn on_backend_connected(&self, stream: &StreamInfo, target: &str, _latency: Duration) {
+        self.push(format!(
+            "connected {}:{} {target}",
+            stream.circuit_id, stream.virtual_port
+        ));
+    }
+
+    fn on_transfer(&self, _stream: &StreamInfo, direction: Direction, bytes: usize) {
+        self.push(format!("{direction:?} {bytes}"));
+    }
+
+    fn on_stream_closed(&self, stream: &StreamInfo, summary: &StreamSummary) {
+        self.push(format!(
+            "closed {}:{} {} {}/{}",
+            stream.circuit_id,
+            stream.virtual_port,
+            summary.reason,
+            summary.bytes_up,
+            summary.bytes_down
+        ));
+    }
+}
+
+/// Rejects odd ports and sends port 8080 elsewhere.
+struct Policy;
+
+impl StreamHook for Policy {
+    fn on_stream_request(&self, stream: &StreamInfo) -> StreamDecision {
+        match stream.virtual_port {
+            8080 => StreamDecision::Remap("127.0.0.1:3001".to_string()),
+            port if port % 2 == 1 => StreamDecision::Reject,
+            _ => StreamDecision::Accept,
+        }
+    }
+}
+
+/// A rendezvous circuit whose stream requests are known in advance.
+struct MockCircuit(Vec<MockStream>);
+
+impl PendingCircuit for MockCircuit {
+    type Stream = MockStream;
+    type Error = Infallible;
+
+    async fn accept(self) -> Result<BoxStream<'static, MockStream>, Infallible> {
+        Ok(stream::iter(self.0).boxed())
+    }
+}
+
+/// A stream request to `port`, recording how it was answered.
+struct MockStream {
+    port: u16,
+    stream: LocalStream,
+    answers: Recorder,
+}
+
+impl PendingStream for MockStream {
+    type Stream = LocalStream;
+    type Error = Infallible;
+
+    fn port(&self) -> u16 {
+        self.port
+    }
+
+    async fn accept(self) -> Result<LocalStream, Infallible> {
+        self.answers.push(format!("accepted {}", self.port));
+        Ok(self.stream)
+    }
+
+    async fn reject(self) -> Result<(), Infallible> {
+        self.answers.push(format!("rejected {}", self.port));
+        Ok(())
+    }
+}
+
+#[test]
+fn test_hooks_follow_the_stream() {
+    let runtime = MockRuntime::new();
+
+    runtime.clone().block_on(async move {
+        let backend = HandlerBackend::new(runtime.clone(), |mut stream| async move {
+            let mut buf = [0u8; 4];
+            stream.read_exact(&mut buf).await.unwrap();
+            stream.write_all(b"PONG!").await.unwrap();
+            stream.close().await.unwrap();
+        });
+
+        let recorder = Recorder::default();
+        let metrics = PortMetrics::new();
+        let mut hooks = Hooks::new();
+        hooks.add(recorder.clone());
+        hooks.add(metrics.clone());
+
+        let options = ProxyOptions {
+            hooks: hooks.for_circuit(3).for_stream(80),
+            ..Default::default()
+        };
+
+        let (mut client_side, stream_dummy_tor) = tor_rtmock::io::stream_pair();
+        client_side.write_all(b"PING").await.unwrap();
+        client_side.close().await.unwrap();
+
+        let reason =
+            proxy::handle_connection_with_backend(runtime, stream_dummy_tor, &backend, &options)
+                .await
+                .unwrap();
+        assert_eq!(reason, CloseReason::Completed);
+
+        let calls = recorder.calls();
+        assert_eq!(calls[0], "circuit 3");
+        assert_eq!(calls[1], "connected 3:80 in-process handler");
+        assert!(calls.contains(&"Upload 4".to_string()));
+        assert!(calls.contains(&"Download 5".to_string()));
+        assert_eq!(calls.last().unwrap(), "closed 3:80 completed 4/5");
+
+        let ports = metrics.snapshot();
+        let port = &ports[&80];
+        assert_eq!(port.streams_closed, 1);
+        assert_eq!(port.errors, 0);
+        assert_eq!(port.bytes_up, 4);
+        assert_eq!(port.bytes_down, 5);
+    });
+}
+
+#[test]
+fn test_first_decision_wins() {
+    let mut hooks = Hooks::new();
+    assert!(hooks.is_empty());
+    assert_eq!(
+        hooks.for_stream(81).stream_request(),
+        StreamDecision::Accept
+    );
+
+    hooks.add(Recorder::default());
+    hooks.add(Policy);
+    hooks.add(Policy);
+    let circuit = hooks.for_circuit(1);
+
+    assert_eq!(
+        circuit.for_stream(80).stream_request(),
+        StreamDecision::Accept
+    );
+    assert_eq!(
+        circuit.for_stream(81).stream_request(),
+        StreamDecision::Reject
+    );
+    assert_eq!(
+        circuit.for_stream(8080).stream_request(),
+        StreamDecision::Remap("127.0.0.1:3001".to_string())
+    );
+    assert_eq!(
+        circuit.for_stream(443).stream(),
+        StreamInfo {
+            circuit_id: 1,
+            virtual_port: 443
+        }
+    );
+}
+
+#[test]
+fn test_hooks_see_unreachable_backend() {
+    let runtime = MockRuntime::new();
+
+    runtime.clone().block_on(async move {
+        let metrics = PortMetrics::new();
+        let mut hooks = Hooks::new();
+        hooks.add(metrics.clone());
+        let options = ProxyOptions {
+            hooks: hooks.for_stream(22),
+            ..Default::default()
+        };
+
+        let (_client_side, stream_dummy_tor) = tor_rtmock::io::stream_pair();
+        let backend = onionize::backend::TcpBackend::new(runtime.clone(), "127.0.0.1:9999");
+        let result =
+            proxy::handle_connection_with_backend(runtime, stream_dummy_tor, &backend, &options)
+                .await;
+        assert!(result.is_err());
+
+        let ports = metrics.snapshot();
+        assert_eq!(ports[&22].streams_closed, 1);
+        assert_eq!(ports[&22].errors, 1);
+    });
+}
+
+#[test]
+fn test_backend_loop_refuses_before_accepting() {
+    let network = MockNetwork::new();
+    let runtime = network
+        .builder()
+        .add_address("127.0.0.1".parse().unwrap())
+        .runtime(MockRuntime::new());
+
+    runtime.clone().block_on(async move {
+        // Target of the remapped port 8080
+        let target: SocketAddr = "127.0.0.1:3001".parse().unwrap();
+        let listener = runtime.listen(&target).await.unwrap();
+        runtime
+            .spawn(async move {
+                if let Some(Ok((mut socket, _))) = listener.incoming().next().await {
+                    socket.write_all(b"remapped").await.unwrap();
+                    socket.close().await.unwrap();
+                }
+            })
+            .unwrap();
+
+        let backend = HandlerBackend::new(runtime.clone(), |mut stream| async move {
+            stream.write_all(b"backend").await.unwrap();
+            stream.close().await.unwrap();
+        });
+
+        let recorder = Recorder::default();
+        let mut hooks = Hooks::new();
+        hooks.add(recorder.clone());
+        hooks.add(Policy);
+        let options = ProxyOptions {
+            hooks,
+            ..Default::default()
+        };
+        let stats = options.stats.clone();
+
+        let answers = Recorder::default();
+        let mut clients = Vec::new();
+        let mut requests = Vec::new();
+        for port in [80, 81, 8080] {
+            let (client_side, stream) = tor_rtmock::io::stream_pair();
+            clients.push(client_side);
+            requests.push(MockStream {
+                port,
+                stream,
+                answers: answers.clone(),
+            });
+        }
+
+        proxy::run_backend_loop(
+            runtime.clone(),
+            stream::iter([MockCircuit(requests)]),
+            Arc::new(backend),
+            options,
+            ShutdownHandle::new(),
+        )
+        .await;
+
+        let mut replies = Vec::new();
+        for client_side in &mut clients {
+            let _ = client_side.close().await;
+            let mut reply = String::new();
+            let _ = client_side.read_to_string(&mut reply).await;
+            replies.push(reply);
+        }
+
+        assert_eq!(replies, ["backend", "", "remapped"]);
+        assert_eq!(
+            answers.calls(),
+            ["accepted 80", "rejected 81", "accepted 8080"]
+        );
+        assert!(
+            recorder
+                .calls()
+                .contains(&"closed 1:81 rejected 0/0".to_string())
+        );
+        assert_eq!(stats.snapshot().requests_rejected, 1);
+        assert_eq!(stats.snapshot().streams_total, 2);
+    });
+}
@@ -0,0 +1,88 @@
+// tests/http_test.rs
+#![cfg(feature = "axum")]
+
+use axum::routing::get;
+use axum::{Extension, Router};
+use futures::{AsyncReadExt, AsyncWriteExt};
+use onionize::http::{OnionStream, serve_http_connection};
+use onionize::shutdown::ShutdownHandle;
+use tor_rtcompat::ToplevelBlockOn;
+use tor_rtmock::MockRuntime;
+
+fn router() -> Router {
+    Router::new().route(
+        "/whoami",
+        get(|Extension(info): Extension<OnionStream>| async move {
+            format!("circuit={} port={}", info.circuit_id, info.virtual_port)
+        }),
+    )
+}
+
+#[test]
+fn test_serves_router_with_stream_info() {
+    let runtime = MockRuntime::new();
+
+    runtime.clone().block_on(async move {
+        let (mut client_side, stream_dummy_tor) = tor_rtmock::io::stream_pair();
+        let info = OnionStream {
+            circuit_id: 7,
+            virtual_port: 80,
+        };
+        let server = serve_http_connection(
+            runtime,
+            stream_dummy_tor,
+            router(),
+            info,
+            ShutdownHandle::new(),
+        );
+
+        let client = async {
+            client_side
+                .write_all(
+                    b"GET /whoami HTTP/1.1\r\nHost: example.onion\r\nConnection: close\r\n\r\n",
+                )
+                .await
+                .unwrap();
+            let mut response = String::new();
+            client_side.read_to_string(&mut response).await.unwrap();
+            response
+        };
+
+        let (result, response) = futures::join!(server, client);
+        result.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        assert!(response.ends_with("circuit=7 port=80"), "{response}");
+    });
+}
+
+#[test]
+fn test_shutdown_closes_idle_connection() {
+    let runtime = MockRuntime::new();
+
+    runtime.clone().block_on(async move {
+        let (mut client_side, stream_dummy_tor) = tor_rtmock::io::stream_pair();
+        let shutdown = ShutdownHandle::new();
+        let info = OnionStream {
+            circuit_id: 1,
+            virtual_port: 443,
+        };
+        let server =
+            serve_http_connection(runtime, stream_dummy_tor, router(), info, shutdown.clone());
+
+        let client = async {
+            // Keep-alive request, the connection stays open after the response
+            client_side
+                .write_all(b"GET /missing HTTP/1.1\r\nHost: example.onion\r\n\r\n")
+                .await
+                .unwrap();
+            let mut buf = [0u8; 12];
+            client_side.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"HTTP/1.1 404");
+
+            shutdown.shutdown();
+        };
+
+        let (result, ()) = futures::join!(server, client);
+        result.unwrap();
+    });
+}
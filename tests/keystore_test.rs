@@ -0,0 +1,139 @@
+// tests/keystore_test.rs
+use onionize::Error;
+use onionize::keystore::{EncryptedKeystore, remove_stale_states};
+use onionize::secret::Secret;
+use std::path::PathBuf;
+
+/// A cheap scrypt work factor, the default one takes about a second.
+const TEST_WORK_FACTOR: u8 = 2;
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("onionize-{name}-{}", std::process::id()))
+}
+
+fn open(file: &PathBuf, passphrase: &str) -> Result<EncryptedKeystore, Error> {
+    let mut keystore = EncryptedKeystore::open(file, Secret::new(passphrase.to_string()))?;
+    keystore.set_work_factor(TEST_WORK_FACTOR);
+    Ok(keystore)
+}
+
+#[test]
+fn test_state_round_trip() {
+    let file = temp_path("state-round-trip.age");
+    let _ = std::fs::remove_file(&file);
+
+    let keystore = open(&file, "correct horse").unwrap();
+    assert!(keystore.is_new());
+    let state_dir = keystore.state_dir().to_path_buf();
+    std::fs::create_dir_all(state_dir.join("keystore")).unwrap();
+    std::fs::write(state_dir.join("keystore/hs_id.key"), b"secret key").unwrap();
+    keystore.save().unwrap();
+
+    // The encrypted file does not contain the plain text
+    let encrypted = std::fs::read(&file).unwrap();
+    assert!(encrypted.starts_with(b"age-encryption.org/v1"));
+    assert!(!encrypted.windows(10).any(|window| window == b"secret key"));
+
+    drop(keystore);
+    assert!(!state_dir.exists(), "the decrypted state is deleted");
+
+    let keystore = open(&file, "correct horse").unwrap();
+    assert!(!keystore.is_new());
+    assert_ne!(keystore.state_dir(), state_dir);
+    assert_eq!(
+        std::fs::read(keystore.state_dir().join("keystore/hs_id.key")).unwrap(),
+        b"secret key"
+    );
+    drop(keystore);
+    let _ = std::fs::remove_file(&file);
+}
+
+#[test]
+fn test_rekey_and_wrong_passphrase() {
+    let file = temp_path("state-rekey.age");
+    let _ = std::fs::remove_file(&file);
+    open(&file, "old passphrase").unwrap().save().unwrap();
+
+    assert!(matches!(
+        open(&file, "wrong passphrase"),
+        Err(Error::KeystorePassphrase(path)) if path == file
+    ));
+
+    let mut keystore = open(&file, "old passphrase").unwrap();
+    // A second process could save the state again with the old passphrase
+    assert!(matches!(
+        open(&file, "old passphrase"),
+        Err(Error::KeystoreLocked(path)) if path == file
+    ));
+    keystore
+        .rekey(Secret::new("new passphrase".to_string()))
+        .unwrap();
+    drop(keystore);
+
+    assert!(matches!(
+        open(&file, "old passphrase"),
+        Err(Error::KeystorePassphrase(_))
+    ));
+    assert!(open(&file, "new passphrase").is_ok());
+    let _ = std::fs::remove_file(&file);
+    let _ = std::fs::remove_file(file.with_extension("age.lock"));
+}
+
+#[test]
+fn test_import_keys_of_plain_state() {
+    let file = temp_path("state-import.age");
+    let _ = std::fs::remove_file(&file);
+    let plain_dir = temp_path("plain-state");
+    std::fs::create_dir_all(plain_dir.join("keystore/hss/my-service")).unwrap();
+    std::fs::write(
+        plain_dir.join("keystore/hss/my-service/ks_hs_id.ed25519_expanded_private"),
+        b"identity",
+    )
+    .unwrap();
+
+    let keystore = open(&file, "passphrase").unwrap();
+    assert!(!keystore.import_keys(&temp_path("no-state")).unwrap());
+    assert!(keystore.import_keys(&plain_dir).unwrap());
+    assert_eq!(
+        std::fs::read(
+            keystore
+                .state_dir()
+                .join("keystore/hss/my-service/ks_hs_id.ed25519_expanded_private")
+        )
+        .unwrap(),
+        b"identity"
+    );
+    // Saved before the plain copy is deleted
+    assert!(file.is_file());
+    assert!(!plain_dir.join("keystore").exists());
+
+    drop(keystore);
+    let _ = std::fs::remove_dir_all(&plain_dir);
+    let _ = std::fs::remove_file(&file);
+    let _ = std::fs::remove_file(file.with_extension("age.lock"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_remove_stale_states() {
+    let root = temp_path("stale-states");
+    let _ = std::fs::remove_dir_all(&root);
+    let dead = root.join("onionize-state-2147483647-0123456789abcdef");
+    let old_format = root.join("onionize-state-0123456789abcdef");
+    let alive = root.join(format!(
+        "onionize-state-{}-0123456789abcdef",
+        std::process::id()
+    ));
+    let other = root.join("something-else");
+    for dir in [&dead, &old_format, &alive, &other] {
+        std::fs::create_dir_all(dir).unwrap();
+    }
+
+    assert_eq!(remove_stale_states(&root), 2);
+    assert!(!dead.exists(), "the state of a dead process is deleted");
+    assert!(!old_format.exists());
+    assert!(alive.exists(), "the state of a running process is kept");
+    assert!(other.exists());
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,52 @@
+// tests/maintenance_test.rs
+
+use onionize::maintenance::MaintenancePage;
+
+#[test]
+fn test_builtin_json_page() {
+    rust_i18n::set_locale("en");
+    let page = MaintenancePage::from_arg("json").unwrap();
+
+    assert_eq!(page.content_type, "application/json");
+    let json: serde_json::Value = serde_json::from_slice(&page.body).unwrap();
+    assert_eq!(json["error"], "service_unavailable");
+}
+
+#[test]
+fn test_page_from_file() {
+    let path = std::env::temp_dir().join("onionize-maintenance-test.htm");
+    std::fs::write(&path, "<h1>Back soon</h1>").unwrap();
+
+    let page = MaintenancePage::from_arg(path.to_str().unwrap()).unwrap();
+
+    assert_eq!(page.content_type, "text/html; charset=utf-8");
+    assert_eq!(page.body, b"<h1>Back soon</h1>");
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_keywords_select_builtin_pages() {
+    let dir = std::env::temp_dir().join(format!("onionize-maintenance-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("html"), "custom").unwrap();
+
+    // A file named like a keyword is only loaded when given as a path
+    assert_eq!(
+        MaintenancePage::from_arg("html").unwrap(),
+        MaintenancePage::html()
+    );
+    let page = MaintenancePage::from_arg(dir.join("html").to_str().unwrap()).unwrap();
+    assert_eq!(page.body, b"custom");
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_page_from_missing_file() {
+    rust_i18n::set_locale("en");
+    let result = MaintenancePage::from_arg("/nonexistent/onionize/page.html");
+
+    let err = result.expect_err("Expected an error for a missing file");
+    assert!(err.to_string().contains("Failed to read maintenance page"));
+}
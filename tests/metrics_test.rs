@@ -0,0 +1,110 @@
+// tests/metrics_test.rs
+
+use futures::task::SpawnExt;
+use futures::{AsyncReadExt, AsyncWriteExt};
+use onionize::metrics;
+use onionize::stats::TrafficStats;
+use onionize::tor::TorStatus;
+use std::net::{IpAddr, SocketAddr};
+use tor_hsservice::status::State;
+use tor_rtcompat::{NetStreamProvider, ToplevelBlockOn};
+use tor_rtmock::{MockRuntime, net::MockNetwork};
+
+#[test]
+fn test_render_prometheus_text() {
+    let stats = TrafficStats::new();
+    let _circuit = stats.for_circuit();
+    stats.request_rejected();
+
+    let tor = TorStatus::new();
+    tor.record_bootstrap(1.0, true);
+    tor.record_service_state(State::Running, false);
+
+    let text = metrics::render(&stats.snapshot(), &tor.snapshot());
+
+    assert!(text.contains("# TYPE onionize_circuits_active gauge\nonionize_circuits_active 1\n"));
+    assert!(text.contains("onionize_requests_rejected_total 1\n"));
+    assert!(text.contains("onionize_bytes_total{direction=\"up\"} 0\n"));
+    assert!(text.contains("onionize_streams_closed_total{reason=\"idle_timeout\"} 0\n"));
+    assert!(text.contains("onionize_tor_ready 1\n"));
+    assert!(text.contains("onionize_service_state{state=\"running\"} 1\n"));
+    assert!(text.contains("onionize_service_state{state=\"broken\"} 0\n"));
+    assert!(text.contains("onionize_service_reachable_transitions_total 1\n"));
+}
+
+#[test]
+fn test_tor_status_counts_descriptor_events() {
+    let tor = TorStatus::new();
+
+    tor.record_service_state(State::Bootstrapping, false);
+    tor.record_service_state(State::Running, false);
+    // Repeated events do not count twice
+    tor.record_service_state(State::Running, false);
+    tor.record_service_state(State::DegradedUnreachable, true);
+    tor.record_service_state(State::DegradedUnreachable, true);
+    tor.record_service_state(State::Running, false);
+
+    let snapshot = tor.snapshot();
+    assert_eq!(snapshot.service_state, Some("running"));
+    assert_eq!(snapshot.reachable_transitions, 2);
+    assert_eq!(snapshot.descriptor_upload_failures, 1);
+    assert!(!snapshot.descriptor_failing);
+
+    // The same problem across state changes is a single failure
+    tor.record_service_state(State::DegradedReachable, true);
+    tor.record_service_state(State::Recovering, true);
+    tor.record_service_state(State::DegradedUnreachable, true);
+    let snapshot = tor.snapshot();
+    assert_eq!(snapshot.descriptor_upload_failures, 2);
+    assert!(snapshot.descriptor_failing);
+}
+
+#[test]
+fn test_metrics_server_serves_scrapes() {
+    let network = MockNetwork::new();
+    let local_ip: IpAddr = "127.0.0.1".parse().unwrap();
+    let runtime = network
+        .builder()
+        .add_address(local_ip)
+        .runtime(MockRuntime::new());
+
+    runtime.block_on(async {
+        let metrics_addr: SocketAddr = "127.0.0.1:9464".parse().unwrap();
+
+        let rt_server = runtime.clone();
+        runtime
+            .spawn(async move {
+                let _ = metrics::run_metrics_server(
+                    rt_server,
+                    metrics_addr,
+                    TrafficStats::new(),
+                    TorStatus::new(),
+                )
+                .await;
+            })
+            .unwrap();
+        runtime.inner().progress_until_stalled().await;
+
+        let mut client = runtime.connect(&metrics_addr).await.unwrap();
+        client
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("text/plain; version=0.0.4"));
+        assert!(response.contains("onionize_tor_ready 0\n"));
+
+        let mut client = runtime.connect(&metrics_addr).await.unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    });
+}
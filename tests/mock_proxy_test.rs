@@ -3,8 +3,12 @@
 use futures::channel::oneshot;
 use futures::task::SpawnExt;
 use futures::{AsyncReadExt, AsyncWriteExt, StreamExt};
+use onionize::events::Event;
+use onionize::maintenance::MaintenancePage;
 use onionize::proxy;
 use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
 use tor_rtcompat::{NetStreamListener, NetStreamProvider, ToplevelBlockOn};
 use tor_rtmock::{MockRuntime, net::MockNetwork};
 
@@ -211,3 +215,306 @@ fn test_server_disconnect_handling() {
         );
     });
 }
+
+/// Starts a backend on `bind_addr` that accepts one connection and never sends anything.
+async fn spawn_silent_backend<R>(runtime: &R, bind_addr: SocketAddr)
+where
+    R: NetStreamProvider<SocketAddr> + futures::task::Spawn,
+{
+    let listener = runtime.listen(&bind_addr).await.unwrap();
+    runtime
+        .spawn(async move {
+            let mut incoming = listener.incoming();
+            if let Some(Ok((mut socket, _))) = incoming.next().await {
+                // Keep the socket open until the proxy closes it
+                let mut buf = [0u8; 1024];
+                while matches!(socket.read(&mut buf).await, Ok(n) if n > 0) {}
+            }
+        })
+        .unwrap();
+}
+
+#[test]
+fn test_proxy_idle_timeout() {
+    let network = MockNetwork::new();
+    let target_ip: IpAddr = "127.0.0.1".parse().unwrap();
+    let runtime = network
+        .builder()
+        .add_address(target_ip)
+        .runtime(MockRuntime::new());
+
+    runtime.block_on(async {
+        let target_str = "127.0.0.1:8082";
+        spawn_silent_backend(&runtime, target_str.parse().unwrap()).await;
+
+        let (mut client_side, stream_dummy_tor) = tor_rtmock::io::stream_pair();
+        let timeouts = proxy::StreamTimeouts {
+            idle: Some(Duration::from_secs(30)),
+            ..Default::default()
+        };
+
+        let (tx, mut rx) = oneshot::channel();
+        let rt_for_proxy = runtime.clone();
+        runtime
+            .spawn(async move {
+                let result = proxy::handle_connection_with_timeouts(
+                    rt_for_proxy,
+                    stream_dummy_tor,
+                    target_str,
+                    &timeouts,
+                )
+                .await;
+                let _ = tx.send(result.unwrap());
+            })
+            .unwrap();
+
+        // Traffic in the middle of the period postpones the idle timeout
+        runtime.inner().advance_by(Duration::from_secs(20)).await;
+        client_side.write_all(b"ping").await.unwrap();
+        runtime.inner().advance_by(Duration::from_secs(20)).await;
+        assert!(rx.try_recv().unwrap().is_none(), "Stream closed too early");
+        runtime.inner().advance_by(Duration::from_secs(10)).await;
+
+        assert_eq!(rx.await.unwrap(), proxy::CloseReason::IdleTimeout);
+    });
+}
+
+#[test]
+fn test_proxy_max_lifetime() {
+    let network = MockNetwork::new();
+    let target_ip: IpAddr = "127.0.0.1".parse().unwrap();
+    let runtime = network
+        .builder()
+        .add_address(target_ip)
+        .runtime(MockRuntime::new());
+
+    runtime.block_on(async {
+        let target_str = "127.0.0.1:8083";
+        spawn_silent_backend(&runtime, target_str.parse().unwrap()).await;
+
+        let (_client_side, stream_dummy_tor) = tor_rtmock::io::stream_pair();
+        let timeouts = proxy::StreamTimeouts {
+            lifetime: Some(Duration::from_secs(60)),
+            ..Default::default()
+        };
+
+        let (tx, rx) = oneshot::channel();
+        let rt_for_proxy = runtime.clone();
+        runtime
+            .spawn(async move {
+                let result = proxy::handle_connection_with_timeouts(
+                    rt_for_proxy,
+                    stream_dummy_tor,
+                    target_str,
+                    &timeouts,
+                )
+                .await;
+                let _ = tx.send(result.unwrap());
+            })
+            .unwrap();
+
+        runtime.inner().advance_by(Duration::from_secs(60)).await;
+
+        assert_eq!(rx.await.unwrap(), proxy::CloseReason::LifetimeExceeded);
+    });
+}
+
+#[test]
+fn test_proxy_connect_timeout() {
+    rust_i18n::set_locale("en");
+    let network = MockNetwork::new();
+    let target_ip: IpAddr = "127.0.0.1".parse().unwrap();
+    let runtime = network
+        .builder()
+        .add_address(target_ip)
+        .runtime(MockRuntime::new());
+
+    runtime.block_on(async {
+        let target_str = "127.0.0.1:8084";
+        // Connections to a blackhole never complete
+        network.add_blackhole(target_str.parse().unwrap()).unwrap();
+
+        let (_client_side, stream_dummy_tor) = tor_rtmock::io::stream_pair();
+        let timeouts = proxy::StreamTimeouts {
+            connect: Some(Duration::from_secs(10)),
+            ..Default::default()
+        };
+
+        let (tx, rx) = oneshot::channel();
+        let rt_for_proxy = runtime.clone();
+        runtime
+            .spawn(async move {
+                let result = proxy::handle_connection_with_timeouts(
+                    rt_for_proxy,
+                    stream_dummy_tor,
+                    target_str,
+                    &timeouts,
+                )
+                .await;
+                let _ = tx.send(result.map_err(|e| e.to_string()));
+            })
+            .unwrap();
+
+        runtime.inner().advance_by(Duration::from_secs(10)).await;
+
+        let err = rx.await.unwrap().expect_err("Expected a connect timeout");
+        assert!(err.contains("Timed out connecting to local service"));
+    });
+}
+
+#[test]
+fn test_stream_closed_event_json() {
+    let event = Event::StreamClosed {
+        reason: proxy::CloseReason::IdleTimeout,
+        bytes_up: 10,
+        bytes_down: 20,
+        duration_ms: 30_000,
+    };
+
+    let json: serde_json::Value = serde_json::to_value(&event).unwrap();
+
+    assert_eq!(json["event"], "stream_closed");
+    assert_eq!(json["reason"], "idle_timeout");
+    assert_eq!(json["bytes_down"], 20);
+}
+
+#[test]
+fn test_proxy_half_close_from_client() {
+    let network = MockNetwork::new();
+    let target_ip: IpAddr = "127.0.0.1".parse().unwrap();
+    let runtime = network
+        .builder()
+        .add_address(target_ip)
+        .runtime(MockRuntime::new());
+
+    runtime.block_on(async {
+        let target_str = "127.0.0.1:8085";
+        let bind_addr: SocketAddr = target_str.parse().unwrap();
+        let listener = runtime.listen(&bind_addr).await.unwrap();
+
+        // Server task: read the whole request until EOF, then answer
+        runtime
+            .spawn(async move {
+                let mut incoming = listener.incoming();
+                if let Some(Ok((mut socket, _))) = incoming.next().await {
+                    let mut request = Vec::new();
+                    socket.read_to_end(&mut request).await.unwrap();
+                    assert_eq!(request, b"REQUEST");
+                    socket.write_all(b"RESPONSE").await.unwrap();
+                    socket.close().await.unwrap();
+                }
+            })
+            .unwrap();
+
+        let (mut client_side, stream_dummy_tor) = tor_rtmock::io::stream_pair();
+
+        let (tx, rx) = oneshot::channel();
+        let rt_for_proxy = runtime.clone();
+        runtime
+            .spawn(async move {
+                let result =
+                    proxy::handle_connection(rt_for_proxy, stream_dummy_tor, target_str).await;
+                let _ = tx.send(result.is_ok());
+            })
+            .unwrap();
+
+        // Send the request, then shut down our write half like `nc -N`
+        client_side.write_all(b"REQUEST").await.unwrap();
+        client_side.close().await.unwrap();
+
+        // The response still flows back after the half-close
+        let mut response = Vec::new();
+        client_side.read_to_end(&mut response).await.unwrap();
+        assert_eq!(response, b"RESPONSE");
+
+        assert!(
+            rx.await.unwrap(),
+            "Proxy should complete after both sides finish"
+        );
+    });
+}
+
+#[test]
+fn test_proxy_half_close_from_server() {
+    let network = MockNetwork::new();
+    let target_ip: IpAddr = "127.0.0.1".parse().unwrap();
+    let runtime = network
+        .builder()
+        .add_address(target_ip)
+        .runtime(MockRuntime::new());
+
+    runtime.block_on(async {
+        let target_str = "127.0.0.1:8086";
+        let bind_addr: SocketAddr = target_str.parse().unwrap();
+        let listener = runtime.listen(&bind_addr).await.unwrap();
+
+        // Server task: greet, shut down the write half, then keep reading
+        let (server_tx, server_rx) = oneshot::channel();
+        runtime
+            .spawn(async move {
+                let mut incoming = listener.incoming();
+                if let Some(Ok((mut socket, _))) = incoming.next().await {
+                    socket.write_all(b"HELLO").await.unwrap();
+                    socket.close().await.unwrap();
+
+                    let mut received = Vec::new();
+                    socket.read_to_end(&mut received).await.unwrap();
+                    let _ = server_tx.send(received);
+                }
+            })
+            .unwrap();
+
+        let (mut client_side, stream_dummy_tor) = tor_rtmock::io::stream_pair();
+
+        let rt_for_proxy = runtime.clone();
+        runtime
+            .spawn(async move {
+                let _ = proxy::handle_connection(rt_for_proxy, stream_dummy_tor, target_str).await;
+            })
+            .unwrap();
+
+        // The server's EOF reaches the client
+        let mut greeting = Vec::new();
+        client_side.read_to_end(&mut greeting).await.unwrap();
+        assert_eq!(greeting, b"HELLO");
+
+        // The client can still send data to the server
+        client_side.write_all(b"LATE DATA").await.unwrap();
+        client_side.close().await.unwrap();
+
+        assert_eq!(server_rx.await.unwrap(), b"LATE DATA");
+    });
+}
+
+#[test]
+fn test_proxy_sends_maintenance_page_when_backend_down() {
+    rust_i18n::set_locale("en");
+    let network = MockNetwork::new();
+    // No address is added, so connecting to the backend is refused
+    let runtime = network.builder().runtime(MockRuntime::new());
+
+    runtime.block_on(async {
+        let (mut client_side, stream_dummy_tor) = tor_rtmock::io::stream_pair();
+        let options = proxy::ProxyOptions {
+            maintenance: Some(Arc::new(MaintenancePage::html())),
+            ..Default::default()
+        };
+
+        let result = proxy::handle_connection_with_options(
+            runtime.clone(),
+            stream_dummy_tor,
+            "127.0.0.1:9999",
+            &options,
+        )
+        .await;
+        assert!(result.is_err(), "Backend failure should still be reported");
+
+        let mut response = Vec::new();
+        client_side.read_to_end(&mut response).await.unwrap();
+        let response = String::from_utf8(response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 502 Bad Gateway\r\n"));
+        assert!(response.contains("Content-Type: text/html"));
+        assert!(response.contains("Service temporarily unavailable"));
+    });
+}
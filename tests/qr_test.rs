@@ -0,0 +1,89 @@
+// tests/qr_test.rs
+use onionize::qr::{self, ImageFormat, TerminalStyle};
+use onionize::{Error, keygen};
+use std::path::Path;
+
+const ADDRESS: &str = "2gzyxa5ihm7nsggfxnu52rck2vv4rvmdlkiu3zzui5du4xyclen53wid.onion";
+
+#[test]
+fn test_terminal_style_fits_width() {
+    let code = qr::encode(&format!("http://{ADDRESS}")).unwrap();
+    let dense = TerminalStyle::Dense.width(&code);
+    let full = TerminalStyle::FullBlock.width(&code);
+    assert_eq!(full, 2 * dense);
+
+    assert_eq!(
+        TerminalStyle::fitting(&code, full),
+        Some(TerminalStyle::FullBlock)
+    );
+    assert_eq!(
+        TerminalStyle::fitting(&code, full - 1),
+        Some(TerminalStyle::Dense)
+    );
+    assert_eq!(TerminalStyle::fitting(&code, dense - 1), None);
+
+    for style in [TerminalStyle::FullBlock, TerminalStyle::Dense] {
+        let image = qr::render_terminal(&code, style);
+        assert!(
+            image
+                .lines()
+                .all(|line| line.chars().count() == style.width(&code))
+        );
+    }
+}
+
+#[test]
+fn test_credential_qr_code() {
+    let keys = keygen::generate_keys();
+    let secret = keygen::parse_client_secret(&keys.client_string).unwrap();
+    let line = keygen::auth_private_line(ADDRESS, &secret);
+    assert!(line.starts_with(&ADDRESS[..56]));
+
+    let entry = keygen::parse_auth_private(&line).unwrap();
+    assert_eq!(entry.onion_address, ADDRESS);
+    assert_eq!(entry.secret, secret);
+
+    // Still fits next to a log in an 80 column terminal
+    let code = qr::encode(&line).unwrap();
+    assert_eq!(
+        TerminalStyle::fitting(&code, 80),
+        Some(TerminalStyle::Dense)
+    );
+}
+
+#[test]
+fn test_write_qr_images() {
+    let code = qr::encode(&format!("http://{ADDRESS}")).unwrap();
+    let dir = std::env::temp_dir();
+
+    let png = dir.join(format!("onionize-qr-{}.png", std::process::id()));
+    qr::write_file(&code, &png).unwrap();
+    assert!(std::fs::read(&png).unwrap().starts_with(b"\x89PNG\r\n"));
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&png).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // An existing readable file is restricted when overwritten
+        std::fs::set_permissions(&png, std::fs::Permissions::from_mode(0o644)).unwrap();
+        qr::write_file(&code, &png).unwrap();
+        let mode = std::fs::metadata(&png).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+    let _ = std::fs::remove_file(&png);
+
+    let svg = dir.join(format!("onionize-qr-{}.SVG", std::process::id()));
+    qr::write_file(&code, &svg).unwrap();
+    assert!(std::fs::read_to_string(&svg).unwrap().contains("<svg"));
+    let _ = std::fs::remove_file(&svg);
+
+    assert_eq!(
+        ImageFormat::from_path(Path::new("code.png")).unwrap(),
+        ImageFormat::Png
+    );
+    assert!(matches!(
+        qr::write_file(&code, Path::new("code.jpg")),
+        Err(Error::QrFormat(file)) if file == Path::new("code.jpg")
+    ));
+}
@@ -0,0 +1,145 @@
+// tests/ratelimit_test.rs
+
+use futures::channel::oneshot;
+use futures::task::SpawnExt;
+use futures::{AsyncReadExt, AsyncWriteExt, StreamExt};
+use onionize::proxy::{self, ProxyOptions};
+use onionize::ratelimit::{BandwidthLimits, Direction, RateLimiter, TokenBucket};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tor_rtcompat::{NetStreamListener, NetStreamProvider, SleepProvider, ToplevelBlockOn};
+use tor_rtmock::{MockRuntime, net::MockNetwork};
+
+#[test]
+fn test_token_bucket_allows_burst_then_waits() {
+    let runtime = MockRuntime::new();
+    let bucket = TokenBucket::new(1000);
+    let start = runtime.now();
+
+    // A full bucket holds one second worth of bytes
+    assert_eq!(bucket.take(start, 1000), Duration::ZERO);
+    // Going into debt requires waiting until it is paid off
+    assert_eq!(bucket.take(start, 500), Duration::from_millis(500));
+    // Half a second later the debt is paid, the next 1000 bytes need one more second
+    assert_eq!(
+        bucket.take(start + Duration::from_millis(500), 1000),
+        Duration::from_secs(1)
+    );
+}
+
+#[test]
+fn test_child_limiters_share_parent_buckets() {
+    let runtime = MockRuntime::new();
+
+    runtime.clone().block_on(async move {
+        let global = RateLimiter::new(BandwidthLimits {
+            upload: Some(1000),
+            download: None,
+        });
+        let circuit_a = global.child(BandwidthLimits::default());
+        let circuit_b = global.child(BandwidthLimits::default());
+
+        let (tx, mut rx) = oneshot::channel();
+        let rt_task = runtime.clone();
+        runtime
+            .spawn(async move {
+                let start = rt_task.now();
+                circuit_a.throttle(&rt_task, Direction::Upload, 1000).await;
+                circuit_b.throttle(&rt_task, Direction::Upload, 1000).await;
+                // Downloads are not limited
+                circuit_b
+                    .throttle(&rt_task, Direction::Download, 1_000_000)
+                    .await;
+                let _ = tx.send(rt_task.now() - start);
+            })
+            .unwrap();
+
+        runtime.progress_until_stalled().await;
+        assert!(
+            rx.try_recv().unwrap().is_none(),
+            "Shared bucket should be empty"
+        );
+
+        runtime.advance_by(Duration::from_secs(1)).await;
+        assert_eq!(rx.await.unwrap(), Duration::from_secs(1));
+    });
+}
+
+#[test]
+fn test_proxy_download_limit() {
+    let network = MockNetwork::new();
+    let target_ip: IpAddr = "127.0.0.1".parse().unwrap();
+    let runtime = network
+        .builder()
+        .add_address(target_ip)
+        .runtime(MockRuntime::new());
+
+    runtime.block_on(async {
+        let target_str = "127.0.0.1:8090";
+        let bind_addr: SocketAddr = target_str.parse().unwrap();
+        let listener = runtime.listen(&bind_addr).await.unwrap();
+
+        let data_size = 40 * 1024;
+
+        // Server task: send 40 KiB and close
+        runtime
+            .spawn(async move {
+                let mut incoming = listener.incoming();
+                if let Some(Ok((mut socket, _))) = incoming.next().await {
+                    socket.write_all(&vec![7u8; data_size]).await.unwrap();
+                    socket.close().await.unwrap();
+                }
+            })
+            .unwrap();
+
+        let (mut client_side, stream_dummy_tor) = tor_rtmock::io::stream_pair();
+
+        let options = ProxyOptions {
+            rate_limiter: RateLimiter::new(BandwidthLimits {
+                upload: None,
+                download: Some(10 * 1024),
+            }),
+            ..Default::default()
+        };
+        let rt_for_proxy = runtime.clone();
+        runtime
+            .spawn(async move {
+                let _ = proxy::handle_connection_with_options(
+                    rt_for_proxy,
+                    stream_dummy_tor,
+                    target_str,
+                    &options,
+                )
+                .await;
+            })
+            .unwrap();
+
+        let received = Arc::new(AtomicUsize::new(0));
+        let counter = received.clone();
+        runtime
+            .spawn(async move {
+                let mut buf = [0u8; 4096];
+                while let Ok(n) = client_side.read(&mut buf).await {
+                    if n == 0 {
+                        break;
+                    }
+                    counter.fetch_add(n, Ordering::SeqCst);
+                }
+            })
+            .unwrap();
+
+        runtime.inner().progress_until_stalled().await;
+        let at_start = received.load(Ordering::SeqCst);
+        assert!(at_start < data_size, "Limit should hold back the transfer");
+
+        runtime.inner().advance_by(Duration::from_secs(1)).await;
+        let after_one_second = received.load(Ordering::SeqCst);
+        assert!(after_one_second > at_start);
+        assert!(after_one_second < data_size);
+
+        runtime.inner().advance_by(Duration::from_secs(3)).await;
+        assert_eq!(received.load(Ordering::SeqCst), data_size);
+    });
+}
@@ -0,0 +1,146 @@
+// tests/receive_test.rs
+
+use futures::task::SpawnExt;
+use futures::{AsyncReadExt, AsyncWriteExt};
+use onionize::receive::{self, ReceiveConfig};
+use std::path::PathBuf;
+use tor_rtcompat::ToplevelBlockOn;
+use tor_rtmock::MockRuntime;
+
+/// Creates an empty upload directory unique to the test.
+fn upload_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("onionize-receive-{}", name));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Builds a multipart/form-data POST request with the given files.
+fn upload_request(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let boundary = "----onionizeTestBoundary";
+    let mut body = Vec::new();
+    for (name, content) in files {
+        body.extend_from_slice(format!("--{}\r\n", boundary).as_bytes());
+        body.extend_from_slice(
+            format!(
+                "Content-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n",
+                name
+            )
+            .as_bytes(),
+        );
+        body.extend_from_slice(b"Content-Type: application/octet-stream\r\n\r\n");
+        body.extend_from_slice(content);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+
+    let mut request = format!(
+        "POST / HTTP/1.1\r\nHost: example.onion\r\nContent-Type: multipart/form-data; boundary={}\r\nContent-Length: {}\r\n\r\n",
+        boundary,
+        body.len()
+    )
+    .into_bytes();
+    request.extend_from_slice(&body);
+    request
+}
+
+/// Sends `request` to the receive handler and returns the raw response.
+fn exchange(config: ReceiveConfig, request: Vec<u8>) -> String {
+    let runtime = MockRuntime::new();
+
+    runtime.clone().block_on(async move {
+        let (mut client_side, server_side) = tor_rtmock::io::stream_pair();
+
+        let handler_runtime = runtime.clone();
+        runtime
+            .spawn(async move {
+                let _ = receive::handle_receive_connection(&handler_runtime, server_side, &config)
+                    .await;
+            })
+            .unwrap();
+
+        client_side.write_all(&request).await.unwrap();
+
+        let mut response = Vec::new();
+        client_side.read_to_end(&mut response).await.unwrap();
+        String::from_utf8_lossy(&response).into_owned()
+    })
+}
+
+#[test]
+fn test_get_returns_upload_form() {
+    let config = ReceiveConfig {
+        dir: upload_dir("form"),
+        max_size: 1024,
+    };
+
+    let response = exchange(config, b"GET / HTTP/1.1\r\nHost: x\r\n\r\n".to_vec());
+
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    assert!(response.contains("multipart/form-data"));
+    assert!(response.contains("type=\"file\""));
+}
+
+#[test]
+fn test_upload_is_stored_with_sanitized_name() {
+    let dir = upload_dir("store");
+    let config = ReceiveConfig {
+        dir: dir.clone(),
+        max_size: 1024 * 1024,
+    };
+
+    // Content containing CRLF and a partial boundary must survive untouched.
+    let content: &[u8] = b"line one\r\n------onionize\r\nline two";
+    let request = upload_request(&[("../../etc/passwd", content), ("report.log", b"LOG")]);
+
+    let response = exchange(config, request);
+
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    assert_eq!(std::fs::read(dir.join("passwd")).unwrap(), content);
+    assert_eq!(std::fs::read(dir.join("report.log")).unwrap(), b"LOG");
+    assert!(!dir.join("passwd.part").exists());
+}
+
+#[test]
+fn test_upload_does_not_overwrite_existing_files() {
+    let dir = upload_dir("collision");
+    std::fs::write(dir.join("notes.txt"), b"original").unwrap();
+    let config = ReceiveConfig {
+        dir: dir.clone(),
+        max_size: 1024,
+    };
+
+    let response = exchange(config, upload_request(&[("notes.txt", b"new")]));
+
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    assert_eq!(std::fs::read(dir.join("notes.txt")).unwrap(), b"original");
+    assert_eq!(std::fs::read(dir.join("notes-1.txt")).unwrap(), b"new");
+}
+
+#[test]
+fn test_upload_over_limit_is_rejected() {
+    let dir = upload_dir("limit");
+    let config = ReceiveConfig {
+        dir: dir.clone(),
+        max_size: 16,
+    };
+
+    let response = exchange(config, upload_request(&[("big.bin", &[7u8; 64])]));
+
+    assert!(response.starts_with("HTTP/1.1 413"), "{}", response);
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+}
+
+#[test]
+fn test_sanitize_filename() {
+    assert_eq!(receive::sanitize_filename("report.pdf"), "report.pdf");
+    assert_eq!(receive::sanitize_filename("../../etc/passwd"), "passwd");
+    assert_eq!(
+        receive::sanitize_filename("C:\\Users\\me\\a b.txt"),
+        "a_b.txt"
+    );
+    assert_eq!(receive::sanitize_filename(".bashrc"), "bashrc");
+    assert_eq!(receive::sanitize_filename(".."), "upload");
+    assert_eq!(receive::sanitize_filename(""), "upload");
+    assert_eq!(receive::sanitize_filename(&"a".repeat(500)).len(), 100);
+}
@@ -0,0 +1,50 @@
+// tests/secret_test.rs
+use onionize::keygen;
+use onionize::secret::{self, Secret};
+
+#[test]
+fn test_debug_redacts_secrets() {
+    let keys = keygen::generate_keys();
+    let secret_b32 = keys.client_string.rsplit(':').next().unwrap();
+
+    let debug = format!("{keys:?}");
+    assert!(debug.contains(&keys.public_b32), "{debug}");
+    assert!(debug.contains("[REDACTED]"), "{debug}");
+    assert!(!debug.contains(secret_b32), "{debug}");
+
+    let address = "a".repeat(56);
+    let entry =
+        keygen::parse_auth_private(&format!("{address}:descriptor:x25519:{secret_b32}")).unwrap();
+    let debug = format!("{entry:?}");
+    assert!(debug.contains(&address), "{debug}");
+    assert!(!debug.contains(&format!("{:?}", *entry.secret)), "{debug}");
+
+    let identity = keygen::generate_service_identity();
+    assert_eq!(
+        format!("{identity:?}"),
+        format!(
+            "ServiceIdentity {{ onion_address: {:?}, .. }}",
+            identity.onion_address
+        )
+    );
+}
+
+#[test]
+fn test_secret_with_memory_locking() {
+    secret::set_memory_locking(true);
+    assert!(secret::memory_locking());
+
+    // Locking may be refused by the sandbox, the secret works either way
+    let key = Secret::new(vec![7u8; 32]);
+    let copy = key.clone();
+    assert_eq!(copy, key);
+    assert_eq!(key, vec![7u8; 32]);
+    drop(key);
+    assert_eq!(copy.len(), 32);
+
+    let empty = Secret::new(String::new());
+    assert!(empty.is_empty());
+
+    secret::set_memory_locking(false);
+    assert!(!secret::memory_locking());
+}
@@ -0,0 +1,102 @@
+// tests/selftest_test.rs
+
+use futures::channel::oneshot;
+use futures::task::SpawnExt;
+use futures::{AsyncReadExt, AsyncWriteExt};
+use onionize::events::Event;
+use onionize::selftest;
+use tor_rtcompat::ToplevelBlockOn;
+use tor_rtmock::MockRuntime;
+
+#[test]
+fn test_probe_http_reads_status_line() {
+    let runtime = MockRuntime::new();
+
+    runtime.clone().block_on(async move {
+        let (probe_side, mut backend_side) = tor_rtmock::io::stream_pair();
+
+        runtime
+            .spawn(async move {
+                let mut buf = [0u8; 1024];
+                let n = backend_side.read(&mut buf).await.unwrap();
+                assert!(buf[..n].starts_with(b"HEAD / HTTP/1.0\r\n"));
+                backend_side
+                    .write_all(b"HTTP/1.0 200 OK\r\nServer: test\r\n\r\n")
+                    .await
+                    .unwrap();
+            })
+            .unwrap();
+
+        let response = selftest::probe_http(&runtime, probe_side, "example.onion")
+            .await
+            .unwrap()
+            .expect("Expected a response from the backend");
+
+        assert_eq!(response.status_line, "HTTP/1.0 200 OK");
+    });
+}
+
+#[test]
+fn test_probe_http_fails_when_stream_closes_without_response() {
+    rust_i18n::set_locale("en");
+    let runtime = MockRuntime::new();
+
+    runtime.clone().block_on(async move {
+        let (probe_side, mut backend_side) = tor_rtmock::io::stream_pair();
+
+        runtime
+            .spawn(async move {
+                let mut buf = [0u8; 1024];
+                let _ = backend_side.read(&mut buf).await;
+                backend_side.close().await.unwrap();
+            })
+            .unwrap();
+
+        let result = selftest::probe_http(&runtime, probe_side, "example.onion").await;
+
+        let err = result.expect_err("Expected an error for a silent close");
+        assert!(err.to_string().contains("Stream closed without a response"));
+    });
+}
+
+#[test]
+fn test_probe_http_times_out_on_silent_backend() {
+    let runtime = MockRuntime::new();
+
+    runtime.clone().block_on(async move {
+        // Keep the backend side open but never answer
+        let (probe_side, _backend_side) = tor_rtmock::io::stream_pair();
+        let (tx, rx) = oneshot::channel();
+
+        let rt_probe = runtime.clone();
+        runtime
+            .spawn(async move {
+                let result = selftest::probe_http(&rt_probe, probe_side, "example.onion").await;
+                let _ = tx.send(result.map(|r| r.is_none()));
+            })
+            .unwrap();
+
+        runtime.advance_by(selftest::RESPONSE_TIMEOUT).await;
+
+        let timed_out = rx.await.unwrap().unwrap();
+        assert!(timed_out, "Silent backend should yield no response");
+    });
+}
+
+#[test]
+fn test_self_test_event_json() {
+    let event = Event::SelfTest {
+        success: true,
+        connect_ms: Some(1200),
+        response_ms: Some(80),
+        status_line: Some("HTTP/1.0 200 OK".to_string()),
+        error: None,
+    };
+
+    let json: serde_json::Value = serde_json::to_value(&event).unwrap();
+
+    assert_eq!(json["event"], "self_test");
+    assert_eq!(json["success"], true);
+    assert_eq!(json["connect_ms"], 1200);
+    assert_eq!(json["status_line"], "HTTP/1.0 200 OK");
+}
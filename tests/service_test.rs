@@ -0,0 +1,120 @@
+// tests/service_test.rs
+
+use futures::StreamExt;
+use onionize::Onionizer;
+use onionize::events::{Event, EventBus};
+use onionize::keygen;
+use onionize::proxy::CloseReason;
+
+#[test]
+fn test_builder_needs_a_target() {
+    assert!(Onionizer::builder().build().is_err());
+
+    // Mapped ports are enough
+    assert!(
+        Onionizer::builder()
+            .map_port(80, "127.0.0.1:8080")
+            .build()
+            .is_ok()
+    );
+}
+
+#[test]
+fn test_builder_checks_targets_and_keys() {
+    assert!(
+        Onionizer::builder()
+            .target("localhost:8080")
+            .build()
+            .is_err()
+    );
+    assert!(
+        Onionizer::builder()
+            .target("127.0.0.1:8080")
+            .map_port(22, "not-an-address")
+            .build()
+            .is_err()
+    );
+    assert!(
+        Onionizer::builder()
+            .target("127.0.0.1:8080")
+            .authorize("descriptor:x25519:garbage")
+            .build()
+            .is_err()
+    );
+
+    let key = keygen::generate_keys().server_string;
+    assert!(
+        Onionizer::builder()
+            .target("127.0.0.1:8080")
+            .nickname("my-app")
+            .authorize(key)
+            .build()
+            .is_ok()
+    );
+}
+
+#[test]
+fn test_event_bus() {
+    let bus = EventBus::new();
+    let first = bus.subscribe();
+    let mut second = bus.subscribe();
+
+    let event = Event::ServiceReady {
+        onion_address: "example.onion".to_string(),
+        credential: None,
+    };
+    bus.publish(&event);
+
+    // Dropped subscribers do not stop the others
+    drop(first);
+    bus.publish(&event);
+
+    futures::executor::block_on(async {
+        assert_eq!(second.next().await, Some(event.clone()));
+        assert_eq!(second.next().await, Some(event));
+    });
+}
+
+#[test]
+fn test_event_bus_replays_service_ready() {
+    let bus = EventBus::new();
+    let ready = Event::ServiceReady {
+        onion_address: "example.onion".to_string(),
+        credential: None,
+    };
+    let closed = Event::StreamClosed {
+        reason: CloseReason::Completed,
+        bytes_up: 1,
+        bytes_down: 2,
+        duration_ms: 3,
+    };
+    bus.publish(&ready);
+    bus.publish(&closed);
+
+    // A late subscriber still learns that the service is ready, but not older streams
+    let mut late = bus.subscribe();
+    bus.publish(&closed);
+
+    futures::executor::block_on(async {
+        assert_eq!(late.next().await, Some(ready));
+        assert_eq!(late.next().await, Some(closed));
+    });
+}
+
+#[test]
+fn test_service_ready_event_json() {
+    let public = Event::ServiceReady {
+        onion_address: "example.onion".to_string(),
+        credential: None,
+    };
+    let json = serde_json::to_value(&public).unwrap();
+    assert_eq!(json["event"], "service_ready");
+    assert!(json.get("credential").is_none());
+
+    let restricted = Event::ServiceReady {
+        onion_address: "example.onion".to_string(),
+        credential: Some("example:descriptor:x25519:KEY".to_string()),
+    };
+    let json = serde_json::to_value(&restricted).unwrap();
+    assert_eq!(json["credential"], "example:descriptor:x25519:KEY");
+}
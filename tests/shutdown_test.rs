@@ -0,0 +1,219 @@
+// tests/shutdown_test.rs
+
+use futures::StreamExt;
+use futures::channel::{mpsc, oneshot};
+use futures::stream::BoxStream;
+use futures::task::SpawnExt;
+use onionize::proxy::{self, PendingCircuit, PendingStream};
+use onionize::shutdown::ShutdownHandle;
+use onionize::stats::TrafficStats;
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tor_rtcompat::{SleepProvider, ToplevelBlockOn};
+use tor_rtmock::MockRuntime;
+use tor_rtmock::io::LocalStream;
+
+/// A rendezvous circuit whose stream requests are sent over a channel.
+struct MockCircuit(mpsc::UnboundedReceiver<MockStream>);
+
+impl PendingCircuit for MockCircuit {
+    type Stream = MockStream;
+    type Error = Infallible;
+
+    async fn accept(self) -> Result<BoxStream<'static, MockStream>, Infallible> {
+        Ok(self.0.boxed())
+    }
+}
+
+/// A stream request to `port`, which records the port once accepted.
+struct MockStream {
+    port: u16,
+    stream: LocalStream,
+    accepted: Arc<Mutex<Vec<u16>>>,
+}
+
+impl PendingStream for MockStream {
+    type Stream = LocalStream;
+    type Error = Infallible;
+
+    fn port(&self) -> u16 {
+        self.port
+    }
+
+    async fn accept(self) -> Result<LocalStream, Infallible> {
+        self.accepted.lock().unwrap().push(self.port);
+        Ok(self.stream)
+    }
+
+    async fn reject(self) -> Result<(), Infallible> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_shutdown_wakes_waiters() {
+    let runtime = MockRuntime::new();
+
+    runtime.clone().block_on(async move {
+        let shutdown = ShutdownHandle::new();
+        let (tx, rx) = oneshot::channel();
+
+        let waiter = shutdown.clone();
+        runtime
+            .spawn(async move {
+                waiter.wait().await;
+                let _ = tx.send(());
+            })
+            .unwrap();
+
+        assert!(!shutdown.is_shutdown());
+        shutdown.shutdown();
+        assert!(shutdown.is_shutdown());
+
+        rx.await.expect("Waiter should be woken by shutdown");
+    });
+}
+
+#[test]
+fn test_drain_waits_for_active_streams() {
+    let runtime = MockRuntime::new();
+
+    runtime.clone().block_on(async move {
+        let shutdown = ShutdownHandle::new();
+        let active = shutdown.track();
+        assert_eq!(shutdown.active_streams(), 1);
+
+        // The stream finishes after 5 seconds
+        let rt_stream = runtime.clone();
+        runtime
+            .spawn(async move {
+                rt_stream.sleep(Duration::from_secs(5)).await;
+                drop(active);
+            })
+            .unwrap();
+
+        let (tx, rx) = oneshot::channel();
+        let rt_drain = runtime.clone();
+        let drain = shutdown.clone();
+        runtime
+            .spawn(async move {
+                let drained = drain.drain(&rt_drain, Duration::from_secs(30)).await;
+                let _ = tx.send(drained);
+            })
+            .unwrap();
+
+        // Drain checks the active streams once per second
+        runtime.advance_by(Duration::from_secs(6)).await;
+
+        assert!(rx.await.unwrap(), "All streams should finish in time");
+        assert_eq!(shutdown.active_streams(), 0);
+    });
+}
+
+#[test]
+fn test_drain_gives_up_after_grace_period() {
+    let runtime = MockRuntime::new();
+
+    runtime.clone().block_on(async move {
+        let shutdown = ShutdownHandle::new();
+        // Never finishes
+        let _active = shutdown.track();
+
+        let (tx, mut rx) = oneshot::channel();
+        let rt_drain = runtime.clone();
+        let drain = shutdown.clone();
+        runtime
+            .spawn(async move {
+                let drained = drain.drain(&rt_drain, Duration::from_secs(10)).await;
+                let _ = tx.send(drained);
+            })
+            .unwrap();
+
+        runtime.advance_by(Duration::from_secs(9)).await;
+        assert!(rx.try_recv().unwrap().is_none(), "Drain ended too early");
+
+        runtime.advance_by(Duration::from_secs(1)).await;
+        assert!(!rx.await.unwrap(), "Drain should report unfinished streams");
+    });
+}
+
+#[test]
+fn test_child_handle() {
+    let runtime = MockRuntime::new();
+
+    runtime.clone().block_on(async move {
+        let parent = ShutdownHandle::new();
+
+        // Shutting down a child leaves the parent running
+        let child = parent.child();
+        let _active = child.track();
+        assert_eq!(parent.active_streams(), 1);
+        child.shutdown();
+        child.wait().await;
+        assert!(!parent.is_shutdown());
+
+        // Shutting down the parent stops every child
+        let other = parent.child();
+        let (tx, rx) = oneshot::channel();
+        let waiter = other.clone();
+        runtime
+            .spawn(async move {
+                waiter.wait().await;
+                let _ = tx.send(());
+            })
+            .unwrap();
+
+        parent.shutdown();
+        assert!(other.is_shutdown());
+        rx.await.expect("Child should be woken by the parent");
+    });
+}
+
+#[test]
+fn test_stream_loop_stops_accepting_after_shutdown() {
+    let runtime = MockRuntime::new();
+
+    runtime.clone().block_on(async move {
+        let shutdown = ShutdownHandle::new();
+        let accepted = Arc::new(Mutex::new(Vec::new()));
+        let request = |port| MockStream {
+            port,
+            stream: tor_rtmock::io::stream_pair().1,
+            accepted: accepted.clone(),
+        };
+
+        let (circuits_tx, circuits_rx) = mpsc::unbounded();
+        let (requests_tx, requests_rx) = mpsc::unbounded();
+        circuits_tx
+            .unbounded_send(MockCircuit(requests_rx))
+            .unwrap();
+
+        let (tx, rx) = oneshot::channel();
+        let rt_loop = runtime.clone();
+        let stop = shutdown.clone();
+        runtime
+            .spawn(async move {
+                proxy::run_stream_loop(rt_loop, circuits_rx, stop, TrafficStats::new(), |_| {
+                    |_tor_stream, _port| async { Ok(()) }
+                })
+                .await;
+                let _ = tx.send(());
+            })
+            .unwrap();
+
+        requests_tx.unbounded_send(request(80)).unwrap();
+        runtime.progress_until_stalled().await;
+        assert_eq!(*accepted.lock().unwrap(), [80]);
+
+        shutdown.shutdown();
+        // The loop returns although more circuits could still arrive
+        rx.await.expect("Loop should stop on shutdown");
+
+        let _ = requests_tx.unbounded_send(request(81));
+        let _ = circuits_tx.unbounded_send(MockCircuit(mpsc::unbounded().1));
+        runtime.progress_until_stalled().await;
+        assert_eq!(*accepted.lock().unwrap(), [80]);
+        assert!(circuits_tx.is_closed());
+    });
+}
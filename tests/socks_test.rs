@@ -0,0 +1,136 @@
+// tests/socks_test.rs
+
+use arti_client::isolation::Isolation;
+use futures::channel::mpsc;
+use futures::task::SpawnExt;
+use futures::{AsyncReadExt, AsyncWriteExt, StreamExt};
+use onionize::socks::{self, SocksTarget};
+use std::net::{IpAddr, SocketAddr};
+use tor_rtcompat::{NetStreamProvider, ToplevelBlockOn};
+use tor_rtmock::{MockRuntime, net::MockNetwork};
+
+/// SOCKS5 CONNECT to `host:port` using username/password authentication.
+async fn socks5_connect<S>(stream: &mut S, user: &[u8], pass: &[u8], host: &str, port: u16) -> u8
+where
+    S: futures::AsyncRead + futures::AsyncWrite + Unpin,
+{
+    // Greeting: version 5, one method, username/password
+    stream.write_all(&[5, 1, 2]).await.unwrap();
+    let mut method = [0u8; 2];
+    stream.read_exact(&mut method).await.unwrap();
+    assert_eq!(method, [5, 2]);
+
+    let mut auth = vec![1, user.len() as u8];
+    auth.extend_from_slice(user);
+    auth.push(pass.len() as u8);
+    auth.extend_from_slice(pass);
+    stream.write_all(&auth).await.unwrap();
+    let mut auth_reply = [0u8; 2];
+    stream.read_exact(&mut auth_reply).await.unwrap();
+    assert_eq!(auth_reply, [1, 0]);
+
+    let mut request = vec![5, 1, 0, 3, host.len() as u8];
+    request.extend_from_slice(host.as_bytes());
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await.unwrap();
+
+    // Reply: version, status, reserved, IPv4 address type + 4 bytes + port
+    let mut reply = [0u8; 10];
+    stream.read_exact(&mut reply).await.unwrap();
+    reply[1]
+}
+
+#[test]
+fn test_socks_proxy_forwards_with_isolation() {
+    let network = MockNetwork::new();
+    let local_ip: IpAddr = "127.0.0.1".parse().unwrap();
+    let runtime = network
+        .builder()
+        .add_address(local_ip)
+        .runtime(MockRuntime::new());
+
+    runtime.block_on(async {
+        let socks_addr: SocketAddr = "127.0.0.1:9150".parse().unwrap();
+        let (targets_tx, mut targets_rx) = mpsc::unbounded::<SocksTarget>();
+
+        // Stands in for Tor: records the target and echoes 4 bytes back.
+        let rt_remote = runtime.clone();
+        let dial = move |target: SocksTarget| {
+            targets_tx.unbounded_send(target).unwrap();
+            let (tor_side, mut remote_side) = tor_rtmock::io::stream_pair();
+            rt_remote
+                .spawn(async move {
+                    let mut buf = [0u8; 4];
+                    if remote_side.read_exact(&mut buf).await.is_ok() {
+                        let _ = remote_side.write_all(&buf).await;
+                    }
+                })
+                .unwrap();
+            async move { Ok(tor_side) }
+        };
+
+        let rt_loop = runtime.clone();
+        runtime
+            .spawn(async move {
+                let _ = socks::run_socks_loop(rt_loop, socks_addr, dial).await;
+            })
+            .unwrap();
+        runtime.inner().progress_until_stalled().await;
+
+        let mut first = runtime.connect(&socks_addr).await.unwrap();
+        let status = socks5_connect(&mut first, b"alice", b"x", "example.onion", 80).await;
+        assert_eq!(status, 0, "SOCKS request should succeed");
+
+        first.write_all(b"PING").await.unwrap();
+        let mut response = [0u8; 4];
+        first.read_exact(&mut response).await.unwrap();
+        assert_eq!(&response, b"PING");
+
+        let mut second = runtime.connect(&socks_addr).await.unwrap();
+        let status = socks5_connect(&mut second, b"bob", b"x", "example.onion", 443).await;
+        assert_eq!(status, 0);
+
+        let alice = targets_rx.next().await.unwrap();
+        let bob = targets_rx.next().await.unwrap();
+
+        assert_eq!(alice.host, "example.onion");
+        assert_eq!(alice.port, 80);
+        assert_eq!(bob.port, 443);
+
+        // Different credentials must not share circuits
+        assert!(alice.isolation.compatible(&alice.isolation.clone()));
+        assert!(!alice.isolation.compatible(&bob.isolation));
+    });
+}
+
+#[test]
+fn test_socks_proxy_reports_dial_failure() {
+    let network = MockNetwork::new();
+    let local_ip: IpAddr = "127.0.0.1".parse().unwrap();
+    let runtime = network
+        .builder()
+        .add_address(local_ip)
+        .runtime(MockRuntime::new());
+
+    runtime.block_on(async {
+        let socks_addr: SocketAddr = "127.0.0.1:9151".parse().unwrap();
+
+        let dial = |_target: SocksTarget| async {
+            Err::<tor_rtmock::io::LocalStream, _>(onionize::Error::Io(std::io::Error::other(
+                "unreachable",
+            )))
+        };
+
+        let rt_loop = runtime.clone();
+        runtime
+            .spawn(async move {
+                let _ = socks::run_socks_loop(rt_loop, socks_addr, dial).await;
+            })
+            .unwrap();
+        runtime.inner().progress_until_stalled().await;
+
+        let mut client = runtime.connect(&socks_addr).await.unwrap();
+        let status = socks5_connect(&mut client, b"u", b"p", "example.onion", 80).await;
+        assert_eq!(status, 1, "Expected SOCKS general failure");
+    });
+}
@@ -0,0 +1,129 @@
+// tests/stats_test.rs
+
+use futures::task::SpawnExt;
+use futures::{AsyncReadExt, AsyncWriteExt, StreamExt};
+use onionize::dashboard;
+use onionize::proxy::{self, CloseReason, ProxyOptions};
+use onionize::stats::TrafficStats;
+use std::net::{IpAddr, SocketAddr};
+use tor_rtcompat::{NetStreamListener, NetStreamProvider, ToplevelBlockOn};
+use tor_rtmock::{MockRuntime, net::MockNetwork};
+
+#[test]
+fn test_stats_count_streams_and_bytes_per_circuit() {
+    let network = MockNetwork::new();
+    let target_ip: IpAddr = "127.0.0.1".parse().unwrap();
+    let runtime = network
+        .builder()
+        .add_address(target_ip)
+        .runtime(MockRuntime::new());
+
+    runtime.block_on(async {
+        let target_str = "127.0.0.1:8091";
+        let bind_addr: SocketAddr = target_str.parse().unwrap();
+        let listener = runtime.listen(&bind_addr).await.unwrap();
+
+        // Server task: answer PONG to PING and close
+        runtime
+            .spawn(async move {
+                let mut incoming = listener.incoming();
+                if let Some(Ok((mut socket, _))) = incoming.next().await {
+                    let mut buf = [0u8; 4];
+                    socket.read_exact(&mut buf).await.unwrap();
+                    socket.write_all(b"PONG").await.unwrap();
+                    socket.close().await.unwrap();
+                }
+            })
+            .unwrap();
+
+        let stats = TrafficStats::new();
+        let options = ProxyOptions {
+            stats: stats.for_circuit(),
+            ..Default::default()
+        };
+
+        let (mut client_side, stream_dummy_tor) = tor_rtmock::io::stream_pair();
+        client_side.write_all(b"PING").await.unwrap();
+        client_side.close().await.unwrap();
+
+        let reason = proxy::handle_connection_with_options(
+            runtime.clone(),
+            stream_dummy_tor,
+            target_str,
+            &options,
+        )
+        .await
+        .unwrap();
+        assert_eq!(reason, CloseReason::Completed);
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.circuits_active, 1);
+        assert_eq!(snapshot.streams_total, 1);
+        assert_eq!(snapshot.streams_active, 0);
+        assert_eq!(snapshot.bytes_up, 4);
+        assert_eq!(snapshot.bytes_down, 4);
+        assert_eq!(snapshot.backend_connects, 1);
+        assert_eq!(snapshot.closed.get(&CloseReason::Completed), Some(&1));
+        assert_eq!(snapshot.errors(), 0);
+
+        let circuit = &snapshot.circuits[0];
+        assert_eq!(circuit.streams_total, 1);
+        assert_eq!(circuit.bytes_down, 4);
+
+        // The circuit disappears once its statistics are dropped
+        drop(options);
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.circuits_active, 0);
+        assert_eq!(snapshot.circuits_total, 1);
+    });
+}
+
+#[test]
+fn test_stats_count_errors_by_kind() {
+    let network = MockNetwork::new();
+    let runtime = network.builder().runtime(MockRuntime::new());
+
+    runtime.block_on(async {
+        let stats = TrafficStats::new();
+        let options = ProxyOptions {
+            stats: stats.clone(),
+            ..Default::default()
+        };
+
+        let (_client_side, stream_dummy_tor) = tor_rtmock::io::stream_pair();
+        let result = proxy::handle_connection_with_options(
+            runtime.clone(),
+            stream_dummy_tor,
+            "127.0.0.1:9999",
+            &options,
+        )
+        .await;
+        assert!(result.is_err());
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.streams_active, 0);
+        assert_eq!(
+            snapshot.closed.get(&CloseReason::BackendUnreachable),
+            Some(&1)
+        );
+        assert_eq!(snapshot.errors(), 1);
+        assert_eq!(snapshot.backend_connect_avg_ms, None);
+    });
+}
+
+#[test]
+fn test_dashboard_render() {
+    rust_i18n::set_locale("en");
+    let stats = TrafficStats::new();
+    let _circuit = stats.for_circuit();
+
+    let frame = dashboard::render(
+        &stats.snapshot(),
+        &["example.onion → 127.0.0.1:3000".into()],
+    );
+
+    assert!(frame.contains("example.onion"));
+    assert!(frame.contains("1 active, 1 total"));
+    assert!(frame.contains("#1"));
+    assert!(frame.contains("Press Ctrl+C to quit"));
+}
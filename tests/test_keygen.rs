@@ -1,5 +1,5 @@
 // tests/test_keygen.rs
-use onionize::keygen;
+use onionize::{Error, keygen};
 
 #[test]
 fn test_keygen_format_structure() {
@@ -70,3 +70,225 @@ fn test_keygen_randomness() {
     assert_ne!(keys1.public_b32, keys2.public_b32, "Keys should be random");
     assert_ne!(keys1.client_string, keys2.client_string);
 }
+
+#[test]
+fn test_parse_client_secret_formats() {
+    let keys = keygen::generate_keys();
+    let secret_b32 = keys.client_string.rsplit(':').next().unwrap();
+
+    // Full client string, descriptor form and raw (upper-case) base32 all yield the same key
+    let from_client_string = keygen::parse_client_secret(&keys.client_string).unwrap();
+    let from_descriptor =
+        keygen::parse_client_secret(&format!("descriptor:x25519:{}", secret_b32)).unwrap();
+    let from_raw = keygen::parse_client_secret(&secret_b32.to_uppercase()).unwrap();
+
+    assert_eq!(from_client_string, from_descriptor);
+    assert_eq!(from_client_string, from_raw);
+
+    // The secret must derive the advertised public key
+    let public = x25519_dalek::PublicKey::from(&x25519_dalek::StaticSecret::from(from_raw));
+    assert_eq!(
+        keygen::decode_key_b32(&keys.public_b32).unwrap(),
+        *public.as_bytes()
+    );
+}
+
+#[test]
+fn test_parse_client_secret_rejects_invalid() {
+    rust_i18n::set_locale("en");
+
+    assert!(keygen::parse_client_secret("not-a-key").is_err());
+    assert!(keygen::parse_client_secret("descriptor:x25519:abc").is_err());
+}
+
+#[test]
+fn test_read_auth_private_dir() {
+    let keys = keygen::generate_keys();
+    let secret_b32 = keys.client_string.rsplit(':').next().unwrap();
+    let address = "2gzyxa5ihm7nsggfxnu52rck2vv4rvmdlkiu3zzui5du4xyclen53wid";
+
+    let dir = std::env::temp_dir().join("onionize-auth-private-test");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("alice.auth_private"),
+        format!("# comment\n{}:descriptor:x25519:{}\n", address, secret_b32),
+    )
+    .unwrap();
+    std::fs::write(dir.join("ignored.txt"), "garbage").unwrap();
+
+    let entries = keygen::read_auth_private_dir(&dir).unwrap();
+
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].onion_address, format!("{}.onion", address));
+    assert_eq!(
+        entries[0].secret,
+        keygen::parse_client_secret(secret_b32).unwrap()
+    );
+}
+
+#[test]
+fn test_inspect_key_formats() {
+    use keygen::KeyKind;
+
+    let keys = keygen::generate_keys();
+    let secret_b32 = keys.client_string.rsplit(':').next().unwrap();
+    let address = "2gzyxa5ihm7nsggfxnu52rck2vv4rvmdlkiu3zzui5du4xyclen53wid";
+
+    let public = keygen::inspect_key(&keys.server_string).unwrap();
+    assert_eq!(public.kind, KeyKind::PublicKey);
+    assert!(!public.has_secret);
+    assert_eq!(public.public_key, keys.server_string);
+
+    // The public key is derived from the secret in every other format
+    let secret = keygen::inspect_key(&secret_b32.to_uppercase()).unwrap();
+    assert_eq!(secret.kind, KeyKind::SecretKey);
+    assert_eq!(secret.public_key, keys.server_string);
+    assert!(secret.matches(&keys.server_string));
+    assert!(secret.matches(&keys.public_b32.to_uppercase()));
+
+    let client = keygen::inspect_key(&keys.client_string).unwrap();
+    assert_eq!(client.kind, KeyKind::ClientString);
+    assert_eq!(client.mismatched_public_key, None);
+
+    let entry = keygen::inspect_key(&format!("{address}:descriptor:x25519:{secret_b32}")).unwrap();
+    assert_eq!(entry.kind, KeyKind::AuthPrivate);
+    assert_eq!(entry.onion_address, Some(format!("{address}.onion")));
+    assert_eq!(entry.public_key, keys.server_string);
+}
+
+#[test]
+fn test_inspect_key_reports_mismatches() {
+    let keys = keygen::generate_keys();
+    let other = keygen::generate_keys();
+    let secret_b32 = keys.client_string.rsplit(':').next().unwrap();
+
+    // A client string whose public key belongs to another secret
+    let spliced = format!("{}:descriptor:x25519:{}", other.public_b32, secret_b32);
+    let info = keygen::inspect_key(&spliced).unwrap();
+    assert_eq!(
+        info.mismatched_public_key.as_deref(),
+        Some(other.server_string.as_str())
+    );
+    assert_eq!(info.public_key, keys.server_string);
+    assert!(!info.matches(&other.server_string));
+}
+
+#[test]
+fn test_inspect_key_explains_invalid_keys() {
+    let keys = keygen::generate_keys();
+
+    assert!(matches!(
+        keygen::inspect_key(&keys.public_b32[..50]),
+        Err(Error::KeyLength(50))
+    ));
+    // 0, 1, 8 and 9 are not in the base32 alphabet
+    assert!(matches!(
+        keygen::inspect_key(&format!("descriptor:x25519:ab1{}", &keys.public_b32[3..])),
+        Err(Error::KeyCharacter {
+            character: '1',
+            position: 3
+        })
+    ));
+    assert!(matches!(
+        keygen::inspect_key(&format!("example:descriptor:x25519:{}", keys.public_b32)),
+        Err(Error::InvalidOnionAddress(_))
+    ));
+    assert!(matches!(
+        keygen::inspect_key(&format!("example descriptor:x25519:{}", keys.public_b32)),
+        Err(Error::InvalidAuthPrivate)
+    ));
+}
+
+/// Cheap parameters, so tests stay fast.
+const TEST_KDF: keygen::KdfParams = keygen::KdfParams {
+    memory_kib: 64,
+    iterations: 1,
+    parallelism: 1,
+};
+
+#[test]
+fn test_derive_keys_is_deterministic() {
+    let seed = keygen::parse_seed("000102030405060708090a0b0c0d0e0f").unwrap();
+
+    let keys = keygen::derive_keys(&seed, &TEST_KDF).unwrap();
+    let again = keygen::derive_keys(&seed, &TEST_KDF).unwrap();
+    assert_eq!(keys.client_string, again.client_string);
+    assert!(
+        keygen::inspect_key(&keys.client_string)
+            .unwrap()
+            .mismatched_public_key
+            .is_none()
+    );
+
+    // Any change of the input or of the cost gives other keys
+    let passphrase = keygen::derive_keys(b"correct horse battery staple", &TEST_KDF).unwrap();
+    assert_ne!(keys.public_b32, passphrase.public_b32);
+    let costlier = keygen::KdfParams {
+        iterations: 2,
+        ..TEST_KDF
+    };
+    let other = keygen::derive_keys(&seed, &costlier).unwrap();
+    assert_ne!(keys.public_b32, other.public_b32);
+
+    assert!(matches!(
+        keygen::derive_keys(
+            &seed,
+            &keygen::KdfParams {
+                memory_kib: 1,
+                ..TEST_KDF
+            }
+        ),
+        Err(Error::InvalidKdfParams(_))
+    ));
+}
+
+#[test]
+fn test_derive_keys_known_answer() {
+    // Fixtures rely on these keys: the derivation must never change
+    let seed = keygen::parse_seed("000102030405060708090a0b0c0d0e0f").unwrap();
+    let keys = keygen::derive_keys(&seed, &keygen::KdfParams::default()).unwrap();
+    assert_eq!(
+        keys.public_b32,
+        "6nmze4igdbqkwagfdthyvafu5slbkmuq7flnjt7jrju7wsdabjwa"
+    );
+}
+
+#[test]
+fn test_parse_seed() {
+    assert_eq!(
+        keygen::parse_seed(" 000102030405060708090A0B0C0D0E0F\n").unwrap(),
+        (0u8..16).collect::<Vec<_>>()
+    );
+    assert!(matches!(
+        keygen::parse_seed("abcd"),
+        Err(Error::InvalidSeed)
+    ));
+    assert!(matches!(
+        keygen::parse_seed("not hexadecimal at all, not hexadecimal"),
+        Err(Error::InvalidSeed)
+    ));
+}
+
+#[test]
+fn test_derive_service_identity() {
+    let identity = keygen::derive_service_identity(b"fixture", &TEST_KDF).unwrap();
+    let again = keygen::derive_service_identity(b"fixture", &TEST_KDF).unwrap();
+    assert_eq!(identity.onion_address, again.onion_address);
+    assert!(identity.onion_address.ends_with(".onion"));
+    assert_eq!(identity.onion_address.len(), 56 + ".onion".len());
+    assert_ne!(
+        identity.onion_address,
+        keygen::generate_service_identity().onion_address
+    );
+
+    let file = std::env::temp_dir().join(format!(
+        "onionize-hs_ed25519_secret_key-{}",
+        std::process::id()
+    ));
+    identity.write_ctor_secret_key(&file).unwrap();
+    let content = std::fs::read(&file).unwrap();
+    assert_eq!(content.len(), 32 + 64);
+    assert!(content.starts_with(b"== ed25519v1-secret: type0 ==\0\0\0"));
+    let _ = std::fs::remove_file(&file);
+}
@@ -37,8 +37,8 @@ fn test_launch_onion_service_invalid_nickname() {
             Ok(_) => panic!("Expected error for invalid nickname, but got success"),
             Err(e) => {
                 let err_msg = e.to_string();
-                // Expect "Invalid nickname" error
-                assert_eq!(err_msg, "Invalid nickname");
+                // Expect "Invalid nickname" error naming the rejected value
+                assert_eq!(err_msg, "Invalid nickname: invalid nickname");
             }
         }
     });
@@ -80,8 +80,8 @@ fn test_launch_onion_service_valid_config_generation() {
                 let is_accepted = accepted_errors.iter().any(|&sub| msg.contains(sub));
 
                 // The nickname is valid, so these errors should not be about invalid nickname or config.
-                assert_ne!(
-                    msg, "Invalid nickname",
+                assert!(
+                    !msg.starts_with("Invalid nickname"),
                     "Nickname validation failed unexpectedly"
                 );
                 assert_ne!(
@@ -149,3 +149,16 @@ fn test_launch_onion_service_with_auth() {
         }
     });
 }
+
+#[test]
+fn test_add_client_auth_key_invalid_address() {
+    set_locale("en");
+
+    let runtime = MockRuntime::new();
+    let client = create_mock_client(runtime);
+
+    let result = tor::add_client_auth_key(&client, "example.com", [7u8; 32]);
+
+    let err = result.expect_err("Expected error for a non-onion address");
+    assert_eq!(err.to_string(), "Invalid onion address: example.com");
+}
@@ -0,0 +1,355 @@
+// tests/torcontrol_test.rs
+
+use futures::io::BufReader;
+use futures::task::SpawnExt;
+use futures::{AsyncBufReadExt, AsyncWriteExt, StreamExt};
+use onionize::keygen;
+use onionize::keygen::ServiceIdentity;
+use onionize::shutdown::ShutdownHandle;
+use onionize::torcontrol::{
+    ControlPassword, ControlPortAuth, MAX_COMMAND_LINE, OnionRegistry, OnionSpec, parse_add_onion,
+    serve_control_port_connection,
+};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tor_rtcompat::{NetStreamListener, NetStreamProvider, ToplevelBlockOn};
+use tor_rtmock::{MockRuntime, net::MockNetwork};
+
+const COOKIE: [u8; 32] = [7; 32];
+
+fn test_auth() -> ControlPortAuth {
+    ControlPortAuth::new(
+        COOKIE,
+        PathBuf::from("/tmp/control_auth_cookie"),
+        Some(ControlPassword::new("secret")),
+    )
+}
+
+#[test]
+fn test_parse_add_onion() {
+    let spec =
+        parse_add_onion("NEW:ED25519-V3 Flags=DiscardPK,Detach Port=80,8080 Port=22").unwrap();
+    assert_eq!(
+        spec.ports,
+        vec![
+            (80, "127.0.0.1:8080".to_string()),
+            (22, "127.0.0.1:22".to_string())
+        ]
+    );
+    assert!(spec.detach);
+    assert!(spec.discard_pk);
+    assert!(spec.clients.is_empty());
+    assert!(spec.key.is_none());
+
+    let spec = parse_add_onion("NEW:BEST Port=443,localhost:8443").unwrap();
+    assert_eq!(spec.ports, vec![(443, "127.0.0.1:8443".to_string())]);
+    assert!(!spec.detach);
+}
+
+#[test]
+fn test_parse_add_onion_client_auth() {
+    let key = keygen::generate_keys().server_string;
+    let public = key.strip_prefix("descriptor:x25519:").unwrap();
+
+    let spec = parse_add_onion(&format!(
+        "NEW:ED25519-V3 Flags=V3Auth Port=80,8080 ClientAuthV3={}",
+        public.to_ascii_uppercase()
+    ))
+    .unwrap();
+    assert_eq!(spec.clients.len(), 1);
+    assert_eq!(spec.clients[0].key, key);
+
+    let err = parse_add_onion("NEW:ED25519-V3 Port=80 ClientAuthV3=garbage").unwrap_err();
+    assert_eq!(err.code, 512);
+}
+
+#[test]
+fn test_parse_add_onion_import_key() {
+    let identity = keygen::generate_service_identity();
+    let private_key = identity.ctor_private_key();
+    assert!(private_key.starts_with("ED25519-V3:"));
+
+    let spec = parse_add_onion(&format!("{} Port=80", private_key.as_str())).unwrap();
+    assert_eq!(
+        spec.key.unwrap().onion_address,
+        identity.onion_address,
+        "an imported key keeps the onion address"
+    );
+
+    let truncated = &private_key[..private_key.len() - 8];
+    let err = parse_add_onion(&format!("{truncated} Port=80")).unwrap_err();
+    assert_eq!(err.code, 513);
+}
+
+#[test]
+fn test_hashed_password() {
+    // Hash of "password" with a fixed salt, as printed by `tor --hash-password`
+    let hashed = "16:5E2A7C11D3B8F046600B358470ED465E9D8F83FA9D3BD1AC3EC8C2C330";
+    let password = ControlPassword::from_hashed(hashed).unwrap();
+    assert!(password.matches(b"password"));
+    assert!(!password.matches(b"Password"));
+    assert_eq!(password.hashed(), hashed);
+    assert_eq!(format!("{password:?}"), "ControlPassword([REDACTED])");
+
+    // A new hash uses a random salt
+    let password = ControlPassword::new("secret");
+    assert!(password.matches(b"secret"));
+    assert_ne!(password.hashed(), ControlPassword::new("secret").hashed());
+    assert_eq!(
+        ControlPassword::from_hashed(&password.hashed()).unwrap(),
+        password
+    );
+
+    for invalid in [
+        "secret",
+        "16:ABCD",
+        "17:5E2A7C11D3B8F046600B358470ED465E9D8F83FA9D3BD1AC3EC8C2C330",
+    ] {
+        assert!(ControlPassword::from_hashed(invalid).is_err(), "{invalid}");
+    }
+}
+
+#[test]
+fn test_parse_add_onion_errors() {
+    let cases = [
+        ("", 512),
+        ("NEW:ED25519-V3", 512),
+        ("NEW:RSA1024 Port=80", 513),
+        ("ED25519-V3:c2VjcmV0 Port=80", 513),
+        ("NEW:ED25519-V3 Port=0", 512),
+        ("NEW:ED25519-V3 Port=80,not-an-address", 512),
+        ("NEW:ED25519-V3 Port=80,unix:/run/app.sock", 513),
+        ("NEW:ED25519-V3 Port=80 Flags=NonAnonymous", 512),
+        ("NEW:ED25519-V3 Port=80 MaxStreams=10", 513),
+    ];
+
+    for (arguments, code) in cases {
+        let err = parse_add_onion(arguments).unwrap_err();
+        assert_eq!(err.code, code, "unexpected reply to `{arguments}`: {err}");
+    }
+}
+
+#[test]
+fn test_control_port_session() {
+    let network = MockNetwork::new();
+    let local_ip: IpAddr = "127.0.0.1".parse().unwrap();
+    let runtime = network
+        .builder()
+        .add_address(local_ip)
+        .runtime(MockRuntime::new());
+
+    runtime.block_on(async {
+        let addr: SocketAddr = "127.0.0.1:9051".parse().unwrap();
+        let listener = runtime.listen(&addr).await.unwrap();
+
+        let shutdown = ShutdownHandle::new();
+        let registry = Arc::new(OnionRegistry::new(shutdown.clone()));
+        let launched: Arc<Mutex<Vec<(OnionSpec, ShutdownHandle)>>> = Arc::default();
+
+        let server_registry = registry.clone();
+        let recorder = launched.clone();
+        runtime
+            .spawn(async move {
+                let launch = |spec: OnionSpec, stop: ShutdownHandle| {
+                    let recorder = recorder.clone();
+                    async move {
+                        let mut launched = recorder.lock().unwrap();
+                        launched.push((spec, stop));
+                        Ok(format!("service{}", launched.len()))
+                    }
+                };
+                let mut incoming = listener.incoming();
+                while let Some(Ok((stream, _))) = incoming.next().await {
+                    let _ = serve_control_port_connection(
+                        stream,
+                        &test_auth(),
+                        &server_registry,
+                        &launch,
+                    )
+                    .await;
+                }
+            })
+            .unwrap();
+
+        let client = runtime.connect(&addr).await.unwrap();
+        let (reader, mut writer) = futures::AsyncReadExt::split(client);
+        let mut lines = BufReader::new(reader).lines();
+
+        writer.write_all(b"PROTOCOLINFO 1\r\n").await.unwrap();
+        let mut reply = Vec::new();
+        loop {
+            let line = lines.next().await.unwrap().unwrap();
+            let last = line.starts_with("250 ");
+            reply.push(line);
+            if last {
+                break;
+            }
+        }
+        assert_eq!(reply[0], "250-PROTOCOLINFO 1");
+        assert_eq!(
+            reply[1],
+            "250-AUTH METHODS=COOKIE,HASHEDPASSWORD COOKIEFILE=\"/tmp/control_auth_cookie\""
+        );
+        assert!(reply[2].starts_with("250-VERSION Tor="));
+
+        writer
+            .write_all(format!("AUTHENTICATE {}\r\n", hex::encode(COOKIE)).as_bytes())
+            .await
+            .unwrap();
+        assert_eq!(lines.next().await.unwrap().unwrap(), "250 OK");
+
+        writer
+            .write_all(b"ADD_ONION NEW:ED25519-V3 Flags=DiscardPK Port=80,8080\r\n")
+            .await
+            .unwrap();
+        assert_eq!(
+            lines.next().await.unwrap().unwrap(),
+            "250-ServiceID=service1"
+        );
+        assert_eq!(lines.next().await.unwrap().unwrap(), "250 OK");
+
+        writer
+            .write_all(b"ADD_ONION NEW:ED25519-V3 Flags=Detach Port=22\r\n")
+            .await
+            .unwrap();
+        assert_eq!(
+            lines.next().await.unwrap().unwrap(),
+            "250-ServiceID=service2"
+        );
+        // Without DiscardPK, the new key is returned and the service launched with it
+        let private_key = lines.next().await.unwrap().unwrap();
+        let private_key = private_key.strip_prefix("250-PrivateKey=").unwrap();
+        assert_eq!(lines.next().await.unwrap().unwrap(), "250 OK");
+        assert_eq!(
+            ServiceIdentity::from_ctor_private_key(private_key)
+                .unwrap()
+                .onion_address,
+            launched.lock().unwrap()[1]
+                .0
+                .key
+                .as_ref()
+                .unwrap()
+                .onion_address
+        );
+
+        // An imported key is not returned
+        writer
+            .write_all(format!("ADD_ONION {private_key} Flags=Detach Port=23\r\n").as_bytes())
+            .await
+            .unwrap();
+        assert_eq!(
+            lines.next().await.unwrap().unwrap(),
+            "250-ServiceID=service3"
+        );
+        assert_eq!(lines.next().await.unwrap().unwrap(), "250 OK");
+        writer.write_all(b"DEL_ONION service3\r\n").await.unwrap();
+        assert_eq!(lines.next().await.unwrap().unwrap(), "250 OK");
+
+        writer
+            .write_all(b"ADD_ONION NEW:RSA1024\r\n")
+            .await
+            .unwrap();
+        assert!(lines.next().await.unwrap().unwrap().starts_with("513 "));
+
+        writer.write_all(b"DEL_ONION nope\r\n").await.unwrap();
+        assert!(lines.next().await.unwrap().unwrap().starts_with("552 "));
+
+        writer.write_all(b"GETINFO version\r\n").await.unwrap();
+        assert!(lines.next().await.unwrap().unwrap().starts_with("510 "));
+
+        writer.write_all(b"DEL_ONION service2\r\n").await.unwrap();
+        assert_eq!(lines.next().await.unwrap().unwrap(), "250 OK");
+        assert!(launched.lock().unwrap()[1].1.is_shutdown());
+        assert_eq!(registry.ids(), vec!["service1".to_string()]);
+
+        // Services that were not detached go away with the connection
+        writer.write_all(b"QUIT\r\n").await.unwrap();
+        assert_eq!(
+            lines.next().await.unwrap().unwrap(),
+            "250 closing connection"
+        );
+        assert!(lines.next().await.is_none());
+        assert!(registry.ids().is_empty());
+        assert!(launched.lock().unwrap()[0].1.is_shutdown());
+        assert!(!shutdown.is_shutdown());
+    });
+}
+
+#[test]
+fn test_control_port_requires_authentication() {
+    let network = MockNetwork::new();
+    let local_ip: IpAddr = "127.0.0.1".parse().unwrap();
+    let runtime = network
+        .builder()
+        .add_address(local_ip)
+        .runtime(MockRuntime::new());
+
+    runtime.block_on(async {
+        let addr: SocketAddr = "127.0.0.1:9051".parse().unwrap();
+        let listener = runtime.listen(&addr).await.unwrap();
+        let registry = Arc::new(OnionRegistry::new(ShutdownHandle::new()));
+
+        let server_registry = registry.clone();
+        runtime
+            .spawn(async move {
+                let launch = |_: OnionSpec, _: ShutdownHandle| async {
+                    Ok::<_, onionize::Error>("unexpected".to_string())
+                };
+                let mut incoming = listener.incoming();
+                while let Some(Ok((stream, _))) = incoming.next().await {
+                    let _ = serve_control_port_connection(
+                        stream,
+                        &test_auth(),
+                        &server_registry,
+                        &launch,
+                    )
+                    .await;
+                }
+            })
+            .unwrap();
+
+        // Commands other than PROTOCOLINFO and AUTHENTICATE close the connection
+        let client = runtime.connect(&addr).await.unwrap();
+        let (reader, mut writer) = futures::AsyncReadExt::split(client);
+        let mut lines = BufReader::new(reader).lines();
+        writer
+            .write_all(b"ADD_ONION NEW:ED25519-V3 Port=80\r\n")
+            .await
+            .unwrap();
+        assert!(lines.next().await.unwrap().unwrap().starts_with("514 "));
+        assert!(lines.next().await.is_none());
+
+        // A wrong secret closes the connection
+        let client = runtime.connect(&addr).await.unwrap();
+        let (reader, mut writer) = futures::AsyncReadExt::split(client);
+        let mut lines = BufReader::new(reader).lines();
+        writer
+            .write_all(format!("AUTHENTICATE {}\r\n", hex::encode([8u8; 32])).as_bytes())
+            .await
+            .unwrap();
+        assert!(lines.next().await.unwrap().unwrap().starts_with("515 "));
+        assert!(lines.next().await.is_none());
+
+        // The password is accepted, quoted as in C-tor
+        let client = runtime.connect(&addr).await.unwrap();
+        let (reader, mut writer) = futures::AsyncReadExt::split(client);
+        let mut lines = BufReader::new(reader).lines();
+        writer
+            .write_all(b"AUTHENTICATE \"secret\"\r\n")
+            .await
+            .unwrap();
+        assert_eq!(lines.next().await.unwrap().unwrap(), "250 OK");
+
+        drop((lines, writer));
+
+        // An overlong line closes the connection before authentication
+        let client = runtime.connect(&addr).await.unwrap();
+        let (reader, mut writer) = futures::AsyncReadExt::split(client);
+        let mut lines = BufReader::new(reader).lines();
+        let _ = writer.write_all(&vec![b'A'; MAX_COMMAND_LINE + 1]).await;
+        assert!(lines.next().await.is_none());
+
+        assert!(registry.ids().is_empty());
+    });
+}